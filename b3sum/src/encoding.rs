@@ -0,0 +1,261 @@
+//! The encode, decode, and slice subcommands, for verified streaming in the style of Bao. These
+//! use the same tree as the regular hash, so the hash that b3sum prints for a file is the one that
+//! its encoding decodes against. See the blake3::encoding module for the format.
+
+use crate::progress::{MIN_MMAP_LEN, MMAP_WINDOW_LEN};
+use anyhow::{anyhow, bail, Result};
+use blake3::encoding::{self, ChunkGroup};
+use clap::{Args, Subcommand};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Write the combined or outboard encoding of INPUT to OUTPUT
+    ///
+    /// The root hash of the encoding is the same hash that `b3sum INPUT`
+    /// prints. The input is streamed, and OUTPUT has to be a file, because
+    /// the tree is put in order at the end by moving things around in it.
+    #[command(long_flag("encode"))]
+    Encode {
+        /// Write only the tree, without the input bytes
+        #[arg(long)]
+        outboard: bool,
+
+        #[command(flatten)]
+        group: GroupArg,
+
+        /// The input file, or - for stdin
+        input: PathBuf,
+
+        /// The output file, which is overwritten
+        output: PathBuf,
+    },
+
+    /// Verify an encoding against HASH, and write the input to OUTPUT
+    ///
+    /// Every byte is verified before it's written. If the encoding is
+    /// corrupt, decoding stops with an error at the first bad group of
+    /// chunks, and OUTPUT contains only the bytes before it.
+    #[command(long_flag("decode"))]
+    Decode {
+        /// The expected hash, as printed by b3sum
+        hash: blake3::Hash,
+
+        /// Read the tree from this outboard encoding, and the input bytes from
+        /// INPUT
+        #[arg(long, value_name("PATH"), conflicts_with("slice_start"))]
+        outboard: Option<PathBuf>,
+
+        /// Decode a slice from `b3sum slice`, starting at this input offset
+        #[arg(long, value_name("OFFSET"), requires("slice_len"))]
+        slice_start: Option<u64>,
+
+        /// Decode a slice from `b3sum slice`, with this many input bytes
+        #[arg(long, value_name("LEN"), requires("slice_start"))]
+        slice_len: Option<u64>,
+
+        #[command(flatten)]
+        group: GroupArg,
+
+        /// The encoding, or - for stdin
+        #[arg(default_value = "-")]
+        input: PathBuf,
+
+        /// The output file, or - for stdout
+        #[arg(default_value = "-")]
+        output: PathBuf,
+    },
+
+    /// Extract the parts of an encoding that cover a range of input bytes
+    ///
+    /// Decode the result with `b3sum decode --slice-start START --slice-len
+    /// LEN`.
+    #[command(long_flag("slice"))]
+    Slice {
+        /// The first input byte in the slice
+        start: u64,
+
+        /// The number of input bytes in the slice
+        len: u64,
+
+        /// Read the tree from this outboard encoding, and the input bytes from
+        /// INPUT
+        #[arg(long, value_name("PATH"))]
+        outboard: Option<PathBuf>,
+
+        #[command(flatten)]
+        group: GroupArg,
+
+        /// The combined encoding, or the original input with --outboard
+        input: PathBuf,
+
+        /// The output file, or - for stdout
+        #[arg(default_value = "-")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Args)]
+pub struct GroupArg {
+    /// Each leaf of the tree is 2^LOG chunks of 1 KiB
+    ///
+    /// Encoding and decoding must use the same value.
+    #[arg(
+        long,
+        value_name("LOG"),
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u8).range(0..=encoding::MAX_CHUNK_GROUP_LOG as i64)
+    )]
+    chunk_group_log: u8,
+}
+
+impl GroupArg {
+    fn group(&self) -> ChunkGroup {
+        ChunkGroup::new(self.chunk_group_log)
+    }
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    File::open(path).map_err(|e| anyhow!("{}: {}", path.display(), e))
+}
+
+fn open_input(path: &Path) -> Result<Box<dyn Read>> {
+    Ok(if path == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(open_file(path)?)
+    })
+}
+
+fn open_output(path: &Path) -> Result<Box<dyn Write>> {
+    Ok(if path == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?)
+    })
+}
+
+// Feed the input to the encoder, mapping it one window at a time if it's a big enough file.
+fn encode_input(encoder: &mut impl Write, input: &Path) -> Result<()> {
+    if input == Path::new("-") {
+        io::copy(&mut io::stdin().lock(), encoder)?;
+        return Ok(());
+    }
+    let mut file = open_file(input)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() < MIN_MMAP_LEN {
+        io::copy(&mut file, encoder)?;
+        return Ok(());
+    }
+    let end = metadata.len();
+    let mut offset = 0;
+    while offset < end {
+        // Set the length of each map explicitly, so that the file changing size can't make us map
+        // past the end.
+        let len = std::cmp::min(MMAP_WINDOW_LEN, end - offset);
+        // SAFETY: If the file changes while it's mapped, we might encode nonsense or crash with
+        // SIGBUS, like any other program that maps files. The map never escapes this function.
+        let map = unsafe {
+            memmap2::MmapOptions::new()
+                .offset(offset)
+                .len(len as usize)
+                .map(&file)?
+        };
+        encoder.write_all(&map)?;
+        offset += len;
+    }
+    Ok(())
+}
+
+pub fn run(command: &Command) -> Result<()> {
+    match command {
+        Command::Encode {
+            outboard,
+            group,
+            input,
+            output,
+        } => {
+            // The encoder reads back what it wrote and seeks around in it, so stdout won't work.
+            if output == Path::new("-") {
+                bail!("Cannot encode to standard output");
+            }
+            let output = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output)
+                .map_err(|e| anyhow!("{}: {}", output.display(), e))?;
+            let mut encoder = if *outboard {
+                encoding::Encoder::new_outboard(output, group.group())
+            } else {
+                encoding::Encoder::new(output, group.group())
+            };
+            encode_input(&mut encoder, input)?;
+            encoder.finalize()?;
+        }
+        Command::Decode {
+            hash,
+            outboard,
+            slice_start,
+            slice_len,
+            group,
+            input,
+            output,
+        } => {
+            let input = open_input(input)?;
+            let mut decoder: Box<dyn Read> = match (outboard, slice_start, slice_len) {
+                (Some(outboard), _, _) => Box::new(encoding::Decoder::new_outboard(
+                    input,
+                    open_input(outboard)?,
+                    hash,
+                    group.group(),
+                )),
+                (None, Some(start), Some(len)) => Box::new(encoding::SliceDecoder::new(
+                    input,
+                    hash,
+                    *start,
+                    *len,
+                    group.group(),
+                )),
+                _ => Box::new(encoding::Decoder::new(input, hash, group.group())),
+            };
+            let mut output = open_output(output)?;
+            // Write out everything that was verified, even if decoding fails partway.
+            let result = io::copy(&mut decoder, &mut output);
+            output.flush()?;
+            result?;
+        }
+        Command::Slice {
+            start,
+            len,
+            outboard,
+            group,
+            input,
+            output,
+        } => {
+            // Extracting a slice seeks around in the encoding, so stdin won't work.
+            if input == Path::new("-") {
+                bail!("Cannot slice from standard input");
+            }
+            let input = open_file(input)?;
+            let mut output = io::BufWriter::new(open_output(output)?);
+            match outboard {
+                Some(outboard) => encoding::extract_slice_outboard(
+                    input,
+                    open_file(outboard)?,
+                    *start,
+                    *len,
+                    group.group(),
+                    &mut output,
+                )?,
+                None => encoding::extract_slice(input, *start, *len, group.group(), &mut output)?,
+            }
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Reading the key for --keyed. By default the key is read from stdin, but it can also come from a
+//! file, an environment variable, or an inherited file descriptor, which leaves stdin free for
+//! input. Every buffer that holds key material is zeroized when it's dropped.
+
+use anyhow::{bail, Result};
+use std::io::prelude::*;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+// A hex key is 64 characters, and there might be a trailing newline or some other whitespace.
+// Anything much longer than that is a mistake, so don't read it all.
+const MAX_KEY_SOURCE_LEN: u64 = 1024;
+
+pub fn read_key_from_stdin() -> Result<Zeroizing<[u8; blake3::KEY_LEN]>> {
+    let mut bytes = Zeroizing::new(Vec::with_capacity(blake3::KEY_LEN + 1));
+    let n = std::io::stdin()
+        .lock()
+        .take(blake3::KEY_LEN as u64 + 1)
+        .read_to_end(&mut bytes)?;
+    if n < blake3::KEY_LEN {
+        bail!(
+            "expected {} key bytes from stdin, found {}",
+            blake3::KEY_LEN,
+            n,
+        )
+    } else if n > blake3::KEY_LEN {
+        bail!("read more than {} key bytes from stdin", blake3::KEY_LEN)
+    } else {
+        Ok(Zeroizing::new(bytes[..blake3::KEY_LEN].try_into().unwrap()))
+    }
+}
+
+pub fn read_key_from_file(path: &Path) -> Result<Zeroizing<[u8; blake3::KEY_LEN]>> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| anyhow::anyhow!("key file {}: {}", path.display(), e))?;
+    let source = format!("key file {}", path.display());
+    decode_key(&read_limited(file)?, &source)
+}
+
+pub fn read_key_from_env(var: &str) -> Result<Zeroizing<[u8; blake3::KEY_LEN]>> {
+    let Some(value) = std::env::var_os(var) else {
+        bail!("environment variable {} is not set", var);
+    };
+    let source = format!("environment variable {}", var);
+    // A hex key is ASCII, so anything that isn't Unicode is invalid anyway.
+    let Some(value) = value.to_str().map(|value| Zeroizing::new(value.to_owned())) else {
+        bail!("{}: invalid hex key", source);
+    };
+    decode_key(value.as_bytes(), &source)
+}
+
+#[cfg(unix)]
+pub fn read_key_from_fd(fd: i32) -> Result<Zeroizing<[u8; blake3::KEY_LEN]>> {
+    use std::os::unix::io::FromRawFd;
+    if fd == 0 {
+        bail!("to read the key from stdin, use --keyed without --key-fd");
+    }
+    // Taking ownership of a descriptor that isn't open would be a bug, so check first.
+    // SAFETY: F_GETFD doesn't do anything to the descriptor.
+    if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        bail!("key file descriptor {} is not open", fd);
+    }
+    // Reading through a File also closes the descriptor when we're done, so the key can't be read
+    // again by anything else.
+    // SAFETY: The descriptor is open, and nothing else in this process uses it.
+    let file = unsafe { std::fs::File::from_raw_fd(fd) };
+    decode_key(&read_limited(file)?, &format!("key file descriptor {}", fd))
+}
+
+#[cfg(not(unix))]
+pub fn read_key_from_fd(_fd: i32) -> Result<Zeroizing<[u8; blake3::KEY_LEN]>> {
+    bail!("--key-fd is only supported on Unix")
+}
+
+fn read_limited(reader: impl Read) -> Result<Zeroizing<Vec<u8>>> {
+    let mut bytes = Zeroizing::new(Vec::new());
+    reader
+        .take(MAX_KEY_SOURCE_LEN + 1)
+        .read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+// A key is either exactly 32 raw bytes, or 64 hex characters with optional whitespace around them.
+// These can't be confused, because a valid hex key is never 32 bytes long.
+pub fn decode_key(bytes: &[u8], source: &str) -> Result<Zeroizing<[u8; blake3::KEY_LEN]>> {
+    let mut key = Zeroizing::new([0; blake3::KEY_LEN]);
+    if bytes.len() == blake3::KEY_LEN {
+        key.copy_from_slice(bytes);
+        return Ok(key);
+    }
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace());
+    let end = bytes.iter().rposition(|b| !b.is_ascii_whitespace());
+    let hex = match (start, end) {
+        (Some(start), Some(end)) => &bytes[start..=end],
+        _ => &[],
+    };
+    if hex.len() != 2 * blake3::KEY_LEN {
+        bail!(
+            "{}: expected {} raw key bytes or {} hex characters, found {} bytes",
+            source,
+            blake3::KEY_LEN,
+            2 * blake3::KEY_LEN,
+            bytes.len(),
+        );
+    }
+    if hex::decode_to_slice(hex, &mut key[..]).is_err() {
+        bail!("{}: invalid hex key", source);
+    }
+    Ok(key)
+}
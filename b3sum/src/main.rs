@@ -1,35 +1,96 @@
 use anyhow::{bail, ensure, Result};
-use clap::Parser;
+use clap::{CommandFactory, Parser, ValueEnum};
 use std::cmp;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
+mod encoding;
+mod key;
+mod output;
+mod progress;
+mod range;
+mod tree;
 #[cfg(test)]
 mod unit_tests;
+mod walk;
 
 const NAME: &str = "b3sum";
 
-const DERIVE_KEY_ARG: &str = "derive_key";
+// Exit codes for --check. When there are several kinds of problems, the first one here wins.
+// Everything else that fails, including hashing in the usual mode, exits with 1.
+const EXIT_MISMATCHED: i32 = 1;
+const EXIT_UNREADABLE: i32 = 3;
+const EXIT_REJECTED: i32 = 4;
+const EXIT_MALFORMED: i32 = 5;
+// The algorithm name in tagged lines, like `BLAKE3 (foo) = <hex>`.
+const TAG: &str = "BLAKE3";
+
 const KEYED_ARG: &str = "keyed";
+const KEY_FILE_ARG: &str = "key_file";
+const KEY_HEX_ENV_ARG: &str = "key_hex_env";
 const LENGTH_ARG: &str = "length";
 const NO_NAMES_ARG: &str = "no_names";
 const RAW_ARG: &str = "raw";
 const CHECK_ARG: &str = "check";
+const NO_MMAP_ARG: &str = "no_mmap";
+const DIRECT_IO_ARG: &str = "direct_io";
+const TREE_HASH_ARG: &str = "tree_hash";
+const RECURSIVE_ARG: &str = "recursive";
+const TAG_ARG: &str = "tag";
+const JSON_ARG: &str = "json";
 
 #[derive(Parser)]
-#[command(version, max_term_width(100))]
+#[command(
+    version,
+    max_term_width(100),
+    args_conflicts_with_subcommands(true),
+    disable_help_subcommand(true)
+)]
 struct Inner {
+    #[command(subcommand)]
+    command: Option<encoding::Command>,
+
     /// Files to hash, or checkfiles to check
     ///
     /// When no file is given, or when - is given, read standard input.
     file: Vec<PathBuf>,
 
     /// Use the keyed mode, reading the 32-byte key from stdin
-    #[arg(long, requires("file"))]
+    ///
+    /// To leave stdin free for input, read the key from --key-file, --key-hex-env, or --key-fd
+    /// instead.
+    #[arg(long)]
     keyed: bool,
 
+    /// Read the key for --keyed from PATH
+    ///
+    /// Keys from any of the --key-* sources can be 32 raw bytes, or 64 hex characters with
+    /// optional whitespace around them.
+    #[arg(long, value_name("PATH"), requires(KEYED_ARG))]
+    key_file: Option<PathBuf>,
+
+    /// Read the key for --keyed from the environment variable VAR
+    #[arg(
+        long,
+        value_name("VAR"),
+        requires(KEYED_ARG),
+        conflicts_with(KEY_FILE_ARG)
+    )]
+    key_hex_env: Option<String>,
+
+    /// Read the key for --keyed from file descriptor N (Unix only)
+    #[arg(
+        long,
+        value_name("N"),
+        requires(KEYED_ARG),
+        conflicts_with(KEY_FILE_ARG),
+        conflicts_with(KEY_HEX_ENV_ARG)
+    )]
+    key_fd: Option<i32>,
+
     /// Use the key derivation mode, with the given context string
     ///
     /// Cannot be used with --keyed.
@@ -58,10 +119,43 @@ struct Inner {
 
     /// Disable memory mapping
     ///
-    /// Currently this also disables multithreading.
+    /// Files are read into buffers instead, and hashing is still
+    /// multithreaded.
     #[arg(long)]
     no_mmap: bool,
 
+    /// Read files with O_DIRECT and io_uring (Linux only)
+    ///
+    /// This bypasses the page cache, with several reads in flight at once.
+    /// If the filesystem doesn't support O_DIRECT, files are read into
+    /// buffers as with --no-mmap. Standard input is always read normally.
+    #[arg(long, conflicts_with(NO_MMAP_ARG))]
+    direct_io: bool,
+
+    /// Hash only the input bytes starting at OFFSET
+    ///
+    /// This applies to every input, and the result is the same as hashing the
+    /// extracted bytes. Files are still memory mapped, and standard input is
+    /// read and discarded up to OFFSET. An offset past the end of an input is
+    /// an error.
+    #[arg(
+        long,
+        value_name("OFFSET"),
+        conflicts_with_all([CHECK_ARG, TREE_HASH_ARG, DIRECT_IO_ARG])
+    )]
+    input_offset: Option<u64>,
+
+    /// Hash only LEN input bytes, after --input-offset if any
+    ///
+    /// A range that goes past the end of an input is an error, rather than a
+    /// shorter hash.
+    #[arg(
+        long,
+        value_name("LEN"),
+        conflicts_with_all([CHECK_ARG, TREE_HASH_ARG, DIRECT_IO_ARG])
+    )]
+    input_length: Option<u64>,
+
     /// Omit filenames in the output
     #[arg(long)]
     no_names: bool,
@@ -72,15 +166,59 @@ struct Inner {
     #[arg(long)]
     raw: bool,
 
+    /// Write lines like `BLAKE3 (FILE) = HASH`, as with `md5sum --tag`
+    ///
+    /// With a non-default --length, the tag includes the length in bits,
+    /// like `BLAKE3-512`. --check accepts lines in either format.
+    #[arg(long, conflicts_with(RAW_ARG), conflicts_with(NO_NAMES_ARG))]
+    tag: bool,
+
+    /// Write a JSON object on its own line for each input
+    ///
+    /// Objects have "path", "hash", "size", "length", "seek", "mode", and
+    /// "error" fields, where fields that don't apply are null. If a path isn't
+    /// valid Unicode, "path_hex" has its raw bytes (UTF-16LE on Windows).
+    /// Errors are reported this way too, instead of on stderr. With --check,
+    /// there's one object for each line of the checkfile, with a "status"
+    /// field that's OK, FAILED, or INVALID, and "hash" is the expected hash.
+    #[arg(
+        long,
+        conflicts_with(RAW_ARG),
+        conflicts_with(NO_NAMES_ARG),
+        conflicts_with(TAG_ARG)
+    )]
+    json: bool,
+
+    /// Write a line for each input from TEMPLATE, like "{hash} {path}"
+    ///
+    /// The fields are {hash}, {size}, {path}, {length}, {seek}, {mode}, and
+    /// {error}. As with --json, errors are reported this way instead of on
+    /// stderr. With --check, {status} is also available, and {hash} is the
+    /// expected hash. Fields that don't apply are empty. Paths and errors are
+    /// always escaped, with \\ for backslash and \n for newline. Use {{ and
+    /// }} for literal braces.
+    #[arg(
+        long,
+        value_name("TEMPLATE"),
+        conflicts_with(RAW_ARG),
+        conflicts_with(NO_NAMES_ARG),
+        conflicts_with(TAG_ARG),
+        conflicts_with(JSON_ARG)
+    )]
+    format: Option<output::Template>,
+
     /// Read BLAKE3 sums from the [FILE]s and check them
+    ///
+    /// The output length of each line comes from the length of its hash, so
+    /// --length isn't needed. To check sums made with --keyed or
+    /// --derive-key, give the same flag and key or context.
     #[arg(
         short,
         long,
-        conflicts_with(DERIVE_KEY_ARG),
-        conflicts_with(KEYED_ARG),
         conflicts_with(LENGTH_ARG),
         conflicts_with(RAW_ARG),
-        conflicts_with(NO_NAMES_ARG)
+        conflicts_with(NO_NAMES_ARG),
+        conflicts_with(TAG_ARG)
     )]
     check: bool,
 
@@ -89,19 +227,152 @@ struct Inner {
     /// Must be used with --check.
     #[arg(long, requires(CHECK_ARG))]
     quiet: bool,
+
+    /// Skip tagged lines for other algorithms, like `SHA256 (FILE) = HASH`
+    ///
+    /// This allows sharing a checkfile with other tools. Without this flag,
+    /// those lines are improperly formatted. Must be used with --check.
+    #[arg(long, requires(CHECK_ARG))]
+    ignore_other_algorithms: bool,
+
+    /// Skip files that don't exist, rather than failing
+    ///
+    /// Must be used with --check.
+    #[arg(long, requires(CHECK_ARG))]
+    ignore_missing: bool,
+
+    /// Fail if any checkfile line is improperly formatted
+    ///
+    /// By default, those lines are counted in the summary at the end, and
+    /// they only cause a failure if a checkfile has no properly formatted
+    /// lines at all. Must be used with --check.
+    #[arg(long, requires(CHECK_ARG))]
+    strict: bool,
+
+    /// Print a message for each improperly formatted checkfile line
+    ///
+    /// The message includes the checkfile name and the line number. Must be
+    /// used with --check.
+    #[arg(long, requires(CHECK_ARG))]
+    warn: bool,
+
+    /// Reject absolute paths and .. components in checkfiles
+    ///
+    /// This keeps a checkfile from an untrusted source from pointing outside
+    /// of the current directory. Rejected paths always fail, like paths that
+    /// can't be represented safely. Must be used with --check.
+    #[arg(long, requires(CHECK_ARG))]
+    safe_paths: bool,
+
+    /// Hash each [FILE] as a whole directory tree
+    ///
+    /// Each [FILE] must be a directory, and it gets a single hash covering
+    /// the names, types, and contents of everything under it. The result
+    /// doesn't depend on the platform, the name of the directory itself, or
+    /// --num-threads. See tree_hash.md for the exact encoding.
+    #[arg(long, conflicts_with(CHECK_ARG))]
+    tree_hash: bool,
+
+    /// Include the executable bit of files in the tree hash
+    ///
+    /// Files are never executable on Windows. Must be used with --tree-hash.
+    #[arg(long, requires(TREE_HASH_ARG))]
+    tree_exec: bool,
+
+    /// Include symlinks and their targets in the tree hash
+    ///
+    /// By default, symlinks to files are followed, and symlinks to
+    /// directories are an error. Must be used with --tree-hash.
+    #[arg(long, requires(TREE_HASH_ARG))]
+    tree_symlinks: bool,
+
+    /// Hash all the files under each directory [FILE]
+    ///
+    /// Files are hashed in a deterministic order, sorted by name within each
+    /// directory, and the output can be checked with --check as usual.
+    /// Errors are printed for entries that can't be read, and the walk keeps
+    /// going. Named pipes, devices, and other special files are skipped.
+    #[arg(
+        short,
+        long,
+        conflicts_with(CHECK_ARG),
+        conflicts_with(RAW_ARG),
+        conflicts_with(TREE_HASH_ARG)
+    )]
+    recursive: bool,
+
+    /// Skip files and directories matching GLOB under --recursive
+    ///
+    /// A pattern that contains a slash matches the path relative to the
+    /// directory given on the command line. Otherwise it matches just the
+    /// name. This can be given more than once.
+    #[arg(long, value_name("GLOB"), requires(RECURSIVE_ARG))]
+    exclude: Vec<glob::Pattern>,
+
+    /// Only hash files matching GLOB under --recursive
+    ///
+    /// Patterns match the same way as with --exclude, and --exclude takes
+    /// precedence. This can be given more than once.
+    #[arg(long, value_name("GLOB"), requires(RECURSIVE_ARG))]
+    include: Vec<glob::Pattern>,
+
+    /// Don't walk into other filesystems under --recursive (Unix only)
+    #[arg(long, requires(RECURSIVE_ARG))]
+    one_file_system: bool,
+
+    /// How to handle symlinks
+    ///
+    /// With "follow", symlinks are hashed like the files they point to, and
+    /// --recursive walks into symlinked directories. With "skip", --recursive
+    /// ignores symlinks, but symlinks given as FILE arguments are still
+    /// followed. With "target", the target path of each symlink is hashed,
+    /// instead of the file it points to. Unlike the other options for
+    /// --recursive, "target" also applies to FILE arguments without
+    /// --recursive, and to the paths in checkfiles. Checking output produced
+    /// with "target" requires --symlinks=target too.
+    #[arg(
+        long,
+        value_enum,
+        value_name("POLICY"),
+        default_value_t = SymlinkPolicy::Follow,
+        conflicts_with(TREE_HASH_ARG)
+    )]
+    symlinks: SymlinkPolicy,
+
+    /// Draw a progress line on stderr while hashing
+    ///
+    /// The line shows the bytes hashed so far, the rate, and, when the total
+    /// is known from the file metadata up front, the total and an estimated
+    /// time remaining.
+    #[arg(long)]
+    progress: bool,
+
+    /// Print a summary on stderr at the end
+    ///
+    /// The summary has the number of files and bytes hashed, the time taken,
+    /// the throughput, and the SIMD implementation in use.
+    #[arg(long)]
+    stats: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SymlinkPolicy {
+    Follow,
+    Skip,
+    Target,
 }
 
 struct Args {
     inner: Inner,
     file_args: Vec<PathBuf>,
-    base_hasher: blake3::Hasher,
+    // With --keyed this holds the key, so it and every clone of it are zeroized when dropped.
+    base_hasher: Zeroizing<blake3::Hasher>,
+    // Only for --progress and --stats.
+    meter: Option<progress::Meter>,
 }
 
 impl Args {
-    fn parse() -> Result<Self> {
-        // wild::args_os() is equivalent to std::env::args_os() on Unix,
-        // but on Windows it adds support for globbing.
-        let inner = Inner::parse_from(wild::args_os());
+    fn from_inner(inner: Inner) -> Result<Self> {
         let file_args = if !inner.file.is_empty() {
             inner.file.clone()
         } else {
@@ -111,21 +382,64 @@ impl Args {
             bail!("Only one filename can be provided when using --raw");
         }
         let base_hasher = if inner.keyed {
-            // In keyed mode, since stdin is used for the key, we can't handle
-            // `-` arguments. Input::open handles that case below.
-            blake3::Hasher::new_keyed(&read_key_from_stdin()?)
+            let key = if let Some(path) = &inner.key_file {
+                key::read_key_from_file(path)?
+            } else if let Some(var) = &inner.key_hex_env {
+                key::read_key_from_env(var)?
+            } else if let Some(fd) = inner.key_fd {
+                key::read_key_from_fd(fd)?
+            } else {
+                // Since stdin is used for the key, we can't handle `-` arguments. hash_file()
+                // handles that case below.
+                if inner.file.is_empty() {
+                    bail!("--keyed reads the key from stdin, so it needs [FILE] arguments");
+                }
+                key::read_key_from_stdin()?
+            };
+            blake3::Hasher::new_keyed(&key)
         } else if let Some(ref context) = inner.derive_key {
             blake3::Hasher::new_derive_key(context)
         } else {
             blake3::Hasher::new()
         };
+        let base_hasher = Zeroizing::new(base_hasher);
+        let meter = (inner.progress || inner.stats).then(|| {
+            let total = if inner.check || inner.recursive || inner.tree_hash {
+                None
+            } else {
+                let range = range::InputRange::new(inner.input_offset, inner.input_length);
+                total_input_len(&file_args, range)
+            };
+            progress::Meter::new(total)
+        });
         Ok(Self {
             inner,
             file_args,
             base_hasher,
+            meter,
         })
     }
 
+    fn meter(&self) -> Option<&progress::Meter> {
+        self.meter.as_ref()
+    }
+
+    fn progress(&self) -> bool {
+        self.inner.progress
+    }
+
+    fn stats(&self) -> bool {
+        self.inner.stats
+    }
+
+    // Erase the --progress line, if any, while printing results.
+    fn suspend_progress<T>(&self, f: impl FnOnce() -> T) -> T {
+        match self.meter() {
+            Some(meter) => meter.suspend(f),
+            None => f(),
+        }
+    }
+
     fn num_threads(&self) -> Option<usize> {
         self.inner.num_threads
     }
@@ -142,6 +456,14 @@ impl Args {
         self.inner.no_mmap
     }
 
+    fn direct_io(&self) -> bool {
+        self.inner.direct_io
+    }
+
+    fn input_range(&self) -> Option<range::InputRange> {
+        range::InputRange::new(self.inner.input_offset, self.inner.input_length)
+    }
+
     fn no_names(&self) -> bool {
         self.inner.no_names
     }
@@ -158,46 +480,213 @@ impl Args {
         self.inner.keyed
     }
 
+    fn key_from_stdin(&self) -> bool {
+        self.keyed()
+            && self.inner.key_file.is_none()
+            && self.inner.key_hex_env.is_none()
+            && self.inner.key_fd.is_none()
+    }
+
     fn quiet(&self) -> bool {
         self.inner.quiet
     }
+
+    fn ignore_missing(&self) -> bool {
+        self.inner.ignore_missing
+    }
+
+    fn strict(&self) -> bool {
+        self.inner.strict
+    }
+
+    fn warn(&self) -> bool {
+        self.inner.warn
+    }
+
+    fn safe_paths(&self) -> bool {
+        self.inner.safe_paths
+    }
+
+    fn tag(&self) -> bool {
+        self.inner.tag
+    }
+
+    fn ignore_other_algorithms(&self) -> bool {
+        self.inner.ignore_other_algorithms
+    }
+
+    fn json(&self) -> bool {
+        self.inner.json
+    }
+
+    fn mode(&self) -> &'static str {
+        if self.keyed() {
+            "keyed_hash"
+        } else if self.inner.derive_key.is_some() {
+            "derive_key"
+        } else {
+            "hash"
+        }
+    }
+
+    // Whether output goes through render(), for --json or --format.
+    fn structured_output(&self) -> bool {
+        self.json() || self.inner.format.is_some()
+    }
+
+    // Only for structured_output().
+    fn render(&self, record: &output::Record) -> String {
+        match &self.inner.format {
+            Some(format) => format.render(record),
+            None => record.to_json(),
+        }
+    }
+
+    fn tree_hash(&self) -> bool {
+        self.inner.tree_hash
+    }
+
+    fn tree_exec(&self) -> bool {
+        self.inner.tree_exec
+    }
+
+    fn tree_symlinks(&self) -> bool {
+        self.inner.tree_symlinks
+    }
+
+    fn recursive(&self) -> bool {
+        self.inner.recursive
+    }
+
+    fn exclude(&self) -> &[glob::Pattern] {
+        &self.inner.exclude
+    }
+
+    fn include(&self) -> &[glob::Pattern] {
+        &self.inner.include
+    }
+
+    fn one_file_system(&self) -> bool {
+        self.inner.one_file_system
+    }
+
+    fn symlinks(&self) -> SymlinkPolicy {
+        self.inner.symlinks
+    }
+}
+
+struct HashedInput {
+    output: Zeroizing<blake3::OutputReader>,
+    // The number of bytes hashed, or None for --tree-hash.
+    size: Option<u64>,
+}
+
+fn hash_path(args: &Args, path: &Path) -> Result<HashedInput> {
+    let (hasher, size) = if args.tree_hash() {
+        (tree::hash_tree(args, path)?, None)
+    } else {
+        let hasher = hash_file(args, path)?;
+        let size = hasher.count();
+        (hasher, Some(size))
+    };
+    let mut output_reader = Zeroizing::new(hasher.finalize_xof());
+    output_reader.set_position(args.seek());
+    Ok(HashedInput {
+        output: output_reader,
+        size,
+    })
+}
+
+// The total length of the inputs for --progress, if they're all regular files.
+fn total_input_len(file_args: &[PathBuf], range: Option<range::InputRange>) -> Option<u64> {
+    let mut total = 0u64;
+    for path in file_args {
+        if path == Path::new("-") {
+            return None;
+        }
+        let metadata = std::fs::metadata(path).ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let len = match range {
+            Some(range) => range.clamp(metadata.len()),
+            None => metadata.len(),
+        };
+        total = total.saturating_add(len);
+    }
+    Some(total)
 }
 
-fn hash_path(args: &Args, path: &Path) -> Result<blake3::OutputReader> {
+fn hash_file(args: &Args, path: &Path) -> Result<Zeroizing<blake3::Hasher>> {
     let mut hasher = args.base_hasher.clone();
+    let meter = args.meter();
     if path == Path::new("-") {
-        if args.keyed() {
-            bail!("Cannot open `-` in keyed mode");
+        if args.key_from_stdin() {
+            bail!("Cannot open `-` in keyed mode without a --key-* source");
+        }
+        match args.input_range() {
+            Some(range) => range::update_reader(&mut hasher, io::stdin(), range, meter)?,
+            None => {
+                hasher.update_reader_rayon(progress::CountingReader::new(io::stdin(), meter))?;
+            }
+        }
+    } else if args.symlinks() == SymlinkPolicy::Target && path.is_symlink() {
+        if args.input_range().is_some() {
+            bail!("--input-offset and --input-length can't be used with --symlinks=target");
         }
-        hasher.update_reader(io::stdin().lock())?;
+        // Hash the target path the way it would be printed.
+        let target = filepath_to_string(&std::fs::read_link(path)?).filepath_string;
+        hasher.update(target.as_bytes());
+        if let Some(meter) = meter {
+            meter.add_bytes(hasher.count());
+        }
+    } else if args.direct_io() {
+        #[cfg(target_os = "linux")]
+        {
+            hasher.update_file_direct(path)?;
+            // Direct reads only get counted once they're all done.
+            if let Some(meter) = meter {
+                meter.add_bytes(hasher.count());
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        bail!("--direct-io is only supported on Linux");
+    } else if let Some(range) = args.input_range() {
+        range::update_file(&mut hasher, path, range, !args.no_mmap(), meter)?;
     } else if args.no_mmap() {
-        hasher.update_reader(File::open(path)?)?;
+        hasher.update_reader_rayon(progress::CountingReader::new(File::open(path)?, meter))?;
+    } else if let Some(meter) = meter {
+        // Mmap the file one window at a time, so that the meter can count as we go.
+        progress::update_mmap_rayon(&mut hasher, path, meter)?;
     } else {
         // The fast path: Try to mmap the file and hash it with multiple threads.
         hasher.update_mmap_rayon(path)?;
     }
-    let mut output_reader = hasher.finalize_xof();
-    output_reader.set_position(args.seek());
-    Ok(output_reader)
+    if let Some(meter) = meter {
+        meter.add_file();
+    }
+    Ok(hasher)
 }
 
-fn write_hex_output(mut output: blake3::OutputReader, args: &Args) -> Result<()> {
-    // Encoding multiples of the 64 bytes is most efficient.
-    // TODO: This computes each output block twice when the --seek argument isn't a multiple of 64.
-    // We'll refactor all of this soon anyway, once SIMD optimizations are available for the XOF.
+fn write_hex_output(output: &mut blake3::OutputReader, args: &Args) -> Result<()> {
+    // Encoding multiples of 64 bytes is most efficient, because the OutputReader computes whole
+    // blocks several at a time. If --seek isn't a multiple of 64, make the first read short, so
+    // that every read after it starts on a block boundary.
     let mut len = args.len();
-    let mut block = [0; blake3::guts::BLOCK_LEN];
+    let mut buf = [0; 16 * blake3::guts::BLOCK_LEN];
+    let position_within_block = (output.position() % blake3::guts::BLOCK_LEN as u64) as usize;
+    let mut read_len = buf.len() - position_within_block;
     while len > 0 {
-        output.fill(&mut block);
-        let hex_str = hex::encode(&block[..]);
-        let take_bytes = cmp::min(len, block.len() as u64);
-        print!("{}", &hex_str[..2 * take_bytes as usize]);
-        len -= take_bytes;
+        let take_bytes = cmp::min(len, read_len as u64) as usize;
+        output.fill(&mut buf[..take_bytes]);
+        print!("{}", hex::encode(&buf[..take_bytes]));
+        len -= take_bytes as u64;
+        read_len = buf.len();
     }
     Ok(())
 }
 
-fn write_raw_output(output: blake3::OutputReader, args: &Args) -> Result<()> {
+fn write_raw_output(output: &mut blake3::OutputReader, args: &Args) -> Result<()> {
     let mut output = output.take(args.len());
     let stdout = std::io::stdout();
     let mut handler = stdout.lock();
@@ -206,25 +695,6 @@ fn write_raw_output(output: blake3::OutputReader, args: &Args) -> Result<()> {
     Ok(())
 }
 
-fn read_key_from_stdin() -> Result<[u8; blake3::KEY_LEN]> {
-    let mut bytes = Vec::with_capacity(blake3::KEY_LEN + 1);
-    let n = std::io::stdin()
-        .lock()
-        .take(blake3::KEY_LEN as u64 + 1)
-        .read_to_end(&mut bytes)?;
-    if n < blake3::KEY_LEN {
-        bail!(
-            "expected {} key bytes from stdin, found {}",
-            blake3::KEY_LEN,
-            n,
-        )
-    } else if n > blake3::KEY_LEN {
-        bail!("read more than {} key bytes from stdin", blake3::KEY_LEN)
-    } else {
-        Ok(bytes[..blake3::KEY_LEN].try_into().unwrap())
-    }
-}
-
 struct FilepathString {
     filepath_string: String,
     is_escaped: bool,
@@ -266,6 +736,19 @@ fn hex_half_byte(c: char) -> Result<u8> {
     bail!("Invalid hex");
 }
 
+// A checkfile path that we refuse to check. Unlike other problems with a checkfile line, these
+// always fail, even without --strict.
+#[derive(Debug)]
+struct RejectedPath(&'static str);
+
+impl std::fmt::Display for RejectedPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for RejectedPath {}
+
 // The `check` command is a security tool. That means it's much better for a
 // check to fail more often than it should (a false negative), than for a check
 // to ever succeed when it shouldn't (a false positive). By forbidding certain
@@ -275,13 +758,13 @@ fn check_for_invalid_characters(utf8_path: &str) -> Result<()> {
     // Null characters in paths should never happen, but they can result in a
     // path getting silently truncated on Unix.
     if utf8_path.contains('\0') {
-        bail!("Null character in path");
+        bail!(RejectedPath("Null character in path"));
     }
     // Because we convert invalid UTF-8 sequences in paths to the Unicode
     // replacement character, multiple different invalid paths can map to the
     // same UTF-8 string.
     if utf8_path.contains('�') {
-        bail!("Unicode replacement character in path");
+        bail!(RejectedPath("Unicode replacement character in path"));
     }
     // We normalize all Windows backslashes to forward slashes in our output,
     // so the only natural way to get a backslash in a checkfile on Windows is
@@ -290,7 +773,7 @@ fn check_for_invalid_characters(utf8_path: &str) -> Result<()> {
     // we forbid backslashes entirely on Windows. Note that this check comes
     // after unescaping has been done.
     if cfg!(windows) && utf8_path.contains('\\') {
-        bail!("Backslash in path");
+        bail!(RejectedPath("Backslash in path"));
     }
     Ok(())
 }
@@ -298,13 +781,13 @@ fn check_for_invalid_characters(utf8_path: &str) -> Result<()> {
 fn unescape(mut path: &str) -> Result<String> {
     let mut unescaped = String::with_capacity(2 * path.len());
     while let Some(i) = path.find('\\') {
-        ensure!(i < path.len() - 1, "Invalid backslash escape");
+        ensure!(i < path.len() - 1, RejectedPath("Invalid backslash escape"));
         unescaped.push_str(&path[..i]);
         match path[i + 1..].chars().next().unwrap() {
             // Anything other than a recognized escape sequence is an error.
             'n' => unescaped.push_str("\n"),
             '\\' => unescaped.push_str("\\"),
-            _ => bail!("Invalid backslash escape"),
+            _ => bail!(RejectedPath("Invalid backslash escape")),
         }
         path = &path[i + 2..];
     }
@@ -317,7 +800,39 @@ struct ParsedCheckLine {
     file_string: String,
     is_escaped: bool,
     file_path: PathBuf,
-    expected_hash: blake3::Hash,
+    expected_output: Vec<u8>,
+}
+
+// If this is a tagged line in the style of `md5sum --tag`, like `BLAKE3 (foo) = <hex>`, return
+// the algorithm tag. Tags are ASCII letters, digits, and dashes, and they start with a letter, so
+// an untagged line with a path that contains " (" isn't confused for one.
+fn tagged_line_algorithm(line: &str) -> Option<&str> {
+    let line = line.strip_prefix('\\').unwrap_or(line);
+    let algorithm = &line[..line.find(" (")?];
+    let mut chars = algorithm.chars();
+    if chars.next()?.is_ascii_alphabetic() && chars.all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        Some(algorithm)
+    } else {
+        None
+    }
+}
+
+// `BLAKE3` means the default output length, and e.g. `BLAKE3-512` means a 64-byte output. This
+// matches the way `b2sum --tag` works.
+fn blake3_tag_output_len(algorithm: &str) -> Result<usize> {
+    if algorithm == TAG {
+        return Ok(blake3::OUT_LEN);
+    }
+    let bits = algorithm
+        .strip_prefix(TAG)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .ok_or_else(|| anyhow::anyhow!("Unsupported algorithm {}", algorithm))?;
+    // Leading zeros and other variations would be harmless, but rejecting them keeps each length
+    // spelled only one way.
+    match bits.parse::<usize>() {
+        Ok(n) if n > 0 && n % 8 == 0 && !bits.starts_with('0') => Ok(n / 8),
+        _ => bail!("Invalid {} output length", TAG),
+    }
 }
 
 fn parse_check_line(mut line: &str) -> Result<ParsedCheckLine> {
@@ -330,33 +845,51 @@ fn parse_check_line(mut line: &str) -> Result<ParsedCheckLine> {
     } else {
         bail!("Empty line");
     };
+    let algorithm = tagged_line_algorithm(line);
     let mut is_escaped = false;
     if first == '\\' {
         is_escaped = true;
         line = &line[1..];
     }
-    // The front of the line must be a hash of the usual length, followed by
-    // two spaces. The hex characters in the hash must be lowercase for now,
-    // though we could support uppercase too if we wanted to.
-    let hash_hex_len = 2 * blake3::OUT_LEN;
-    let num_spaces = 2;
-    let prefix_len = hash_hex_len + num_spaces;
-    ensure!(line.len() > prefix_len, "Short line");
-    ensure!(
-        line.chars().take(prefix_len).all(|c| c.is_ascii()),
-        "Non-ASCII prefix"
-    );
-    ensure!(&line[hash_hex_len..][..2] == "  ", "Invalid space");
+    let hash_hex;
+    let file_string;
+    if let Some(algorithm) = algorithm {
+        // A tagged line is `ALGORITHM (path) = hex`. The hex can't contain
+        // ") = ", so search for that from the right, in case the path
+        // contains it.
+        let output_len = blake3_tag_output_len(algorithm)?;
+        let rest = &line[algorithm.len() + 2..];
+        let separator = rest
+            .rfind(") = ")
+            .ok_or_else(|| anyhow::anyhow!("Invalid tag"))?;
+        file_string = rest[..separator].to_string();
+        hash_hex = &rest[separator + 4..];
+        ensure!(hash_hex.len() == 2 * output_len, "Wrong hash length");
+        ensure!(!file_string.is_empty(), "Empty path");
+    } else {
+        // The front of the line must be a hash, followed by two spaces. The
+        // hash can be any nonzero number of bytes, which is how --check
+        // supports --length. The hex characters in the hash must be lowercase
+        // for now, though we could support uppercase too if we wanted to.
+        ensure!(line.contains(' '), "Short line");
+        let hash_hex_len = line
+            .find("  ")
+            .ok_or_else(|| anyhow::anyhow!("Invalid space"))?;
+        ensure!(hash_hex_len > 0, "Short line");
+        ensure!(hash_hex_len % 2 == 0, "Wrong hash length");
+        hash_hex = &line[..hash_hex_len];
+        file_string = line[hash_hex_len + 2..].to_string();
+        ensure!(!file_string.is_empty(), "Short line");
+    }
     // Decode the hash hex.
-    let mut hash_bytes = [0; blake3::OUT_LEN];
-    let mut hex_chars = line[..hash_hex_len].chars();
-    for byte in &mut hash_bytes {
+    ensure!(hash_hex.is_ascii(), "Invalid hex");
+    let mut expected_output = vec![0; hash_hex.len() / 2];
+    let mut hex_chars = hash_hex.chars();
+    for byte in &mut expected_output {
         let high_char = hex_chars.next().unwrap();
         let low_char = hex_chars.next().unwrap();
         *byte = 16 * hex_half_byte(high_char)? + hex_half_byte(low_char)?;
     }
-    let expected_hash: blake3::Hash = hash_bytes.into();
-    let file_string = line[prefix_len..].to_string();
     let file_path_string = if is_escaped {
         // If we detected a backslash at the start of the line earlier, now we
         // need to unescape backslashes and newlines.
@@ -369,18 +902,37 @@ fn parse_check_line(mut line: &str) -> Result<ParsedCheckLine> {
         file_string,
         is_escaped,
         file_path: file_path_string.into(),
-        expected_hash,
+        expected_output,
     })
 }
 
-fn hash_one_input(path: &Path, args: &Args) -> Result<()> {
-    let output = hash_path(args, path)?;
+fn write_one_output(path: &Path, hashed: HashedInput, args: &Args) -> Result<()> {
+    let HashedInput { mut output, size } = hashed;
+    if args.structured_output() {
+        let mut bytes = vec![0; usize::try_from(args.len())?];
+        output.fill(&mut bytes);
+        let record = output::Record {
+            path: Some(path),
+            hash: Some(hex::encode(bytes)),
+            size,
+            length: args.len(),
+            seek: args.seek(),
+            mode: args.mode(),
+            status: None,
+            error: None,
+        };
+        println!("{}", args.render(&record));
+        return Ok(());
+    }
+    if args.tag() {
+        return write_tagged_output(path, &mut output, args);
+    }
     if args.raw() {
-        write_raw_output(output, args)?;
+        write_raw_output(&mut output, args)?;
         return Ok(());
     }
     if args.no_names() {
-        write_hex_output(output, args)?;
+        write_hex_output(&mut output, args)?;
         println!();
         return Ok(());
     }
@@ -391,26 +943,298 @@ fn hash_one_input(path: &Path, args: &Args) -> Result<()> {
     if is_escaped {
         print!("\\");
     }
-    write_hex_output(output, args)?;
+    write_hex_output(&mut output, args)?;
     println!("  {}", filepath_string);
     Ok(())
 }
 
-// Returns true for success. Having a boolean return value here, instead of
-// passing down the files_failed reference, makes it less likely that we might
-// forget to set it in some error condition.
-fn check_one_line(line: &str, args: &Args) -> bool {
-    let parse_result = parse_check_line(&line);
+// `BLAKE3 (path) = hex`, in the style of `md5sum --tag`.
+fn write_tagged_output(path: &Path, output: &mut blake3::OutputReader, args: &Args) -> Result<()> {
+    let FilepathString {
+        filepath_string,
+        is_escaped,
+    } = filepath_to_string(path);
+    let mut algorithm = TAG.to_string();
+    if args.len() != blake3::OUT_LEN as u64 {
+        let bits = args
+            .len()
+            .checked_mul(8)
+            .ok_or_else(|| anyhow::anyhow!("Output length too large for --tag"))?;
+        algorithm += &format!("-{}", bits);
+    }
+    if is_escaped {
+        print!("\\");
+    }
+    print!("{} ({}) = ", algorithm, filepath_string);
+    write_hex_output(output, args)?;
+    println!();
+    Ok(())
+}
+
+// Inputs are hashed in parallel batches, and their output is printed in order. Most of the work
+// happens in the parallel part, and the output for each input is just its OutputReader. The batch
+// size bounds how many of those are held back, waiting for an earlier input that's slower.
+struct InputBatch<'a> {
+    args: &'a Args,
+    // Errors from the walk are queued too, so that they get printed in order.
+    inputs: Vec<(PathBuf, Result<()>)>,
+    batch_len: usize,
+    files_failed: &'a mut u64,
+}
+
+impl<'a> InputBatch<'a> {
+    fn new(args: &'a Args, files_failed: &'a mut u64) -> Self {
+        let batch_len = 64 * rayon::current_num_threads();
+        Self {
+            args,
+            inputs: Vec::with_capacity(batch_len),
+            batch_len,
+            files_failed,
+        }
+    }
+
+    fn push(&mut self, path: &Path, walk_result: Result<()>) {
+        self.inputs.push((path.to_owned(), walk_result));
+        if self.inputs.len() == self.batch_len {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        use rayon::prelude::*;
+        let args = self.args;
+        // Large files use update_mmap_rayon() on the same thread pool, so they share it with the
+        // small ones, rather than oversubscribing the CPU.
+        let results: Vec<_> = std::mem::take(&mut self.inputs)
+            .into_par_iter()
+            .map(|(path, walk_result)| {
+                let output = walk_result.and_then(|()| hash_path(args, &path));
+                (path, output)
+            })
+            .collect();
+        args.suspend_progress(|| self.print_results(results));
+    }
+
+    fn print_results(&mut self, results: Vec<(PathBuf, Result<HashedInput>)>) {
+        let args = self.args;
+        for (path, output) in results {
+            // Errors encountered in hashing are tolerated and printed to
+            // stderr, or as records with --json and --format. This allows
+            // e.g. `b3sum *` to print errors for non-files and keep going.
+            // However, if we encounter any errors we'll still return non-zero
+            // at the end.
+            if let Err(e) = output.and_then(|output| write_one_output(&path, output, args)) {
+                *self.files_failed = self.files_failed.saturating_add(1);
+                if args.structured_output() {
+                    let record = output::Record {
+                        path: Some(&path),
+                        hash: None,
+                        size: None,
+                        length: args.len(),
+                        seek: args.seek(),
+                        mode: args.mode(),
+                        status: None,
+                        error: Some(e.to_string()),
+                    };
+                    println!("{}", args.render(&record));
+                } else {
+                    eprintln!("{}: {}: {}", NAME, path.to_string_lossy(), e);
+                }
+            }
+        }
+    }
+}
+
+// What happened to one checkfile line, for the summary and the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CheckOutcome {
+    Ok,
+    // Missing files with --ignore-missing.
+    Skipped,
+    // Other algorithms with --ignore-other-algorithms. Unlike skipped files, these don't count as
+    // properly formatted lines.
+    OtherAlgorithm,
+    Mismatched,
+    Unreadable,
+    Malformed,
+    Rejected,
+}
+
+// What checking one line printed. Lines are checked in parallel, so this gets printed afterwards,
+// in checkfile order.
+struct CheckedLine {
+    // Having an outcome field here, instead of passing down the summary reference, makes it less
+    // likely that we might forget to set it in some error condition.
+    outcome: CheckOutcome,
+    stdout: Option<String>,
+    stderr: Option<String>,
+}
+
+impl CheckedLine {
+    fn print(&self) {
+        if let Some(stdout) = &self.stdout {
+            println!("{}", stdout);
+        }
+        if let Some(stderr) = &self.stderr {
+            eprintln!("{}", stderr);
+        }
+    }
+}
+
+// Counts of each kind of problem, across all the checkfiles.
+#[derive(Default)]
+struct CheckSummary {
+    mismatched: u64,
+    unreadable: u64,
+    malformed: u64,
+    rejected: u64,
+    // Properly formatted lines, including failed ones, for noticing checkfiles that have none.
+    formatted: u64,
+    // Checkfiles with no properly formatted lines, including empty ones.
+    unformatted_checkfiles: u64,
+}
+
+impl CheckSummary {
+    fn add(&mut self, outcome: CheckOutcome) {
+        // The exit code depends on these being nonzero, so it's important for correctness that
+        // it's impossible for them to overflow.
+        if !matches!(
+            outcome,
+            CheckOutcome::Malformed | CheckOutcome::OtherAlgorithm
+        ) {
+            self.formatted = self.formatted.saturating_add(1);
+        }
+        let counter = match outcome {
+            CheckOutcome::Ok | CheckOutcome::Skipped | CheckOutcome::OtherAlgorithm => return,
+            CheckOutcome::Mismatched => &mut self.mismatched,
+            CheckOutcome::Unreadable => &mut self.unreadable,
+            CheckOutcome::Malformed => &mut self.malformed,
+            CheckOutcome::Rejected => &mut self.rejected,
+        };
+        *counter = counter.saturating_add(1);
+    }
+
+    fn print(&self) {
+        let warn = |count: u64, one: &str, many: &str| {
+            if count == 1 {
+                eprintln!("{}: WARNING: 1 {}", NAME, one);
+            } else if count > 1 {
+                eprintln!("{}: WARNING: {} {}", NAME, count, many);
+            }
+        };
+        warn(
+            self.malformed,
+            "line is improperly formatted",
+            "lines are improperly formatted",
+        );
+        warn(
+            self.rejected,
+            "listed path was rejected",
+            "listed paths were rejected",
+        );
+        warn(
+            self.unreadable,
+            "listed file could not be read",
+            "listed files could not be read",
+        );
+        warn(
+            self.mismatched,
+            "computed checksum did NOT match",
+            "computed checksums did NOT match",
+        );
+    }
+
+    // The most serious kind of problem determines the exit code.
+    fn exit_code(&self, args: &Args) -> i32 {
+        if self.mismatched > 0 {
+            EXIT_MISMATCHED
+        } else if self.unreadable > 0 {
+            EXIT_UNREADABLE
+        } else if self.rejected > 0 {
+            EXIT_REJECTED
+        } else if self.unformatted_checkfiles > 0 || (self.malformed > 0 && args.strict()) {
+            EXIT_MALFORMED
+        } else {
+            0
+        }
+    }
+}
+
+// Reject paths that could point outside of the current directory.
+fn check_safe_path(path: &Path) -> Result<()> {
+    use std::path::Component;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => bail!(RejectedPath("Absolute path")),
+            Component::ParentDir => bail!(RejectedPath("Parent directory in path")),
+            Component::CurDir | Component::Normal(_) => {}
+        }
+    }
+    Ok(())
+}
+
+// `line_number` counts from 1, and it's only used in messages.
+fn check_one_line(line: &str, checkfile: &Path, line_number: u64, args: &Args) -> CheckedLine {
+    let checked = |outcome, stdout, stderr| CheckedLine {
+        outcome,
+        stdout,
+        stderr,
+    };
+    if args.ignore_other_algorithms() {
+        if let Some(algorithm) = tagged_line_algorithm(line) {
+            if algorithm != TAG && !algorithm.starts_with(&format!("{}-", TAG)) {
+                return checked(CheckOutcome::OtherAlgorithm, None, None);
+            }
+        }
+    }
+    let parse_result = parse_check_line(line).and_then(|parsed| {
+        if args.safe_paths() {
+            check_safe_path(&parsed.file_path)?;
+        }
+        Ok(parsed)
+    });
     let ParsedCheckLine {
         file_string,
         is_escaped,
         file_path,
-        expected_hash,
+        expected_output,
     } = match parse_result {
         Ok(parsed) => parsed,
         Err(e) => {
-            eprintln!("{}: {}", NAME, e);
-            return false;
+            let outcome = if e.is::<RejectedPath>() {
+                CheckOutcome::Rejected
+            } else {
+                CheckOutcome::Malformed
+            };
+            // With --json or --format, invalid lines are reported on stdout like everything else.
+            let record = output::Record {
+                path: None,
+                hash: None,
+                size: None,
+                length: args.len(),
+                seek: args.seek(),
+                mode: args.mode(),
+                status: Some("INVALID"),
+                error: Some(e.to_string()),
+            };
+            if args.structured_output() {
+                return checked(outcome, Some(args.render(&record)), None);
+            }
+            let stderr = if args.warn() {
+                Some(format!(
+                    "{}: {}:{}: {}",
+                    NAME,
+                    checkfile.to_string_lossy(),
+                    line_number,
+                    e,
+                ))
+            } else if outcome == CheckOutcome::Rejected {
+                Some(format!("{}: {}", NAME, e))
+            } else {
+                // Like md5sum, improperly formatted lines are only counted, without --warn.
+                None
+            };
+            return checked(outcome, None, stderr);
         }
     };
     let file_string = if is_escaped {
@@ -418,36 +1242,90 @@ fn check_one_line(line: &str, args: &Args) -> bool {
     } else {
         file_string
     };
-    let found_hash: blake3::Hash;
-    match hash_path(args, &file_path) {
-        Ok(mut output) => {
-            let mut found_hash_bytes = [0; blake3::OUT_LEN];
-            output.fill(&mut found_hash_bytes);
-            found_hash = found_hash_bytes.into();
+    let record = |status, size, error| output::Record {
+        path: Some(&file_path),
+        hash: Some(hex::encode(&expected_output)),
+        size,
+        length: expected_output.len() as u64,
+        seek: args.seek(),
+        mode: args.mode(),
+        status: Some(status),
+        error,
+    };
+    let mut found_output = vec![0; expected_output.len()];
+    let size = match hash_path(args, &file_path) {
+        Ok(mut hashed) => {
+            hashed.output.fill(&mut found_output);
+            hashed.size
         }
         Err(e) => {
-            println!("{}: FAILED ({})", file_string, e);
-            return false;
+            let not_found = e
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::NotFound);
+            if not_found && args.ignore_missing() {
+                return checked(CheckOutcome::Skipped, None, None);
+            }
+            let stdout = if args.structured_output() {
+                args.render(&record("FAILED", None, Some(e.to_string())))
+            } else {
+                format!("{}: FAILED ({})", file_string, e)
+            };
+            return checked(CheckOutcome::Unreadable, Some(stdout), None);
         }
     };
-    // This is a constant-time comparison.
-    if expected_hash == found_hash {
-        if !args.quiet() {
-            println!("{}: OK", file_string);
-        }
-        true
+    // This is a constant-time comparison, the same one blake3::Hash uses.
+    if constant_time_eq::constant_time_eq(&expected_output, &found_output) {
+        let stdout = (!args.quiet()).then(|| {
+            if args.structured_output() {
+                args.render(&record("OK", size, None))
+            } else {
+                format!("{}: OK", file_string)
+            }
+        });
+        checked(CheckOutcome::Ok, stdout, None)
     } else {
-        println!("{}: FAILED", file_string);
-        false
+        let stdout = if args.structured_output() {
+            args.render(&record("FAILED", size, None))
+        } else {
+            format!("{}: FAILED", file_string)
+        };
+        checked(CheckOutcome::Mismatched, Some(stdout), None)
     }
 }
 
-fn check_one_checkfile(path: &Path, args: &Args, files_failed: &mut u64) -> Result<()> {
+// Check a batch of lines in parallel, and print the results in order. Each line is hashed on the
+// same thread pool, so large files that use update_mmap_rayon() share it with the small ones,
+// rather than oversubscribing the CPU.
+fn check_lines(
+    lines: &[String],
+    checkfile: &Path,
+    first_line_number: u64,
+    args: &Args,
+    summary: &mut CheckSummary,
+) {
+    use rayon::prelude::*;
+    let results: Vec<CheckedLine> = lines
+        .par_iter()
+        .enumerate()
+        .map(|(i, line)| check_one_line(line, checkfile, first_line_number + i as u64, args))
+        .collect();
+    args.suspend_progress(|| {
+        for result in &results {
+            result.print();
+            summary.add(result.outcome);
+        }
+    });
+}
+
+fn check_one_checkfile(path: &Path, args: &Args, summary: &mut CheckSummary) -> Result<()> {
     let mut file;
     let stdin;
     let mut stdin_lock;
     let mut bufreader: io::BufReader<&mut dyn Read>;
     if path == Path::new("-") {
+        if args.key_from_stdin() {
+            bail!("Cannot read the checkfile from `-` in keyed mode without a --key-* source");
+        }
         stdin = io::stdin();
         stdin_lock = stdin.lock();
         bufreader = io::BufReader::new(&mut stdin_lock);
@@ -455,59 +1333,124 @@ fn check_one_checkfile(path: &Path, args: &Args, files_failed: &mut u64) -> Resu
         file = File::open(path)?;
         bufreader = io::BufReader::new(&mut file);
     }
-    let mut line = String::new();
+    // Reading the checkfile in batches keeps memory use bounded, and it keeps output flowing.
+    let batch_len = 64 * rayon::current_num_threads();
+    let mut lines = Vec::with_capacity(batch_len);
+    let mut first_line_number = 1;
+    let formatted_before = summary.formatted;
     loop {
-        line.clear();
-        let n = bufreader.read_line(&mut line)?;
-        if n == 0 {
-            return Ok(());
+        let mut line = String::new();
+        let read_result = bufreader.read_line(&mut line);
+        let done = !matches!(read_result, Ok(n) if n > 0);
+        if !done {
+            lines.push(line);
         }
-        // check_one_line() prints errors and turns them into a success=false
-        // return, so it doesn't return a Result.
-        let success = check_one_line(&line, args);
-        if !success {
-            // We use `files_failed > 0` to indicate a mismatch, so it's important for correctness
-            // that it's impossible for this counter to overflow.
-            *files_failed = files_failed.saturating_add(1);
+        if done || lines.len() == batch_len {
+            // check_one_line() turns errors into an outcome in the summary, so it doesn't return a
+            // Result.
+            check_lines(&lines, path, first_line_number, args, summary);
+            first_line_number += lines.len() as u64;
+            lines.clear();
+        }
+        if done {
+            // Lines before a read error are still checked.
+            read_result?;
+            break;
         }
     }
+    // Like md5sum, a checkfile that's all garbage fails even without --strict, because otherwise
+    // checking the wrong file would look like a success.
+    if summary.formatted == formatted_before {
+        args.suspend_progress(|| {
+            eprintln!(
+                "{}: {}: no properly formatted checksum lines found",
+                NAME,
+                path.to_string_lossy(),
+            );
+        });
+        summary.unformatted_checkfiles = summary.unformatted_checkfiles.saturating_add(1);
+    }
+    Ok(())
+}
+
+// b3sum hashed files named `encode` and so on before those were subcommands, so a first argument
+// that names an existing path is still a file to hash. The --encode style flags always mean the
+// subcommand.
+fn parse_inner() -> Inner {
+    // wild::args_os() is equivalent to std::env::args_os() on Unix,
+    // but on Windows it adds support for globbing.
+    let mut args: Vec<std::ffi::OsString> = wild::args_os().collect();
+    let shadowed = args
+        .get(1)
+        .and_then(|arg| arg.to_str())
+        .filter(|arg| Inner::command().find_subcommand(arg).is_some() && Path::new(arg).exists())
+        .map(PathBuf::from);
+    let Some(shadowed) = shadowed else {
+        return Inner::parse_from(args);
+    };
+    // Spell the path differently so that clap doesn't see the subcommand, but print it as given.
+    args[1] = Path::new(".").join(&shadowed).into();
+    let mut inner = Inner::parse_from(args);
+    inner.file[0] = shadowed;
+    inner
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse()?;
+    let inner = parse_inner();
+    if let Some(command) = &inner.command {
+        return encoding::run(command);
+    }
+    let args = Args::from_inner(inner)?;
     let mut thread_pool_builder = rayon::ThreadPoolBuilder::new();
     if let Some(num_threads) = args.num_threads() {
         thread_pool_builder = thread_pool_builder.num_threads(num_threads);
     }
     let thread_pool = thread_pool_builder.build()?;
-    thread_pool.install(|| {
-        let mut files_failed = 0u64;
-        // Note that file_args automatically includes `-` if nothing is given.
+    let result = std::thread::scope(|scope| {
+        // The progress line is drawn until this sender is dropped.
+        let _stop_progress = args.meter().filter(|_| args.progress()).map(|meter| {
+            let (sender, receiver) = std::sync::mpsc::channel::<()>();
+            scope.spawn(move || meter.draw_until(receiver));
+            sender
+        });
+        thread_pool.install(|| run(&args))
+    });
+    if let Some(meter) = args.meter().filter(|_| args.stats()) {
+        meter.print_stats();
+    }
+    std::process::exit(result?);
+}
+
+// Returns the exit code.
+fn run(args: &Args) -> Result<i32> {
+    // Note that file_args automatically includes `-` if nothing is given.
+    if args.check() {
+        let mut summary = CheckSummary::default();
         for path in &args.file_args {
-            if args.check() {
-                check_one_checkfile(path, &args, &mut files_failed)?;
-            } else {
-                // Errors encountered in hashing are tolerated and printed to
-                // stderr. This allows e.g. `b3sum *` to print errors for
-                // non-files and keep going. However, if we encounter any
-                // errors we'll still return non-zero at the end.
-                let result = hash_one_input(path, &args);
-                if let Err(e) = result {
-                    files_failed = files_failed.saturating_add(1);
-                    eprintln!("{}: {}: {}", NAME, path.to_string_lossy(), e);
-                }
-            }
+            check_one_checkfile(path, args, &mut summary)?;
         }
-        if args.check() && files_failed > 0 {
-            eprintln!(
-                "{}: WARNING: {} computed checksum{} did NOT match",
-                NAME,
-                files_failed,
-                if files_failed == 1 { "" } else { "s" },
-            );
+        args.suspend_progress(|| summary.print());
+        return Ok(summary.exit_code(args));
+    }
+    let mut files_failed = 0u64;
+    let mut inputs = InputBatch::new(args, &mut files_failed);
+    for path in &args.file_args {
+        if path == Path::new("-") {
+            // Print everything before standard input first, and don't read it
+            // concurrently with anything else, in case it's interactive.
+            inputs.flush();
+            inputs.push(path, Ok(()));
+            inputs.flush();
+        } else if args.recursive() && path.is_dir() {
+            walk::walk(args, path, &mut |file, walk_result| {
+                inputs.push(file, walk_result);
+            });
+        } else {
+            inputs.push(path, Ok(()));
         }
-        std::process::exit(if files_failed > 0 { 1 } else { 0 });
-    })
+    }
+    inputs.flush();
+    Ok(if files_failed > 0 { 1 } else { 0 })
 }
 
 #[cfg(test)]
@@ -0,0 +1,216 @@
+//! The --json and --format output styles. Both are meant for scripts, so unlike the default
+//! output, they don't depend on a leading backslash to say whether a path is escaped.
+
+use anyhow::{bail, Result};
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+// Everything that --json or --format can say about one input or one checkfile line. Fields that
+// don't apply are None.
+pub struct Record<'a> {
+    pub path: Option<&'a Path>,
+    pub hash: Option<String>,
+    pub size: Option<u64>,
+    pub length: u64,
+    pub seek: u64,
+    pub mode: &'static str,
+    // Only in --check mode: OK, FAILED, or INVALID for a line that couldn't be parsed.
+    pub status: Option<&'static str>,
+    pub error: Option<String>,
+}
+
+impl Record<'_> {
+    // One JSON object, without a trailing newline. Paths are written as a string, with any
+    // invalid Unicode replaced. When that loses information, `path_hex` holds the raw bytes.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        if let Some(path) = self.path {
+            let lossy = path.to_string_lossy();
+            write!(json, "\"path\":{}", json_string(&lossy)).unwrap();
+            if path.to_str().is_none() {
+                let raw = hex::encode(raw_path_bytes(path));
+                write!(json, ",\"path_hex\":\"{}\"", raw).unwrap();
+            }
+        } else {
+            json += "\"path\":null";
+        }
+        if let Some(status) = self.status {
+            write!(json, ",\"status\":\"{}\"", status).unwrap();
+        }
+        write!(
+            json,
+            ",\"hash\":{}",
+            json_option_string(self.hash.as_deref())
+        )
+        .unwrap();
+        match self.size {
+            Some(size) => write!(json, ",\"size\":{}", size).unwrap(),
+            None => json += ",\"size\":null",
+        }
+        write!(
+            json,
+            ",\"length\":{},\"seek\":{},\"mode\":\"{}\"",
+            self.length, self.seek, self.mode,
+        )
+        .unwrap();
+        write!(
+            json,
+            ",\"error\":{}}}",
+            json_option_string(self.error.as_deref())
+        )
+        .unwrap();
+        json
+    }
+}
+
+// The bytes of a path that isn't valid Unicode, for `path_hex`.
+#[cfg(unix)]
+fn raw_path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+// Windows paths are UTF-16, possibly with unpaired surrogates, so give the UTF-16LE bytes.
+#[cfg(windows)]
+fn raw_path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str()
+        .encode_wide()
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn raw_path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            '\n' => json += "\\n",
+            '\r' => json += "\\r",
+            '\t' => json += "\\t",
+            c if c < ' ' => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_option_string(s: Option<&str>) -> String {
+    s.map_or_else(|| "null".to_string(), json_string)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Hash,
+    Size,
+    Path,
+    Length,
+    Seek,
+    Mode,
+    Status,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+// A --format template, like `{hash} {size} {path}`. Fields that don't apply are empty, and `{{`
+// and `}}` are literal braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+        while let Some(i) = rest.find(['{', '}']) {
+            literal += &rest[..i];
+            let brace = &rest[i..];
+            if brace.starts_with("{{") || brace.starts_with("}}") {
+                literal.push(brace.chars().next().unwrap());
+                rest = &brace[2..];
+                continue;
+            }
+            if brace.starts_with('}') {
+                bail!("Unmatched }} in template");
+            }
+            let Some(end) = brace.find('}') else {
+                bail!("Unmatched {{ in template");
+            };
+            let field = match &brace[1..end] {
+                "hash" => Field::Hash,
+                "size" => Field::Size,
+                "path" => Field::Path,
+                "length" => Field::Length,
+                "seek" => Field::Seek,
+                "mode" => Field::Mode,
+                "status" => Field::Status,
+                "error" => Field::Error,
+                name => bail!("Unknown template field {{{}}}", name),
+            };
+            if !literal.is_empty() {
+                pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+            }
+            pieces.push(Piece::Field(field));
+            rest = &brace[end + 1..];
+        }
+        literal += rest;
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Self { pieces })
+    }
+}
+
+impl Template {
+    // Paths are always escaped the way b3sum prints them, with `\\` and `\n`, so that each record
+    // stays on one line.
+    pub fn render(&self, record: &Record) -> String {
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(literal) => out += literal,
+                Piece::Field(field) => match field {
+                    Field::Hash => out += record.hash.as_deref().unwrap_or(""),
+                    Field::Size => {
+                        if let Some(size) = record.size {
+                            write!(out, "{}", size).unwrap();
+                        }
+                    }
+                    Field::Path => {
+                        if let Some(path) = record.path {
+                            out += &crate::filepath_to_string(path).filepath_string;
+                        }
+                    }
+                    Field::Length => write!(out, "{}", record.length).unwrap(),
+                    Field::Seek => write!(out, "{}", record.seek).unwrap(),
+                    Field::Mode => out += record.mode,
+                    Field::Status => out += record.status.unwrap_or(""),
+                    Field::Error => {
+                        // Keep errors on one line too.
+                        let error = record.error.as_deref().unwrap_or("");
+                        out += &error.replace('\\', "\\\\").replace('\n', "\\n");
+                    }
+                },
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,175 @@
+//! The --progress line and the --stats summary. Both go to stderr, so that stdout is the same with
+//! or without them.
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+// Files shorter than this are read instead of mapped, the same as in Hasher::update_mmap_rayon.
+pub const MIN_MMAP_LEN: u64 = 16 * 1024;
+
+// Big files are mapped and hashed one window at a time, so that progress gets reported as they
+// go. This is a power of two, to keep every window hashing as wide as possible.
+pub const MMAP_WINDOW_LEN: u64 = 1 << 26;
+
+pub struct Meter {
+    start: Instant,
+    // The total length of the inputs, when it's known from metadata before we start.
+    total: Option<u64>,
+    bytes: AtomicU64,
+    files: AtomicU64,
+    // The length of the progress line currently drawn on stderr, or 0 if there isn't one.
+    drawn: Mutex<usize>,
+}
+
+impl Meter {
+    pub fn new(total: Option<u64>) -> Self {
+        Self {
+            start: Instant::now(),
+            total,
+            bytes: AtomicU64::new(0),
+            files: AtomicU64::new(0),
+            drawn: Mutex::new(0),
+        }
+    }
+
+    pub fn add_bytes(&self, n: u64) {
+        self.bytes.fetch_add(n, Relaxed);
+    }
+
+    pub fn add_file(&self) {
+        self.files.fetch_add(1, Relaxed);
+    }
+
+    // Redraw the progress line until `stop` is dropped, and then erase it.
+    pub fn draw_until(&self, stop: mpsc::Receiver<()>) {
+        while let Err(mpsc::RecvTimeoutError::Timeout) = stop.recv_timeout(REDRAW_INTERVAL) {
+            let line = self.progress_line();
+            let mut drawn = self.drawn.lock().unwrap();
+            // Pad with spaces to cover anything left over from a longer line.
+            eprint!("\r{:<width$}", line, width = *drawn);
+            *drawn = std::cmp::max(line.len(), *drawn);
+        }
+        self.suspend(|| ());
+    }
+
+    // Erase the progress line while printing something else. It gets redrawn on the next tick.
+    pub fn suspend<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut drawn = self.drawn.lock().unwrap();
+        if *drawn > 0 {
+            eprint!("\r{:width$}\r", "", width = *drawn);
+            *drawn = 0;
+        }
+        f()
+    }
+
+    fn progress_line(&self) -> String {
+        let bytes = self.bytes.load(Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            bytes as f64 / elapsed
+        } else {
+            0.0
+        };
+        let mut line = format_bytes(bytes);
+        if let Some(total) = self.total {
+            let percent = if total > 0 {
+                100.0 * bytes as f64 / total as f64
+            } else {
+                100.0
+            };
+            line += &format!(" / {} ({:.0}%)", format_bytes(total), percent);
+        }
+        line += &format!("  {}/s", format_bytes(rate as u64));
+        if let Some(total) = self.total {
+            if rate > 0.0 && total >= bytes {
+                let eta = Duration::from_secs_f64((total - bytes) as f64 / rate);
+                line += &format!("  ETA {}", format_duration(eta));
+            }
+        }
+        line
+    }
+
+    pub fn print_stats(&self) {
+        let bytes = self.bytes.load(Relaxed);
+        let files = self.files.load(Relaxed);
+        let elapsed = self.start.elapsed();
+        let rate = bytes as f64 / elapsed.as_secs_f64().max(f64::MIN_POSITIVE);
+        eprintln!(
+            "{}: {} file{}, {} ({} bytes) in {:.3}s, {}/s, {:?}",
+            crate::NAME,
+            files,
+            if files == 1 { "" } else { "s" },
+            format_bytes(bytes),
+            bytes,
+            elapsed.as_secs_f64(),
+            format_bytes(rate as u64),
+            blake3::platform::Platform::detect(),
+        );
+    }
+}
+
+fn format_bytes(n: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, UNITS[unit])
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+// Counts the bytes read through it, when there's a meter.
+pub struct CountingReader<'a, R> {
+    inner: R,
+    meter: Option<&'a Meter>,
+}
+
+impl<'a, R> CountingReader<'a, R> {
+    pub fn new(inner: R, meter: Option<&'a Meter>) -> Self {
+        Self { inner, meter }
+    }
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(meter) = self.meter {
+            meter.add_bytes(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+// Like Hasher::update_mmap_rayon, but one window at a time, counting each window as it's hashed.
+pub fn update_mmap_rayon(hasher: &mut blake3::Hasher, path: &Path, meter: &Meter) -> Result<()> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() < MIN_MMAP_LEN {
+        hasher.update_reader_rayon(CountingReader::new(file, Some(meter)))?;
+        return Ok(());
+    }
+    let len = metadata.len();
+    crate::range::update_mmap_rayon(hasher, &file, 0, len, MMAP_WINDOW_LEN, Some(meter))
+}
@@ -0,0 +1,178 @@
+//! Hashing one byte range of each input, for --input-offset and --input-length. The result is
+//! always the same as hashing the extracted bytes, and a range that goes past the end of the input
+//! is an error rather than a shorter hash.
+
+use crate::progress::{CountingReader, Meter, MIN_MMAP_LEN, MMAP_WINDOW_LEN};
+use anyhow::{bail, Result};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+#[derive(Clone, Copy)]
+pub struct InputRange {
+    pub offset: u64,
+    // None means everything after the offset.
+    pub length: Option<u64>,
+}
+
+impl InputRange {
+    // None if the whole input is hashed.
+    pub fn new(offset: Option<u64>, length: Option<u64>) -> Option<Self> {
+        if offset.is_none() && length.is_none() {
+            return None;
+        }
+        Some(Self {
+            offset: offset.unwrap_or(0),
+            length,
+        })
+    }
+
+    // The number of bytes the range covers in an input of `size` bytes, for --progress. Ranges
+    // that don't fit are errors later on, so this only needs to be a sensible guess for them.
+    pub fn clamp(&self, size: u64) -> u64 {
+        let len = size.saturating_sub(self.offset);
+        self.length.map_or(len, |length| std::cmp::min(len, length))
+    }
+
+    // The length of the range within an input of `size` bytes, or an error if it doesn't fit.
+    fn len_within(&self, size: u64) -> Result<u64> {
+        if self.offset > size {
+            bail!(
+                "--input-offset {} is past the end of the input ({} bytes)",
+                self.offset,
+                size,
+            );
+        }
+        match self.length {
+            Some(length) if length > size - self.offset && self.offset == 0 => bail!(
+                "--input-length {} is past the end of the input ({} bytes)",
+                length,
+                size,
+            ),
+            Some(length) if length > size - self.offset => bail!(
+                "--input-offset {} plus --input-length {} is past the end of the input ({} bytes)",
+                self.offset,
+                length,
+                size,
+            ),
+            Some(length) => Ok(length),
+            None => Ok(size - self.offset),
+        }
+    }
+}
+
+// Hash the range from a reader that can't seek, like stdin or a pipe, by reading and discarding
+// everything before it.
+pub fn update_reader(
+    hasher: &mut blake3::Hasher,
+    mut reader: impl Read + Send,
+    range: InputRange,
+    meter: Option<&Meter>,
+) -> Result<()> {
+    let skipped = io::copy(&mut (&mut reader).take(range.offset), &mut io::sink())?;
+    if skipped < range.offset {
+        bail!(
+            "--input-offset {} is past the end of the input ({} bytes)",
+            range.offset,
+            skipped,
+        );
+    }
+    update_limited(hasher, reader, range, range.length, meter)
+}
+
+// Hash the range of a file, using a sub-slice of the mmap when the file is mappable, or seeking
+// past the offset when it isn't.
+pub fn update_file(
+    hasher: &mut blake3::Hasher,
+    path: &Path,
+    range: InputRange,
+    mmap: bool,
+    meter: Option<&Meter>,
+) -> Result<()> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    // Block devices report a length of 0 in their metadata, but seeking to the end finds it.
+    let size = if metadata.is_file() {
+        Some(metadata.len())
+    } else {
+        file.seek(SeekFrom::End(0)).ok()
+    };
+    let Some(size) = size else {
+        // Something like a FIFO, which we can only read from the front.
+        return update_reader(hasher, file, range, meter);
+    };
+    let len = range.len_within(size)?;
+    if mmap && metadata.is_file() && len >= MIN_MMAP_LEN {
+        // Map the whole range at once when we can, and in windows when there's progress to report.
+        let window_len = if meter.is_some() || len > isize::MAX as u64 {
+            MMAP_WINDOW_LEN
+        } else {
+            len
+        };
+        update_mmap_rayon(hasher, &file, range.offset, len, window_len, meter)?;
+        return Ok(());
+    }
+    file.seek(SeekFrom::Start(range.offset))?;
+    update_limited(hasher, file, range, Some(len), meter)
+}
+
+// Hash `length` bytes from the reader's current position, which is at `range.offset`, or everything
+// if it's None.
+fn update_limited(
+    hasher: &mut blake3::Hasher,
+    reader: impl Read + Send,
+    range: InputRange,
+    length: Option<u64>,
+    meter: Option<&Meter>,
+) -> Result<()> {
+    let Some(length) = length else {
+        hasher.update_reader_rayon(CountingReader::new(reader, meter))?;
+        return Ok(());
+    };
+    let before = hasher.count();
+    hasher.update_reader_rayon(CountingReader::new(reader.take(length), meter))?;
+    // The input might be shorter than it looked, or it might have shrunk while we read it.
+    let hashed = hasher.count() - before;
+    if hashed < length {
+        // Now that we know the size, report it the same way as we would have up front. If the
+        // range didn't have a length, the file must've shrunk after we checked it.
+        range.len_within(range.offset + hashed)?;
+        bail!("the input got shorter while it was being hashed");
+    }
+    Ok(())
+}
+
+// Map `len` bytes of the file starting at `offset`, one window at a time, and hash them. Each
+// window is counted by the meter as it's hashed.
+pub fn update_mmap_rayon(
+    hasher: &mut blake3::Hasher,
+    file: &File,
+    offset: u64,
+    len: u64,
+    window_len: u64,
+    meter: Option<&Meter>,
+) -> Result<()> {
+    let end = offset + len;
+    let mut offset = offset;
+    while offset < end {
+        // Set the length of each map explicitly, so that the file changing size can't make us
+        // map past the end.
+        let len = std::cmp::min(window_len, end - offset);
+        // SAFETY: If the file changes while it's mapped, we might hash nonsense or crash with
+        // SIGBUS, like any other program that maps files. The map never escapes this function.
+        let map = unsafe {
+            memmap2::MmapOptions::new()
+                .offset(offset)
+                .len(len as usize)
+                .map(file)?
+        };
+        hasher.update_rayon(&map);
+        if let Some(meter) = meter {
+            meter.add_bytes(len);
+        }
+        offset += len;
+    }
+    Ok(())
+}
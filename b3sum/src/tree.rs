@@ -0,0 +1,127 @@
+//! The --tree-hash mode, which hashes a whole directory into a single value. The encoding is
+//! documented in tree_hash.md, and it needs to stay stable, because people store these hashes.
+
+use anyhow::{anyhow, bail, Result};
+use rayon::prelude::*;
+use std::fs;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+const FILE_TAG: u8 = b'f';
+const EXECUTABLE_TAG: u8 = b'x';
+const DIRECTORY_TAG: u8 = b'd';
+const SYMLINK_TAG: u8 = b'l';
+
+// Returns the hasher for the root directory listing, so that the caller can use --length and
+// --seek with it like with any other input.
+pub fn hash_tree(args: &crate::Args, path: &Path) -> Result<Zeroizing<blake3::Hasher>> {
+    if path == Path::new("-") {
+        bail!("Cannot hash standard input with --tree-hash");
+    }
+    if !fs::metadata(path)?.is_dir() {
+        bail!("Not a directory");
+    }
+    hash_directory(args, path)
+}
+
+fn hash_directory(args: &crate::Args, path: &Path) -> Result<Zeroizing<blake3::Hasher>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path).map_err(with_path(path))? {
+        let entry = entry.map_err(with_path(path))?;
+        let name = entry.file_name();
+        if name.to_str().is_none() {
+            // Lossy conversion could make two different names look the same.
+            bail!("Non-UTF-8 filename in {}", path.display());
+        }
+        let escaped = crate::filepath_to_string(Path::new(&name)).filepath_string;
+        entries.push((escaped, entry.path()));
+    }
+    // Sort by the bytes of the escaped names, which is the same on every platform.
+    entries.sort_unstable_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+    // Encode the entries in parallel, but keep them in sorted order, so that the result doesn't
+    // depend on --num-threads.
+    let records = entries
+        .par_iter()
+        .map(|(name, path)| encode_entry(args, name, path))
+        .collect::<Result<Vec<Vec<u8>>>>()?;
+    let mut hasher = args.base_hasher.clone();
+    for record in &records {
+        hasher.update(record);
+    }
+    Ok(hasher)
+}
+
+// Each record is a one-byte tag, the escaped name, and then a payload that depends on the tag.
+fn encode_entry(args: &crate::Args, name: &str, path: &Path) -> Result<Vec<u8>> {
+    let metadata = if args.tree_symlinks() {
+        fs::symlink_metadata(path)
+    } else {
+        fs::metadata(path)
+    }
+    .map_err(with_path(path))?;
+    let mut payload = Vec::new();
+    let tag = if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path).map_err(with_path(path))?;
+        if target.to_str().is_none() {
+            bail!("{}: Non-UTF-8 symlink target", path.display());
+        }
+        push_string(
+            &mut payload,
+            &crate::filepath_to_string(&target).filepath_string,
+        );
+        SYMLINK_TAG
+    } else if metadata.is_dir() {
+        if !args.tree_symlinks()
+            && fs::symlink_metadata(path)
+                .map_err(with_path(path))?
+                .file_type()
+                .is_symlink()
+        {
+            // Following these could loop forever.
+            bail!(
+                "{}: Symlink to a directory (use --tree-symlinks to include it as a symlink)",
+                path.display(),
+            );
+        }
+        payload.extend_from_slice(hash_directory(args, path)?.finalize().as_bytes());
+        DIRECTORY_TAG
+    } else if metadata.is_file() {
+        let hash = crate::hash_file(args, path).map_err(with_path(path))?;
+        payload.extend_from_slice(hash.finalize().as_bytes());
+        if args.tree_exec() && is_executable(&metadata) {
+            EXECUTABLE_TAG
+        } else {
+            FILE_TAG
+        }
+    } else {
+        bail!("{}: Unsupported file type", path.display());
+    };
+    let mut record = vec![tag];
+    push_string(&mut record, name);
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+// The top level prints the path it was given, but errors deeper in the tree need to say where
+// they happened. (Adding anyhow context would hide the cause, because that's printed with {}.)
+fn with_path<E: std::fmt::Display>(path: &Path) -> impl FnOnce(E) -> anyhow::Error + '_ {
+    move |e| anyhow!("{}: {}", path.display(), e)
+}
+
+// Strings are prefixed with their length in bytes, as a little-endian u64.
+fn push_string(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
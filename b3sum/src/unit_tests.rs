@@ -11,12 +11,12 @@ fn test_parse_check_line() {
         file_string,
         is_escaped,
         file_path,
-        expected_hash,
+        expected_output,
     } = crate::parse_check_line(
         "0909090909090909090909090909090909090909090909090909090909090909  foo",
     )
     .unwrap();
-    assert_eq!(expected_hash, blake3::Hash::from([0x09; 32]));
+    assert_eq!(expected_output, [0x09; 32]);
     assert!(!is_escaped);
     assert_eq!(file_string, "foo");
     assert_eq!(file_path, Path::new("foo"));
@@ -26,12 +26,12 @@ fn test_parse_check_line() {
         file_string,
         is_escaped,
         file_path,
-        expected_hash,
+        expected_output,
     } = crate::parse_check_line(
         "fafafafafafafafafafafafafafafafafafafafafafafafafafafafafafafafa  fo \to\n\n\n",
     )
     .unwrap();
-    assert_eq!(expected_hash, blake3::Hash::from([0xfa; 32]));
+    assert_eq!(expected_output, [0xfa; 32]);
     assert!(!is_escaped);
     assert_eq!(file_string, "fo \to");
     assert_eq!(file_path, Path::new("fo \to"));
@@ -41,12 +41,12 @@ fn test_parse_check_line() {
         file_string,
         is_escaped,
         file_path,
-        expected_hash,
+        expected_output,
     } = crate::parse_check_line(
         "4242424242424242424242424242424242424242424242424242424242424242   ",
     )
     .unwrap();
-    assert_eq!(expected_hash, blake3::Hash::from([0x42; 32]));
+    assert_eq!(expected_output, [0x42; 32]);
     assert!(!is_escaped);
     assert_eq!(file_string, " ");
     assert_eq!(file_path, Path::new(" "));
@@ -60,12 +60,12 @@ fn test_parse_check_line() {
             file_string,
             is_escaped,
             file_path,
-            expected_hash,
+            expected_output,
         } = crate::parse_check_line(
             "4343434343434343434343434343434343434343434343434343434343434343  fo\\a\\no",
         )
         .unwrap();
-        assert_eq!(expected_hash, blake3::Hash::from([0x43; 32]));
+        assert_eq!(expected_output, [0x43; 32]);
         assert!(!is_escaped);
         assert_eq!(file_string, "fo\\a\\no");
         assert_eq!(file_path, Path::new("fo\\a\\no"));
@@ -76,12 +76,12 @@ fn test_parse_check_line() {
         file_string,
         is_escaped,
         file_path,
-        expected_hash,
+        expected_output,
     } = crate::parse_check_line(
         "\\4444444444444444444444444444444444444444444444444444444444444444  fo\\n\\no",
     )
     .unwrap();
-    assert_eq!(expected_hash, blake3::Hash::from([0x44; 32]));
+    assert_eq!(expected_output, [0x44; 32]);
     assert!(is_escaped);
     assert_eq!(file_string, "fo\\n\\no");
     assert_eq!(file_path, Path::new("fo\n\no"));
@@ -93,12 +93,12 @@ fn test_parse_check_line() {
             file_string,
             is_escaped,
             file_path,
-            expected_hash,
+            expected_output,
         } = crate::parse_check_line(
             "\\4545454545454545454545454545454545454545454545454545454545454545  fo\\n\\\\o",
         )
         .unwrap();
-        assert_eq!(expected_hash, blake3::Hash::from([0x45; 32]));
+        assert_eq!(expected_output, [0x45; 32]);
         assert!(is_escaped);
         assert_eq!(file_string, "fo\\n\\\\o");
         assert_eq!(file_path, Path::new("fo\n\\o"));
@@ -109,12 +109,12 @@ fn test_parse_check_line() {
         file_string,
         is_escaped,
         file_path,
-        expected_hash,
+        expected_output,
     } = crate::parse_check_line(
         "4646464646464646464646464646464646464646464646464646464646464646  否认",
     )
     .unwrap();
-    assert_eq!(expected_hash, blake3::Hash::from([0x46; 32]));
+    assert_eq!(expected_output, [0x46; 32]);
     assert!(!is_escaped);
     assert_eq!(file_string, "否认");
     assert_eq!(file_path, Path::new("否认"));
@@ -187,3 +187,140 @@ fn test_parse_check_line() {
         .unwrap_err();
     }
 }
+
+#[test]
+fn test_parse_tagged_check_line() {
+    // the basic case
+    let crate::ParsedCheckLine {
+        file_string,
+        is_escaped,
+        file_path,
+        expected_output,
+    } = crate::parse_check_line(
+        "BLAKE3 (foo) = 0909090909090909090909090909090909090909090909090909090909090909\n",
+    )
+    .unwrap();
+    assert_eq!(expected_output, [0x09; 32]);
+    assert!(!is_escaped);
+    assert_eq!(file_string, "foo");
+    assert_eq!(file_path, Path::new("foo"));
+
+    // a path containing the separators
+    let crate::ParsedCheckLine {
+        file_path,
+        expected_output,
+        ..
+    } = crate::parse_check_line(
+        "BLAKE3 (a (b) = c) = 0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a",
+    )
+    .unwrap();
+    assert_eq!(expected_output, [0x0a; 32]);
+    assert_eq!(file_path, Path::new("a (b) = c"));
+
+    // escaped
+    let crate::ParsedCheckLine {
+        file_string,
+        is_escaped,
+        file_path,
+        ..
+    } = crate::parse_check_line(
+        "\\BLAKE3 (fo\\no) = 0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+    )
+    .unwrap();
+    assert!(is_escaped);
+    assert_eq!(file_string, "fo\\no");
+    assert_eq!(file_path, Path::new("fo\no"));
+
+    // non-default lengths
+    let crate::ParsedCheckLine {
+        expected_output, ..
+    } = crate::parse_check_line("BLAKE3-64 (foo) = 0c0c0c0c0c0c0c0c").unwrap();
+    assert_eq!(expected_output, [0x0c; 8]);
+    let long_line = format!("BLAKE3-512 (foo) = {}", "0d".repeat(64));
+    let crate::ParsedCheckLine {
+        expected_output, ..
+    } = crate::parse_check_line(&long_line).unwrap();
+    assert_eq!(expected_output, [0x0d; 64]);
+
+    // An untagged line whose path looks like a tag is still untagged.
+    let crate::ParsedCheckLine { file_path, .. } = crate::parse_check_line(
+        "0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e  BLAKE3 (foo) = 00",
+    )
+    .unwrap();
+    assert_eq!(file_path, Path::new("BLAKE3 (foo) = 00"));
+
+    // ===== Failure Cases =====
+
+    let hex = "00".repeat(32);
+    for line in [
+        // other algorithms
+        format!("SHA256 (foo) = {}", hex),
+        format!("BLAKE2b (foo) = {}", hex),
+        // wrong lengths
+        "BLAKE3 (foo) = 0000".to_string(),
+        format!("BLAKE3-128 (foo) = {}", hex),
+        format!("BLAKE3-0 (foo) = {}", hex),
+        format!("BLAKE3-0256 (foo) = {}", hex),
+        format!("BLAKE3-255 (foo) = {}", hex),
+        format!("BLAKE3-x (foo) = {}", hex),
+        // malformed
+        format!("BLAKE3 (foo) {}", hex),
+        format!("BLAKE3 () = {}", hex),
+        format!("BLAKE3 (foo) = {}", "AA".repeat(32)),
+        format!("BLAKE3 (fo\\o) = {}", hex).replace("BLAKE3", "\\BLAKE3"),
+    ] {
+        crate::parse_check_line(&line).unwrap_err();
+    }
+}
+
+#[test]
+fn test_output_template() {
+    let record = crate::output::Record {
+        path: Some(Path::new("a\\b\nc")),
+        hash: Some("00ff".to_string()),
+        size: Some(3),
+        length: 2,
+        seek: 0,
+        mode: "hash",
+        status: None,
+        error: None,
+    };
+    let template: crate::output::Template = "{hash} {size} {path} {status}{{x}}".parse().unwrap();
+    assert_eq!("00ff 3 a\\\\b\\nc {x}", template.render(&record));
+    assert_eq!(
+        r#"{"path":"a\\b\nc","hash":"00ff","size":3,"length":2,"seek":0,"mode":"hash","error":null}"#,
+        record.to_json(),
+    );
+
+    // ===== Failure Cases =====
+
+    for template in ["{hash", "hash}", "{}", "{bogus}", "{{hash}"] {
+        template.parse::<crate::output::Template>().unwrap_err();
+    }
+}
+
+#[test]
+fn test_parse_check_line_lengths() {
+    // Untagged lines can have any nonzero number of hash bytes.
+    for len in [1, 16, 32, 64, 100] {
+        let line = format!("{}  foo", "ab".repeat(len));
+        let crate::ParsedCheckLine {
+            file_path,
+            expected_output,
+            ..
+        } = crate::parse_check_line(&line).unwrap();
+        assert_eq!(file_path, Path::new("foo"));
+        assert_eq!(expected_output, vec![0xab; len]);
+    }
+
+    // The path starts after the first two spaces, even if it contains more.
+    let crate::ParsedCheckLine { file_path, .. } =
+        crate::parse_check_line("abab  foo  bar").unwrap();
+    assert_eq!(file_path, Path::new("foo  bar"));
+
+    // ===== Failure Cases =====
+
+    for line in ["  foo", "aba  foo", "ab ab  foo", "abab foo  bar"] {
+        crate::parse_check_line(line).unwrap_err();
+    }
+}
@@ -0,0 +1,156 @@
+//! The --recursive directory walk. The order of the output only depends on the names of the files,
+//! so that running the same command twice gives the same output.
+
+use crate::{Args, SymlinkPolicy};
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct Walker<'a> {
+    args: &'a Args,
+    root: &'a Path,
+    #[cfg(unix)]
+    root_device: Option<u64>,
+    // The canonical paths of the directories we're currently inside of, when following symlinks.
+    ancestors: Vec<PathBuf>,
+}
+
+// Calls `visit` with Ok for each file to hash, and with Err for each entry that couldn't be read.
+pub fn walk(args: &Args, root: &Path, visit: &mut dyn FnMut(&Path, Result<()>)) {
+    if cfg!(not(unix)) && args.one_file_system() {
+        visit(
+            root,
+            Err(anyhow!("--one-file-system is only supported on Unix")),
+        );
+        return;
+    }
+    let mut walker = Walker {
+        args,
+        root,
+        #[cfg(unix)]
+        root_device: None,
+        ancestors: Vec::new(),
+    };
+    #[cfg(unix)]
+    if args.one_file_system() {
+        use std::os::unix::fs::MetadataExt;
+        match fs::metadata(root) {
+            Ok(metadata) => walker.root_device = Some(metadata.dev()),
+            Err(e) => return visit(root, Err(e.into())),
+        }
+    }
+    walker.walk_dir(root, visit);
+}
+
+impl Walker<'_> {
+    fn walk_dir(&mut self, dir: &Path, visit: &mut dyn FnMut(&Path, Result<()>)) {
+        if self.args.symlinks() == SymlinkPolicy::Follow {
+            // A symlink to one of our own ancestors would send us around in circles.
+            let canonical = match fs::canonicalize(dir) {
+                Ok(canonical) => canonical,
+                Err(e) => return visit(dir, Err(e.into())),
+            };
+            if self.ancestors.contains(&canonical) {
+                return visit(dir, Err(anyhow!("Symlink loop")));
+            }
+            self.ancestors.push(canonical);
+        }
+        match sorted_entries(dir) {
+            Ok(entries) => {
+                for path in entries {
+                    self.walk_entry(&path, visit);
+                }
+            }
+            Err(e) => visit(dir, Err(e.into())),
+        }
+        if self.args.symlinks() == SymlinkPolicy::Follow {
+            self.ancestors.pop();
+        }
+    }
+
+    fn walk_entry(&mut self, path: &Path, visit: &mut dyn FnMut(&Path, Result<()>)) {
+        let mut metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) => return visit(path, Err(e.into())),
+        };
+        if metadata.file_type().is_symlink() {
+            match self.args.symlinks() {
+                SymlinkPolicy::Skip => return,
+                // hash_file() hashes the target path.
+                SymlinkPolicy::Target => {
+                    if self.should_hash(path) {
+                        visit(path, Ok(()));
+                    }
+                    return;
+                }
+                SymlinkPolicy::Follow => match fs::metadata(path) {
+                    Ok(target_metadata) => metadata = target_metadata,
+                    Err(e) => return visit(path, Err(e.into())),
+                },
+            }
+        }
+        if metadata.is_dir() {
+            if self.is_excluded(path) || !self.same_file_system(&metadata) {
+                return;
+            }
+            self.walk_dir(path, visit);
+        } else if metadata.is_file() && self.should_hash(path) {
+            visit(path, Ok(()));
+        }
+    }
+
+    fn should_hash(&self, path: &Path) -> bool {
+        !self.is_excluded(path)
+            && (self.args.include().is_empty()
+                || self.args.include().iter().any(|p| self.matches(p, path)))
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.args.exclude().iter().any(|p| self.matches(p, path))
+    }
+
+    fn matches(&self, pattern: &glob::Pattern, path: &Path) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        if pattern.as_str().contains('/') {
+            let relative = path.strip_prefix(self.root).unwrap_or(path);
+            let mut relative = relative.to_string_lossy().into_owned();
+            if cfg!(windows) {
+                relative = relative.replace('\\', "/");
+            }
+            pattern.matches_with(&relative, options)
+        } else {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            pattern.matches_with(&name, options)
+        }
+    }
+
+    #[cfg(unix)]
+    fn same_file_system(&self, metadata: &fs::Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        self.root_device.is_none() || self.root_device == Some(metadata.dev())
+    }
+
+    #[cfg(not(unix))]
+    fn same_file_system(&self, _metadata: &fs::Metadata) -> bool {
+        true
+    }
+}
+
+// Sort by the names as b3sum prints them, and then by the raw names, in case two different raw
+// names print the same way.
+fn sorted_entries(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let printed = crate::filepath_to_string(Path::new(&name)).filepath_string;
+        entries.push((printed, name));
+    }
+    entries.sort_unstable();
+    Ok(entries
+        .into_iter()
+        .map(|(_, name)| dir.join(name))
+        .collect())
+}
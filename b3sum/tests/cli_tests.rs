@@ -126,6 +126,21 @@ fn test_hash_length_and_seek() {
     assert_eq!(expected[99..], seek99_output);
 }
 
+#[test]
+fn test_hex_length_and_unaligned_seek() {
+    // Long enough to span several multi-block reads, starting partway into a block.
+    let mut expected = [0; 3000];
+    blake3::Hasher::new()
+        .update(b"foo")
+        .finalize_xof()
+        .fill(&mut expected);
+    let output = cmd!(b3sum_exe(), "--no-names", "--length=2900", "--seek=77", "-")
+        .stdin_bytes("foo")
+        .read()
+        .unwrap();
+    assert_eq!(hex::encode(&expected[77..2977]), output);
+}
+
 #[test]
 fn test_keyed() {
     let key = [42; blake3::KEY_LEN];
@@ -157,6 +172,56 @@ fn test_keyed() {
     }
 }
 
+#[test]
+fn test_key_sources() {
+    let key = [42; blake3::KEY_LEN];
+    let expected = blake3::keyed_hash(&key, b"foo").to_hex();
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("raw"), key).unwrap();
+    fs::write(dir.path().join("hex"), format!("{}\n", hex::encode(key))).unwrap();
+
+    // With the key coming from somewhere else, the input can come from stdin.
+    for key_file in ["raw", "hex"] {
+        let output = cmd!(b3sum_exe(), "--keyed", "--key-file", key_file)
+            .dir(dir.path())
+            .stdin_bytes("foo")
+            .read()
+            .unwrap();
+        assert_eq!(format!("{}  -", expected), output);
+    }
+    let output = cmd!(b3sum_exe(), "--keyed", "--key-hex-env=B3SUM_TEST_KEY", "-")
+        .env("B3SUM_TEST_KEY", hex::encode(key))
+        .stdin_bytes("foo")
+        .read()
+        .unwrap();
+    assert_eq!(format!("{}  -", expected), output);
+
+    // Bad keys, conflicting sources, and sources without --keyed are errors.
+    fs::write(dir.path().join("short"), "2a2a").unwrap();
+    fs::write(dir.path().join("nothex"), "zz".repeat(blake3::KEY_LEN)).unwrap();
+    for args in [
+        &["--keyed", "--key-file=short"][..],
+        &["--keyed", "--key-file=nothex"],
+        &["--keyed", "--key-file=missing"],
+        &["--keyed", "--key-hex-env=B3SUM_TEST_UNSET"],
+        &["--keyed", "--key-file=raw", "--key-hex-env=B3SUM_TEST_KEY"],
+        &["--key-file=raw"],
+    ] {
+        let output = cmd(b3sum_exe(), args)
+            .dir(dir.path())
+            .env("B3SUM_TEST_KEY", hex::encode(key))
+            .env_remove("B3SUM_TEST_UNSET")
+            .stdin_bytes("foo")
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap();
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(output.stdout.is_empty());
+    }
+}
+
 #[test]
 fn test_derive_key() {
     let context = "BLAKE3 2019-12-28 10:28:41 example context";
@@ -183,6 +248,57 @@ fn test_no_mmap() {
     assert_eq!(&*expected, &*output);
 }
 
+#[test]
+fn test_multithreaded_reader() {
+    // Long enough to fill several of update_reader_rayon's buffers, with a partial one at the end.
+    let input: Vec<u8> = (0..5_000_000u32).map(|i| (i % 251) as u8).collect();
+    let expected = blake3::hash(&input).to_hex();
+
+    let output = cmd!(b3sum_exe(), "--no-names", "--num-threads=4")
+        .stdin_bytes(&input[..])
+        .read()
+        .unwrap();
+    assert_eq!(&*expected, &*output);
+
+    let f = tempfile::NamedTempFile::new().unwrap();
+    f.as_file().write_all(&input).unwrap();
+    f.as_file().flush().unwrap();
+    let output = cmd!(b3sum_exe(), "--no-mmap", "--no-names", f.path())
+        .read()
+        .unwrap();
+    assert_eq!(&*expected, &*output);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_direct_io() {
+    // Long enough to fill several of update_file_direct's buffers, with a partial one at the end.
+    let input: Vec<u8> = (0..5_000_000u32).map(|i| (i % 251) as u8).collect();
+    let expected = blake3::hash(&input).to_hex();
+
+    let f = tempfile::NamedTempFile::new().unwrap();
+    f.as_file().write_all(&input).unwrap();
+    f.as_file().flush().unwrap();
+    let output = cmd!(b3sum_exe(), "--direct-io", "--no-names", f.path())
+        .read()
+        .unwrap();
+    assert_eq!(&*expected, &*output);
+
+    // Standard input is read normally.
+    let output = cmd!(b3sum_exe(), "--direct-io", "--no-names")
+        .stdin_bytes(&input[..])
+        .read()
+        .unwrap();
+    assert_eq!(&*expected, &*output);
+
+    let result = cmd!(b3sum_exe(), "--direct-io", "--no-mmap", f.path())
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!result.status.success());
+}
+
 #[test]
 fn test_length_without_value_is_an_error() {
     let result = cmd!(b3sum_exe(), "--length")
@@ -476,12 +592,9 @@ fn test_check() {
          c/d: OK\n",
         open_file_error,
     );
-    assert!(!output.status.success());
+    assert_eq!(Some(3), output.status.code());
     assert_eq!(expected_check_failure, stdout);
-    assert_eq!(
-        "b3sum: WARNING: 1 computed checksum did NOT match\n",
-        stderr,
-    );
+    assert_eq!("b3sum: WARNING: 1 listed file could not be read\n", stderr);
 
     // Confirm that --quiet suppresses the OKs but not the FAILEDs.
     let output = cmd!(b3sum_exe(), "--check", "--quiet", &checkfile_path)
@@ -494,12 +607,9 @@ fn test_check() {
     let stdout = std::str::from_utf8(&output.stdout).unwrap();
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
     let expected_check_failure = format!("b: FAILED ({})\n", open_file_error);
-    assert!(!output.status.success());
+    assert_eq!(Some(3), output.status.code());
     assert_eq!(expected_check_failure, stdout);
-    assert_eq!(
-        "b3sum: WARNING: 1 computed checksum did NOT match\n",
-        stderr,
-    );
+    assert_eq!("b3sum: WARNING: 1 listed file could not be read\n", stderr);
 }
 
 #[test]
@@ -516,8 +626,8 @@ fn test_check_invalid_characters() {
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
     let expected_stderr = "\
         b3sum: Null character in path\n\
-        b3sum: WARNING: 1 computed checksum did NOT match\n";
-    assert!(!output.status.success());
+        b3sum: WARNING: 1 listed path was rejected\n";
+    assert_eq!(Some(4), output.status.code());
     assert_eq!("", stdout);
     assert_eq!(expected_stderr, stderr);
 
@@ -533,8 +643,8 @@ fn test_check_invalid_characters() {
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
     let expected_stderr = "\
         b3sum: Unicode replacement character in path\n\
-        b3sum: WARNING: 1 computed checksum did NOT match\n";
-    assert!(!output.status.success());
+        b3sum: WARNING: 1 listed path was rejected\n";
+    assert_eq!(Some(4), output.status.code());
     assert_eq!("", stdout);
     assert_eq!(expected_stderr, stderr);
 
@@ -550,8 +660,8 @@ fn test_check_invalid_characters() {
     let stderr = std::str::from_utf8(&output.stderr).unwrap();
     let expected_stderr = "\
         b3sum: Invalid backslash escape\n\
-        b3sum: WARNING: 1 computed checksum did NOT match\n";
-    assert!(!output.status.success());
+        b3sum: WARNING: 1 listed path was rejected\n";
+    assert_eq!(Some(4), output.status.code());
     assert_eq!("", stdout);
     assert_eq!(expected_stderr, stderr);
 
@@ -569,8 +679,8 @@ fn test_check_invalid_characters() {
         let stderr = std::str::from_utf8(&output.stderr).unwrap();
         let expected_stderr = "\
             b3sum: Backslash in path\n\
-            b3sum: WARNING: 1 computed checksum did NOT match\n";
-        assert!(!output.status.success());
+            b3sum: WARNING: 1 listed path was rejected\n";
+        assert_eq!(Some(4), output.status.code());
         assert_eq!("", stdout);
         assert_eq!(expected_stderr, stderr);
     }
@@ -611,3 +721,1134 @@ fn test_globbing() {
         .unwrap();
     assert_eq!(expected, output);
 }
+
+// Encode one record of a tree hash listing, following tree_hash.md.
+fn tree_record(tag: u8, name: &str, payload: &[u8]) -> Vec<u8> {
+    let mut record = vec![tag];
+    record.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    record.extend_from_slice(name.as_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+#[test]
+fn test_tree_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let top = dir.path().join("top");
+    fs::create_dir_all(top.join("sub").join("empty")).unwrap();
+    fs::write(top.join("b"), b"bar").unwrap();
+    fs::write(top.join("a\nb"), b"newline").unwrap();
+    fs::write(top.join("sub").join("c"), b"baz").unwrap();
+
+    let empty = blake3::hash(b"");
+    let mut sub_listing = tree_record(b'f', "c", blake3::hash(b"baz").as_bytes());
+    sub_listing.extend(tree_record(b'd', "empty", empty.as_bytes()));
+    // "a\\nb" sorts before "b", which sorts before "sub".
+    let mut listing = tree_record(b'f', "a\\nb", blake3::hash(b"newline").as_bytes());
+    listing.extend(tree_record(b'f', "b", blake3::hash(b"bar").as_bytes()));
+    listing.extend(tree_record(
+        b'd',
+        "sub",
+        blake3::hash(&sub_listing).as_bytes(),
+    ));
+    let expected = blake3::hash(&listing);
+
+    for threads in ["1", "2", "8"] {
+        let output = cmd!(b3sum_exe(), "--tree-hash", "--num-threads", threads, &top)
+            .read()
+            .unwrap();
+        assert_eq!(format!("{}  {}", expected, top.to_string_lossy()), output);
+    }
+
+    // --length extends the top level hash.
+    let output = cmd!(
+        b3sum_exe(),
+        "--tree-hash",
+        "--no-names",
+        "--length=100",
+        &top
+    )
+    .read()
+    .unwrap();
+    let mut expected_long = [0; 100];
+    blake3::Hasher::new()
+        .update(&listing)
+        .finalize_xof()
+        .fill(&mut expected_long);
+    assert_eq!(hex::encode(expected_long), output);
+
+    // Every hash in the tree uses the keyed mode.
+    let key = [42; blake3::KEY_LEN];
+    let keyed = |input: &[u8]| blake3::keyed_hash(&key, input);
+    let mut sub_listing = tree_record(b'f', "c", keyed(b"baz").as_bytes());
+    sub_listing.extend(tree_record(b'd', "empty", keyed(b"").as_bytes()));
+    let mut listing = tree_record(b'f', "a\\nb", keyed(b"newline").as_bytes());
+    listing.extend(tree_record(b'f', "b", keyed(b"bar").as_bytes()));
+    listing.extend(tree_record(b'd', "sub", keyed(&sub_listing).as_bytes()));
+    let output = cmd!(b3sum_exe(), "--tree-hash", "--keyed", "--no-names", &top)
+        .stdin_bytes(&key[..])
+        .read()
+        .unwrap();
+    assert_eq!(keyed(&listing).to_hex().as_str(), output);
+
+    // Files aren't directories, and the tree options require --tree-hash.
+    for args in [
+        vec!["--tree-hash".into(), top.join("b").into_os_string()],
+        vec!["--tree-exec".into(), top.clone().into_os_string()],
+    ] {
+        let result = cmd(b3sum_exe(), args)
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap();
+        assert!(!result.status.success());
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_tree_hash_exec_and_symlinks() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    let dir = tempfile::tempdir().unwrap();
+    let top = dir.path().join("top");
+    fs::create_dir_all(top.join("sub")).unwrap();
+    fs::write(top.join("script"), b"#!/bin/sh\n").unwrap();
+    fs::set_permissions(top.join("script"), fs::Permissions::from_mode(0o755)).unwrap();
+    symlink("script", top.join("link")).unwrap();
+
+    let script_hash = blake3::hash(b"#!/bin/sh\n");
+    let empty = blake3::hash(b"");
+    let tree_hash = |flags: &[&str]| {
+        let mut args: Vec<OsString> = flags.iter().map(Into::into).collect();
+        args.push(top.clone().into());
+        cmd(b3sum_exe(), args).stderr_capture().read()
+    };
+
+    // The executable bit and the symlink are ignored by default, but the
+    // directory symlink is an error.
+    let mut listing = tree_record(b'f', "link", script_hash.as_bytes());
+    listing.extend(tree_record(b'f', "script", script_hash.as_bytes()));
+    listing.extend(tree_record(b'd', "sub", empty.as_bytes()));
+    let output = tree_hash(&["--tree-hash", "--no-names"]).unwrap();
+    assert_eq!(blake3::hash(&listing).to_hex().as_str(), output);
+    symlink("..", top.join("sub").join("up")).unwrap();
+    assert!(tree_hash(&["--tree-hash", "--no-names"]).is_err());
+
+    let mut target = 2u64.to_le_bytes().to_vec();
+    target.extend_from_slice(b"..");
+    let sub_listing = tree_record(b'l', "up", &target);
+    let mut target = 6u64.to_le_bytes().to_vec();
+    target.extend_from_slice(b"script");
+    let mut listing = tree_record(b'l', "link", &target);
+    listing.extend(tree_record(b'x', "script", script_hash.as_bytes()));
+    listing.extend(tree_record(
+        b'd',
+        "sub",
+        blake3::hash(&sub_listing).as_bytes(),
+    ));
+    let output = tree_hash(&[
+        "--tree-hash",
+        "--tree-exec",
+        "--tree-symlinks",
+        "--no-names",
+    ])
+    .unwrap();
+    assert_eq!(blake3::hash(&listing).to_hex().as_str(), output);
+}
+
+#[test]
+fn test_recursive() {
+    let dir = tempfile::tempdir().unwrap();
+    let top = dir.path().join("top");
+    fs::create_dir_all(top.join("sub").join("deeper")).unwrap();
+    fs::create_dir_all(top.join("target")).unwrap();
+    fs::write(top.join("b.txt"), b"b").unwrap();
+    fs::write(top.join("a.rs"), b"a").unwrap();
+    fs::write(top.join("sub").join("c.txt"), b"c").unwrap();
+    fs::write(top.join("sub").join("deeper").join("d.txt"), b"d").unwrap();
+    fs::write(top.join("target").join("e.txt"), b"e").unwrap();
+
+    let b3sum = |args: &[&str]| {
+        let mut args: Vec<OsString> = args.iter().map(Into::into).collect();
+        args.push("top".into());
+        cmd(b3sum_exe(), args).dir(dir.path()).read().unwrap()
+    };
+    let line = |input: &[u8], path: &str| format!("{}  {}", blake3::hash(input), path);
+    let expected = [
+        line(b"a", "top/a.rs"),
+        line(b"b", "top/b.txt"),
+        line(b"c", "top/sub/c.txt"),
+        line(b"d", "top/sub/deeper/d.txt"),
+        line(b"e", "top/target/e.txt"),
+    ]
+    .join("\n");
+    let output = b3sum(&["--recursive"]);
+    assert_eq!(expected, output.replace('\\', "/"));
+
+    // The output can be checked as usual.
+    let checkfile = dir.path().join("checkfile");
+    fs::write(&checkfile, format!("{}\n", output)).unwrap();
+    cmd!(b3sum_exe(), "--check", &checkfile)
+        .dir(dir.path())
+        .read()
+        .unwrap();
+
+    // Patterns without a slash match names, and patterns with a slash match
+    // relative paths. Excluding a directory skips everything inside it.
+    let expected = [line(b"b", "top/b.txt"), line(b"c", "top/sub/c.txt")].join("\n");
+    let output = b3sum(&[
+        "-r",
+        "--include=*.txt",
+        "--exclude=target",
+        "--exclude=sub/*/*.txt",
+    ]);
+    assert_eq!(expected, output.replace('\\', "/"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_recursive_symlinks_and_errors() {
+    use std::os::unix::fs::symlink;
+    let dir = tempfile::tempdir().unwrap();
+    let top = dir.path().join("top");
+    fs::create_dir_all(top.join("sub")).unwrap();
+    fs::write(top.join("file"), b"foo").unwrap();
+    symlink("file", top.join("link")).unwrap();
+    symlink("missing", top.join("broken")).unwrap();
+    symlink("..", top.join("sub").join("loop")).unwrap();
+
+    let run = |policy: &str| {
+        cmd!(b3sum_exe(), "-r", "--symlinks", policy, "top")
+            .dir(dir.path())
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap()
+    };
+    let foo_hash = blake3::hash(b"foo");
+
+    // Following symlinks reports the broken link and the loop, and keeps going.
+    let output = run("follow");
+    assert!(!output.status.success());
+    let expected = format!("{}  top/file\n{}  top/link\n", foo_hash, foo_hash);
+    assert_eq!(expected.as_bytes(), &output.stdout[..]);
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("top/broken"), "{}", stderr);
+    assert!(stderr.contains("top/sub/loop: Symlink loop"), "{}", stderr);
+
+    let output = run("skip");
+    assert!(output.status.success());
+    assert_eq!(
+        format!("{}  top/file\n", foo_hash).as_bytes(),
+        &output.stdout[..]
+    );
+
+    // Without --recursive, "skip" still follows FILE arguments, and "target"
+    // still hashes their target paths.
+    let output = cmd!(b3sum_exe(), "--symlinks=skip", "--no-names", "top/link")
+        .dir(dir.path())
+        .read()
+        .unwrap();
+    assert_eq!(foo_hash.to_hex().as_str(), output);
+    let output = cmd!(b3sum_exe(), "--symlinks=target", "--no-names", "top/link")
+        .dir(dir.path())
+        .read()
+        .unwrap();
+    assert_eq!(blake3::hash(b"file").to_hex().as_str(), output);
+
+    // With "target", the link target paths are hashed, and checking requires
+    // the same policy.
+    let output = run("target");
+    assert!(output.status.success());
+    let expected = format!(
+        "{}  top/broken\n{}  top/file\n{}  top/link\n{}  top/sub/loop\n",
+        blake3::hash(b"missing"),
+        foo_hash,
+        blake3::hash(b"file"),
+        blake3::hash(b".."),
+    );
+    assert_eq!(expected.as_bytes(), &output.stdout[..]);
+    let checkfile = dir.path().join("checkfile");
+    fs::write(&checkfile, &output.stdout).unwrap();
+    cmd!(b3sum_exe(), "--check", "--symlinks=target", &checkfile)
+        .dir(dir.path())
+        .read()
+        .unwrap();
+    let result = cmd!(b3sum_exe(), "--check", &checkfile)
+        .dir(dir.path())
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!result.status.success());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_one_file_system() {
+    use std::os::unix::fs::{symlink, MetadataExt};
+    let dir = tempfile::tempdir().unwrap();
+    // tmpfs is usually a separate filesystem. If not, there's nothing to test.
+    let other = match tempfile::tempdir_in("/dev/shm") {
+        Ok(other) => other,
+        Err(_) => return,
+    };
+    let device = |path: &std::path::Path| fs::metadata(path).unwrap().dev();
+    if device(dir.path()) == device(other.path()) {
+        return;
+    }
+    fs::write(dir.path().join("file"), b"foo").unwrap();
+    fs::write(other.path().join("other_file"), b"bar").unwrap();
+    symlink(other.path(), dir.path().join("other")).unwrap();
+
+    let output = cmd!(b3sum_exe(), "-r", "--no-names", dir.path())
+        .read()
+        .unwrap();
+    let expected = format!("{}\n{}", blake3::hash(b"foo"), blake3::hash(b"bar"));
+    assert_eq!(expected, output);
+    let output = cmd!(
+        b3sum_exe(),
+        "-r",
+        "--no-names",
+        "--one-file-system",
+        dir.path()
+    )
+    .read()
+    .unwrap();
+    assert_eq!(blake3::hash(b"foo").to_hex().as_str(), output);
+}
+
+#[test]
+fn test_check_parallel_order() {
+    // Enough lines for several batches at any thread count, with failures and
+    // errors mixed in. The output must be in checkfile order regardless.
+    let dir = tempfile::tempdir().unwrap();
+    let mut checkfile = String::new();
+    let mut expected_stdout = String::new();
+    let mut expected_mismatches = 0;
+    let mut expected_missing = 0;
+    for i in 0..2000 {
+        let name = format!("file{}", i);
+        let contents = format!("contents {}", i);
+        let mut hash = blake3::hash(contents.as_bytes()).to_hex().to_string();
+        if i % 7 == 3 {
+            hash = blake3::hash(b"wrong").to_hex().to_string();
+            expected_stdout += &format!("{}: FAILED\n", name);
+            expected_mismatches += 1;
+        } else if i % 11 == 5 {
+            // Missing files are a FAILED line with the error.
+            expected_stdout += &format!("{}: FAILED (\n", name);
+            expected_missing += 1;
+        } else {
+            expected_stdout += &format!("{}: OK\n", name);
+        }
+        if i % 11 != 5 || i % 7 == 3 {
+            fs::write(dir.path().join(&name), &contents).unwrap();
+        }
+        checkfile += &format!("{}  {}\n", hash, name);
+    }
+    fs::write(dir.path().join("checkfile"), &checkfile).unwrap();
+
+    let mut outputs = Vec::new();
+    for threads in ["1", "3", "16"] {
+        let output = cmd!(
+            b3sum_exe(),
+            "--check",
+            "--num-threads",
+            threads,
+            "checkfile"
+        )
+        .dir(dir.path())
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+        assert!(!output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        // Compare everything but the OS error messages.
+        let lines: Vec<&str> = stdout.lines().collect();
+        let expected_lines: Vec<&str> = expected_stdout.lines().collect();
+        assert_eq!(expected_lines.len(), lines.len());
+        for (expected, found) in expected_lines.iter().zip(&lines) {
+            assert!(found.starts_with(expected), "{} {}", expected, found);
+        }
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let expected_stderr = format!(
+            "b3sum: WARNING: {} listed files could not be read\n\
+             b3sum: WARNING: {} computed checksums did NOT match\n",
+            expected_missing, expected_mismatches,
+        );
+        assert_eq!(expected_stderr, stderr);
+        outputs.push(stdout);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], outputs[2]);
+}
+
+#[test]
+fn test_hash_many_parallel_order() {
+    // Enough files for several batches, with missing files mixed in. Output
+    // and errors must be in argument order, regardless of the thread count.
+    let dir = tempfile::tempdir().unwrap();
+    let mut args: Vec<OsString> = Vec::new();
+    let mut expected = String::new();
+    for i in 0..1000 {
+        let name = format!("file{}", i);
+        if i % 100 != 42 {
+            let contents = format!("contents {}", i);
+            fs::write(dir.path().join(&name), &contents).unwrap();
+            expected += &format!("{}  {}\n", blake3::hash(contents.as_bytes()), name);
+        }
+        args.push(name.into());
+    }
+    for threads in ["1", "3", "16"] {
+        let mut all_args: Vec<OsString> = vec!["--num-threads".into(), threads.into()];
+        all_args.extend(args.iter().cloned());
+        let output = cmd(b3sum_exe(), all_args)
+            .dir(dir.path())
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap();
+        assert!(!output.status.success());
+        assert_eq!(expected.as_bytes(), &output.stdout[..]);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let failed: Vec<&str> = stderr
+            .lines()
+            .map(|line| line.split(": ").nth(1).unwrap())
+            .collect();
+        let expected_failed: Vec<String> =
+            (0..10).map(|i| format!("file{}", 100 * i + 42)).collect();
+        assert_eq!(expected_failed, failed);
+    }
+
+    // Standard input in the middle still comes out in order.
+    let output = cmd!(b3sum_exe(), "file0", "-", "file1")
+        .dir(dir.path())
+        .stdin_bytes("foo")
+        .read()
+        .unwrap();
+    let expected = format!(
+        "{}  file0\n{}  -\n{}  file1",
+        blake3::hash(b"contents 0"),
+        blake3::hash(b"foo"),
+        blake3::hash(b"contents 1"),
+    );
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn test_tag() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo"), b"foo").unwrap();
+    fs::write(dir.path().join("a\nb"), b"bar").unwrap();
+
+    let output = cmd!(b3sum_exe(), "--tag", "foo", "a\nb")
+        .dir(dir.path())
+        .read()
+        .unwrap();
+    let expected = format!(
+        "BLAKE3 (foo) = {}\n\\BLAKE3 (a\\nb) = {}",
+        blake3::hash(b"foo"),
+        blake3::hash(b"bar"),
+    );
+    assert_eq!(expected, output);
+
+    let output = cmd!(b3sum_exe(), "--tag", "--length=64", "foo")
+        .dir(dir.path())
+        .read()
+        .unwrap();
+    let mut long = [0; 64];
+    blake3::Hasher::new()
+        .update(b"foo")
+        .finalize_xof()
+        .fill(&mut long);
+    let long_line = format!("BLAKE3-512 (foo) = {}", hex::encode(long));
+    assert_eq!(long_line, output);
+
+    // A checkfile can mix formats and lengths. Other algorithms are
+    // improperly formatted, unless they're explicitly skipped.
+    let checkfile = format!(
+        "{}\n{}\nSHA256 (foo) = {}\n{}  foo\n",
+        expected,
+        long_line,
+        "00".repeat(32),
+        blake3::hash(b"foo"),
+    );
+    fs::write(dir.path().join("checkfile"), &checkfile).unwrap();
+    let output = cmd!(b3sum_exe(), "--check", "--strict", "--warn", "checkfile")
+        .dir(dir.path())
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.contains("checkfile:4: Unsupported algorithm SHA256"),
+        "{}",
+        stderr
+    );
+    let output = cmd!(
+        b3sum_exe(),
+        "--check",
+        "--ignore-other-algorithms",
+        "checkfile"
+    )
+    .dir(dir.path())
+    .read()
+    .unwrap();
+    assert_eq!("foo: OK\n\\a\\nb: OK\nfoo: OK\nfoo: OK", output);
+
+    // A corrupted long hash fails.
+    let bad_line = long_line.replace("BLAKE3-512 (foo) = ", "BLAKE3-512 (foo) = 00");
+    fs::write(
+        dir.path().join("checkfile"),
+        &bad_line[..bad_line.len() - 2],
+    )
+    .unwrap();
+    let output = cmd!(b3sum_exe(), "--check", "checkfile")
+        .dir(dir.path())
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(b"foo: FAILED\n", &output.stdout[..]);
+}
+
+#[test]
+fn test_json_and_format() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo"), b"foo").unwrap();
+    let hash = blake3::hash(b"foo").to_hex();
+
+    // Errors are reported in the output too, but they still fail the command.
+    let output = cmd!(b3sum_exe(), "--json", "foo", "missing")
+        .dir(dir.path())
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert_eq!(
+        format!(
+            r#"{{"path":"foo","hash":"{}","size":3,"length":32,"seek":0,"mode":"hash","error":null}}"#,
+            hash,
+        ),
+        lines.next().unwrap(),
+    );
+    let missing = lines.next().unwrap();
+    assert!(
+        missing.starts_with(r#"{"path":"missing","hash":null,"size":null,"#),
+        "{}",
+        missing,
+    );
+    assert_eq!(None, lines.next());
+
+    let output = cmd!(
+        b3sum_exe(),
+        "--format={mode} {hash} {size} {path}",
+        "--derive-key=ctx",
+        "--length=4",
+        "foo"
+    )
+    .dir(dir.path())
+    .read()
+    .unwrap();
+    let derived = blake3::derive_key("ctx", b"foo");
+    assert_eq!(
+        format!("derive_key {} 3 foo", hex::encode(&derived[..4])),
+        output,
+    );
+
+    // Errors hashing an input go in the output with --format too.
+    let output = cmd!(
+        b3sum_exe(),
+        "--format={path}:{hash}:{error}",
+        "foo",
+        "missing"
+    )
+    .dir(dir.path())
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!output.status.success());
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(format!("foo:{}:", hash), lines[0]);
+    let missing_error = fs::File::open(dir.path().join("missing")).unwrap_err();
+    assert_eq!(format!("missing::{}", missing_error), lines[1]);
+    assert_eq!(2, lines.len());
+    assert_eq!(b"", &output.stderr[..]);
+
+    // In --check mode, every line gets a status, including lines that can't
+    // be parsed.
+    let checkfile = format!("{}  foo\n{}  missing\ngarbage\n", hash, hash);
+    fs::write(dir.path().join("checkfile"), checkfile).unwrap();
+    let output = cmd!(
+        b3sum_exe(),
+        "--check",
+        "--format={status} {path} {size} {error}",
+        "checkfile"
+    )
+    .dir(dir.path())
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!output.status.success());
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!("OK foo 3 ", lines[0]);
+    assert!(lines[1].starts_with("FAILED missing  "), "{}", lines[1]);
+    assert_eq!("INVALID   Short line", lines[2]);
+    assert_eq!(3, lines.len());
+    // Only the summary goes to stderr.
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    let expected_stderr = "\
+        b3sum: WARNING: 1 line is improperly formatted\n\
+        b3sum: WARNING: 1 listed file could not be read\n";
+    assert_eq!(expected_stderr, stderr);
+
+    let output = cmd!(b3sum_exe(), "--check", "--json", "checkfile")
+        .dir(dir.path())
+        .stdout_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(&format!(
+            r#"{{"path":"foo","status":"OK","hash":"{}","size":3,"#,
+            hash,
+        )),
+        "{}",
+        stdout,
+    );
+    assert_eq!(3, stdout.lines().count());
+
+    // Both styles need to be able to say where a path starts and ends, so
+    // they don't mix with the other output options.
+    for flags in [["--json", "--raw"], ["--format={hash}", "--tag"]] {
+        cmd!(b3sum_exe(), flags[0], flags[1], "foo")
+            .dir(dir.path())
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .map(|output| assert!(!output.status.success()))
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_progress_and_stats() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo"), b"foo").unwrap();
+    // Long enough to be mapped.
+    fs::write(dir.path().join("bar"), vec![0xab; 100_000]).unwrap();
+
+    // Everything goes to stderr, so stdout doesn't change.
+    let plain = cmd!(b3sum_exe(), "foo", "bar", "missing")
+        .dir(dir.path())
+        .stdout_capture()
+        .stderr_null()
+        .unchecked()
+        .run()
+        .unwrap();
+    for flags in [
+        &["--progress"][..],
+        &["--stats"],
+        &["--progress", "--stats", "--no-mmap"],
+    ] {
+        let output = cmd(b3sum_exe(), flags.iter().chain(&["foo", "bar", "missing"]))
+            .dir(dir.path())
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap();
+        assert_eq!(plain.status.code(), output.status.code());
+        assert_eq!(plain.stdout, output.stdout);
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert!(stderr.contains("missing: "), "{}", stderr);
+        if flags.contains(&"--stats") {
+            let summary = stderr.lines().last().unwrap();
+            assert!(
+                summary.starts_with("b3sum: 2 files, 97.66 KiB (100003 bytes) in "),
+                "{}",
+                summary,
+            );
+        }
+    }
+
+    let checkfile = cmd!(b3sum_exe(), "foo", "bar")
+        .dir(dir.path())
+        .read()
+        .unwrap();
+    let output = cmd!(b3sum_exe(), "--check", "--stats", "-")
+        .dir(dir.path())
+        .stdin_bytes(checkfile)
+        .stdout_capture()
+        .stderr_capture()
+        .run()
+        .unwrap();
+    assert_eq!(b"foo: OK\nbar: OK\n", &output.stdout[..]);
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(stderr.contains("2 files, 97.66 KiB"), "{}", stderr);
+}
+
+#[test]
+fn test_check_policies() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub/foo"), b"foo").unwrap();
+    let foo_hash = blake3::hash(b"foo");
+    let check = |checkfile: &str, flags: &[&str]| {
+        fs::write(dir.path().join("sub/checkfile"), checkfile).unwrap();
+        let output = cmd(b3sum_exe(), flags.iter().chain(&["--check", "checkfile"]))
+            .dir(dir.path().join("sub"))
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap();
+        (
+            output.status.code().unwrap(),
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    // Missing files fail with their own exit code, unless they're ignored.
+    let missing = format!("{}  foo\n{}  missing\n", foo_hash, foo_hash);
+    let (code, stdout, _) = check(&missing, &[]);
+    assert_eq!(3, code);
+    assert!(
+        stdout.starts_with("foo: OK\nmissing: FAILED ("),
+        "{}",
+        stdout
+    );
+    assert_eq!(
+        (0, "foo: OK\n".into(), "".into()),
+        check(&missing, &["--ignore-missing"])
+    );
+
+    // Improperly formatted lines are only counted by default. --warn reports
+    // each one with its line number, and --strict makes them fail.
+    let malformed = format!("{}  foo\ngarbage\n\n", foo_hash);
+    let summary = "b3sum: WARNING: 2 lines are improperly formatted\n";
+    assert_eq!(
+        (0, "foo: OK\n".into(), summary.into()),
+        check(&malformed, &[])
+    );
+    let (code, _, stderr) = check(&malformed, &["--warn"]);
+    assert_eq!(0, code);
+    let expected_stderr = format!(
+        "b3sum: checkfile:2: Short line\nb3sum: checkfile:3: Empty line\n{}",
+        summary,
+    );
+    assert_eq!(expected_stderr, stderr);
+    assert_eq!(5, check(&malformed, &["--strict"]).0);
+
+    // Like md5sum, a checkfile with no properly formatted lines fails even
+    // without --strict, and so does an empty one.
+    let no_lines = "b3sum: checkfile: no properly formatted checksum lines found\n";
+    assert_eq!(
+        (5, "".into(), format!("{}{}", no_lines, summary)),
+        check("garbage\n\n", &[])
+    );
+    assert_eq!((5, "".into(), no_lines.into()), check("", &[]));
+    let other = format!("SHA256 (foo) = {}\n", foo_hash);
+    assert_eq!(
+        (5, "".into(), no_lines.into()),
+        check(&other, &["--ignore-other-algorithms"])
+    );
+    // Missing files that are ignored still count as properly formatted.
+    assert_eq!(
+        (0, "".into(), "".into()),
+        check(&format!("{}  missing\n", foo_hash), &["--ignore-missing"])
+    );
+
+    // When there are several kinds of problems, a mismatch wins.
+    let everything = format!(
+        "{}  foo\n{}  missing\ngarbage\n",
+        blake3::hash(b"bar"),
+        foo_hash
+    );
+    let (code, _, stderr) = check(&everything, &["--strict"]);
+    assert_eq!(1, code);
+    let expected_stderr = "\
+        b3sum: WARNING: 1 line is improperly formatted\n\
+        b3sum: WARNING: 1 listed file could not be read\n\
+        b3sum: WARNING: 1 computed checksum did NOT match\n";
+    assert_eq!(expected_stderr, stderr);
+
+    // --safe-paths rejects anything that could point outside the current
+    // directory, even if the file is there.
+    let absolute = dir.path().join("sub/foo");
+    let unsafe_paths = format!(
+        "{}  ../sub/foo\n{}  {}\n{}  ./foo\n",
+        foo_hash,
+        foo_hash,
+        // Windows paths have backslashes, which are forbidden in checkfiles.
+        absolute.to_str().unwrap().replace('\\', "/"),
+        foo_hash,
+    );
+    let (code, stdout, _) = check(&unsafe_paths, &[]);
+    assert_eq!(0, code);
+    assert_eq!(3, stdout.lines().count());
+    let (code, stdout, stderr) = check(&unsafe_paths, &["--safe-paths"]);
+    assert_eq!(4, code);
+    assert_eq!("./foo: OK\n", stdout);
+    let expected_stderr = "\
+        b3sum: Parent directory in path\n\
+        b3sum: Absolute path\n\
+        b3sum: WARNING: 2 listed paths were rejected\n";
+    assert_eq!(expected_stderr, stderr);
+}
+
+#[test]
+fn test_check_keyed_and_derive_key() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo"), b"foo").unwrap();
+    let key = [42; blake3::KEY_LEN];
+    fs::write(dir.path().join("key"), key).unwrap();
+
+    for mode_args in [
+        &["--keyed", "--key-file=key"][..],
+        &["--derive-key=ctx"],
+        &[],
+    ] {
+        // Each line can have its own length, with or without --tag.
+        let mut checkfile = String::new();
+        for extra_args in [
+            &["--length=64"][..],
+            &["--length=8"],
+            &["--length=16", "--tag"],
+            &[],
+        ] {
+            let args = mode_args.iter().chain(extra_args).chain(&["foo"]);
+            checkfile += &cmd(b3sum_exe(), args).dir(dir.path()).read().unwrap();
+            checkfile += "\n";
+        }
+        fs::write(dir.path().join("checkfile"), &checkfile).unwrap();
+        let args = mode_args.iter().chain(&["--check", "checkfile"]);
+        let output = cmd(b3sum_exe(), args).dir(dir.path()).read().unwrap();
+        assert_eq!("foo: OK\nfoo: OK\nfoo: OK\nfoo: OK", output);
+
+        // The wrong mode fails every line.
+        let wrong_args: &[&str] = if mode_args.is_empty() {
+            &["--derive-key=other"]
+        } else {
+            &[]
+        };
+        let output = cmd(
+            b3sum_exe(),
+            wrong_args.iter().chain(&["--check", "checkfile"]),
+        )
+        .dir(dir.path())
+        .stdout_capture()
+        .stderr_null()
+        .unchecked()
+        .run()
+        .unwrap();
+        assert_eq!(Some(1), output.status.code());
+        assert_eq!(
+            b"foo: FAILED\nfoo: FAILED\nfoo: FAILED\nfoo: FAILED\n",
+            &output.stdout[..]
+        );
+    }
+
+    // With the key on stdin, the checkfile can't come from there too.
+    let output = cmd!(b3sum_exe(), "--keyed", "--check", "-")
+        .dir(dir.path())
+        .stdin_bytes(&key[..])
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_encode_decode_slice() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = (0..100_000u32).map(|i| i as u8).collect::<Vec<u8>>();
+    fs::write(dir.path().join("input"), &input).unwrap();
+    // The hash that plain b3sum prints is the one the encoding decodes against.
+    let hash = cmd!(b3sum_exe(), "--no-names", "input")
+        .dir(dir.path())
+        .read()
+        .unwrap();
+
+    cmd!(b3sum_exe(), "encode", "input", "encoded")
+        .dir(dir.path())
+        .run()
+        .unwrap();
+    let output = cmd!(b3sum_exe(), "decode", &hash, "encoded")
+        .dir(dir.path())
+        .stdout_capture()
+        .run()
+        .unwrap();
+    assert_eq!(input, output.stdout);
+
+    // Streaming from stdin gives the same encoding as mapping the file. The
+    // encoder seeks around in its output, so that can't be stdout.
+    cmd!(b3sum_exe(), "encode", "-", "encoded_stdin")
+        .dir(dir.path())
+        .stdin_bytes(&input[..])
+        .run()
+        .unwrap();
+    assert_eq!(
+        fs::read(dir.path().join("encoded")).unwrap(),
+        fs::read(dir.path().join("encoded_stdin")).unwrap(),
+    );
+    let output = cmd!(b3sum_exe(), "encode", "input", "-")
+        .dir(dir.path())
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+
+    // Outboard encoding, with a non-default chunk group size.
+    cmd!(
+        b3sum_exe(),
+        "encode",
+        "--outboard",
+        "--chunk-group-log=2",
+        "input",
+        "outboard"
+    )
+    .dir(dir.path())
+    .run()
+    .unwrap();
+    cmd!(
+        b3sum_exe(),
+        "decode",
+        "--outboard=outboard",
+        "--chunk-group-log=2",
+        &hash,
+        "input",
+        "decoded"
+    )
+    .dir(dir.path())
+    .run()
+    .unwrap();
+    assert_eq!(input, fs::read(dir.path().join("decoded")).unwrap());
+
+    // Slices, from either kind of encoding.
+    let expected = &input[5000..][..20000];
+    for (slice_args, group_arg) in [
+        (&["encoded"][..], "--chunk-group-log=4"),
+        (
+            &["--outboard=outboard", "--chunk-group-log=2", "input"],
+            "--chunk-group-log=2",
+        ),
+    ] {
+        let slice = cmd(
+            b3sum_exe(),
+            ["slice", "5000", "20000"].iter().chain(slice_args),
+        )
+        .dir(dir.path())
+        .stdout_capture()
+        .run()
+        .unwrap()
+        .stdout;
+        let output = cmd!(
+            b3sum_exe(),
+            "decode",
+            "--slice-start=5000",
+            "--slice-len=20000",
+            group_arg,
+            &hash
+        )
+        .stdin_bytes(slice)
+        .stdout_capture()
+        .run()
+        .unwrap();
+        assert_eq!(expected, &output.stdout[..]);
+    }
+
+    // Corrupting the encoding fails the decode, but everything verified before the corrupt chunk
+    // group is written out.
+    let mut corrupt = fs::read(dir.path().join("encoded")).unwrap();
+    corrupt[60_000] ^= 1;
+    let output = cmd!(b3sum_exe(), "decode", &hash)
+        .stdin_bytes(corrupt)
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!output.stdout.is_empty());
+    assert!(output.stdout.len() < input.len());
+    assert_eq!(&input[..output.stdout.len()], &output.stdout[..]);
+
+    // The wrong hash fails right away.
+    let wrong_hash = blake3::hash(b"foo").to_hex();
+    let output = cmd!(b3sum_exe(), "decode", wrong_hash.as_str(), "encoded")
+        .dir(dir.path())
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_subcommand_names_and_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("input"), b"foo").unwrap();
+    fs::write(dir.path().join("encode"), b"bar").unwrap();
+    fs::write(dir.path().join("help"), b"baz").unwrap();
+
+    // Files named like the subcommands are hashed, the way they were before
+    // the subcommands existed.
+    let output = cmd!(b3sum_exe(), "encode", "--length=16", "help")
+        .dir(dir.path())
+        .read()
+        .unwrap();
+    let expected = format!(
+        "{}  encode\n{}  help",
+        &blake3::hash(b"bar").to_hex()[..32],
+        &blake3::hash(b"baz").to_hex()[..32],
+    );
+    assert_eq!(expected, output);
+    let output = cmd!(b3sum_exe(), "help").dir(dir.path()).read().unwrap();
+    assert_eq!(format!("{}  help", blake3::hash(b"baz")), output);
+
+    // The flag spelling always means the subcommand, and so does the plain
+    // name when there's no file in the way.
+    let hash = blake3::hash(b"foo");
+    cmd!(b3sum_exe(), "--encode", "input", "encoded")
+        .dir(dir.path())
+        .run()
+        .unwrap();
+    fs::remove_file(dir.path().join("encode")).unwrap();
+    cmd!(b3sum_exe(), "encode", "input", "encoded2")
+        .dir(dir.path())
+        .run()
+        .unwrap();
+    for encoded in ["encoded", "encoded2"] {
+        let output = cmd!(b3sum_exe(), "--decode", hash.to_hex().as_str(), encoded)
+            .dir(dir.path())
+            .read()
+            .unwrap();
+        assert_eq!("foo", output);
+    }
+}
+
+#[test]
+fn test_input_offset_and_length() {
+    let dir = tempfile::tempdir().unwrap();
+    // Big enough to be memory mapped.
+    let input = (0..300_000u32).map(|i| i as u8).collect::<Vec<u8>>();
+    fs::write(dir.path().join("input"), &input).unwrap();
+
+    for (offset, length) in [
+        (Some(1000), Some(200_000)),
+        (Some(100), None),
+        (None, Some(10)),
+        (Some(300_000), None),
+        (Some(5), Some(0)),
+    ] {
+        let start = offset.unwrap_or(0);
+        let end = length.map_or(input.len(), |length| start + length);
+        let expected = blake3::hash(&input[start..end]).to_hex();
+        let mut range_args = Vec::new();
+        if let Some(offset) = offset {
+            range_args.push(format!("--input-offset={}", offset));
+        }
+        if let Some(length) = length {
+            range_args.push(format!("--input-length={}", length));
+        }
+        for extra_args in [
+            &["input"][..],
+            &["--no-mmap", "input"],
+            &["--progress", "input"],
+        ] {
+            let args = range_args
+                .iter()
+                .map(String::as_str)
+                .chain(["--no-names"])
+                .chain(extra_args.iter().copied());
+            let output = cmd(b3sum_exe(), args)
+                .dir(dir.path())
+                .stderr_null()
+                .read()
+                .unwrap();
+            assert_eq!(&*expected, output);
+        }
+        let output = cmd(
+            b3sum_exe(),
+            range_args.iter().map(String::as_str).chain(["--no-names"]),
+        )
+        .stdin_bytes(&input[..])
+        .read()
+        .unwrap();
+        assert_eq!(&*expected, output);
+    }
+
+    // Ranges past the end are errors, from files and from stdin.
+    for range_args in [
+        &["--input-offset=300001"][..],
+        &["--input-length=300001"],
+        &["--input-offset=299990", "--input-length=20"],
+    ] {
+        for input_args in [&["input"][..], &["--no-mmap", "input"]] {
+            let output = cmd(b3sum_exe(), range_args.iter().chain(input_args))
+                .dir(dir.path())
+                .stdout_capture()
+                .stderr_capture()
+                .unchecked()
+                .run()
+                .unwrap();
+            assert!(!output.status.success());
+            assert!(output.stdout.is_empty());
+            let stderr = std::str::from_utf8(&output.stderr).unwrap();
+            assert!(stderr.contains("past the end of the input (300000 bytes)"));
+        }
+        let output = cmd(b3sum_exe(), range_args)
+            .stdin_bytes(&input[..])
+            .stdout_capture()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+    }
+
+    // The range doesn't make sense with --check or --tree-hash.
+    for other_arg in ["--check", "--tree-hash"] {
+        let output = cmd!(b3sum_exe(), "--input-offset=1", other_arg, "input")
+            .dir(dir.path())
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .unwrap();
+        assert!(!output.status.success());
+    }
+}
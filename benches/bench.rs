@@ -218,6 +218,87 @@ fn bench_many_parents_neon(b: &mut Bencher) {
     }
 }
 
+// Extended output, 16 blocks at a time. The "one block at a time" variants call
+// compress_xof() in a loop, which is what xof_many() replaces.
+const XOF_BLOCKS: usize = 16;
+
+fn bench_xof_fn(b: &mut Bencher, platform: Platform, one_block_at_a_time: bool) {
+    b.bytes += (XOF_BLOCKS * BLOCK_LEN) as u64;
+    let mut r = RandomInput::new(b, BLOCK_LEN);
+    let block = *array_ref!(r.get(), 0, BLOCK_LEN);
+    let cv = [1u32; 8];
+    let mut out = [0; XOF_BLOCKS * BLOCK_LEN];
+    b.iter(|| {
+        if one_block_at_a_time {
+            for (i, out_block) in out.chunks_exact_mut(BLOCK_LEN).enumerate() {
+                out_block.copy_from_slice(&platform.compress_xof(
+                    &cv,
+                    &block,
+                    BLOCK_LEN as u8,
+                    i as u64,
+                    0,
+                ));
+            }
+        } else {
+            platform.xof_many(&cv, &block, BLOCK_LEN as u8, 0, 0, &mut out);
+        }
+        test::black_box(&out);
+    });
+}
+
+#[bench]
+fn bench_xof_portable(b: &mut Bencher) {
+    bench_xof_fn(b, Platform::portable(), false);
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_xof_sse2(b: &mut Bencher) {
+    if let Some(platform) = Platform::sse2() {
+        bench_xof_fn(b, platform, false);
+    }
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_xof_sse41(b: &mut Bencher) {
+    if let Some(platform) = Platform::sse41() {
+        bench_xof_fn(b, platform, false);
+    }
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_xof_sse41_one_block_at_a_time(b: &mut Bencher) {
+    if let Some(platform) = Platform::sse41() {
+        bench_xof_fn(b, platform, true);
+    }
+}
+
+#[bench]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bench_xof_avx2(b: &mut Bencher) {
+    if let Some(platform) = Platform::avx2() {
+        bench_xof_fn(b, platform, false);
+    }
+}
+
+#[bench]
+#[cfg(blake3_avx512_ffi)]
+fn bench_xof_avx512(b: &mut Bencher) {
+    if let Some(platform) = Platform::avx512() {
+        bench_xof_fn(b, platform, false);
+    }
+}
+
+#[bench]
+#[cfg(blake3_avx512_ffi)]
+fn bench_xof_avx512_one_block_at_a_time(b: &mut Bencher) {
+    if let Some(platform) = Platform::avx512() {
+        bench_xof_fn(b, platform, true);
+    }
+}
+
 fn bench_atonce(b: &mut Bencher, len: usize) {
     let mut input = RandomInput::new(b, len);
     b.iter(|| blake3::hash(input.get()));
@@ -0,0 +1,261 @@
+// Linux-only file hashing with O_DIRECT reads submitted through io_uring. See
+// Hasher::update_file_direct. Reading with O_DIRECT skips the page cache, and keeping several
+// reads in flight lets the device work while the current buffer is hashed.
+
+use arrayvec::ArrayVec;
+use io_uring::{opcode, types, IoUring};
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+// O_DIRECT requires the buffer address, the file offset, and the read length to be aligned to the
+// logical block size of the device. 4096 bytes covers all the common ones.
+const ALIGN: usize = 4096;
+
+// Each buffer is a power of two, so that the hasher stays aligned to subtrees of this size, and
+// every full buffer gets hashed as wide as possible.
+const BUFFER_LEN: usize = 1 << 20;
+
+const QUEUE_DEPTH: usize = 4;
+
+struct AlignedBuffer(*mut u8);
+
+impl AlignedBuffer {
+    fn layout() -> Layout {
+        Layout::from_size_align(BUFFER_LEN, ALIGN).unwrap()
+    }
+
+    fn new() -> Self {
+        // SAFETY: The layout isn't zero-sized.
+        let ptr = unsafe { alloc::alloc(Self::layout()) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(Self::layout());
+        }
+        Self(ptr)
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: The pointer came from alloc() with the same layout.
+        unsafe { alloc::dealloc(self.0, Self::layout()) }
+    }
+}
+
+struct Slot {
+    buffer: AlignedBuffer,
+    offset: u64,
+    // The number of bytes this slot should end up with, and the number read so far.
+    expected: usize,
+    filled: usize,
+    done: bool,
+}
+
+struct Reader {
+    ring: IoUring,
+    file: File,
+    slots: Vec<Slot>,
+    in_flight: usize,
+}
+
+impl Reader {
+    // Submit a read for the unfilled part of a slot. The read length is rounded up to the
+    // alignment, which is allowed past the end of the file.
+    fn submit(&mut self, index: usize) -> io::Result<()> {
+        let slot = &mut self.slots[index];
+        let len = (slot.expected - slot.filled + ALIGN - 1) & !(ALIGN - 1);
+        let entry = opcode::Read::new(
+            types::Fd(self.file.as_raw_fd()),
+            // SAFETY: filled < expected <= BUFFER_LEN, and len fits in the rest of the buffer,
+            // because filled is a multiple of ALIGN and so is BUFFER_LEN.
+            unsafe { slot.buffer.0.add(slot.filled) },
+            len as u32,
+        )
+        .offset(slot.offset + slot.filled as u64)
+        .build()
+        .user_data(index as u64);
+        // SAFETY: The buffer isn't touched again until the read completes, and Drop waits for
+        // every read in flight before the buffers are freed. There's at most one read in flight
+        // per slot, so the queue can't be full.
+        unsafe { self.ring.submission().push(&entry) }.expect("submission queue is full");
+        self.in_flight += 1;
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    fn start(&mut self, index: usize, offset: u64, file_len: u64) -> io::Result<()> {
+        let slot = &mut self.slots[index];
+        slot.offset = offset;
+        slot.expected = (file_len - offset).min(BUFFER_LEN as u64) as usize;
+        slot.filled = 0;
+        slot.done = false;
+        self.submit(index)
+    }
+
+    // Wait for at least one read to complete, and record the results. A short read at an aligned
+    // position is resubmitted. Anything else short means the file shrank, and the slot is done.
+    fn wait(&mut self) -> io::Result<()> {
+        match self.ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let completions: ArrayVec<(u64, i32), QUEUE_DEPTH> = self
+            .ring
+            .completion()
+            .map(|entry| (entry.user_data(), entry.result()))
+            .collect();
+        let mut first_error = None;
+        for (index, result) in completions {
+            self.in_flight -= 1;
+            let slot = &mut self.slots[index as usize];
+            if result < 0 {
+                first_error.get_or_insert(io::Error::from_raw_os_error(-result));
+                slot.done = true;
+            } else if result == 0 {
+                slot.done = true;
+            } else {
+                slot.filled = (slot.filled + result as usize).min(slot.expected);
+                if slot.filled == slot.expected || slot.filled & (ALIGN - 1) != 0 {
+                    slot.done = true;
+                } else {
+                    self.submit(index as usize)?;
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // Read the whole file in order, passing each filled buffer to `update`. `hashed` counts the
+    // bytes passed so far, so that the caller knows whether it can still fall back.
+    fn read_all(
+        &mut self,
+        file_len: u64,
+        hashed: &mut u64,
+        mut update: impl FnMut(&[u8]),
+    ) -> io::Result<()> {
+        let mut next_offset = 0;
+        for index in 0..self.slots.len() {
+            if next_offset >= file_len {
+                break;
+            }
+            self.start(index, next_offset, file_len)?;
+            next_offset += BUFFER_LEN as u64;
+        }
+        let mut index = 0;
+        while *hashed < file_len {
+            while !self.slots[index].done {
+                self.wait()?;
+            }
+            let slot = &self.slots[index];
+            // SAFETY: The read into this slot has completed, and it filled this many bytes.
+            let bytes = unsafe { std::slice::from_raw_parts(slot.buffer.0, slot.filled) };
+            update(bytes);
+            *hashed += slot.filled as u64;
+            if slot.filled < slot.expected {
+                // The file shrank. Like reading it the usual way, stop here.
+                break;
+            }
+            if next_offset < file_len {
+                self.start(index, next_offset, file_len)?;
+                next_offset += BUFFER_LEN as u64;
+            }
+            index = (index + 1) % self.slots.len();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        // The kernel may still be writing into the buffers, so wait for every read in flight
+        // before they're freed. If waiting fails, leak the buffers instead.
+        while self.in_flight > 0 {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    for slot in self.slots.drain(..) {
+                        std::mem::forget(slot.buffer);
+                    }
+                    return;
+                }
+            }
+            self.in_flight -= self.ring.completion().count();
+        }
+    }
+}
+
+fn update_fallback(hasher: &mut crate::Hasher, path: &Path) -> io::Result<()> {
+    let file = File::open(path)?;
+    #[cfg(feature = "rayon")]
+    crate::io::copy_wide_rayon(file, hasher, BUFFER_LEN)?;
+    #[cfg(not(feature = "rayon"))]
+    crate::io::copy_wide(file, hasher)?;
+    Ok(())
+}
+
+fn update_buffer(hasher: &mut crate::Hasher, buffer: &[u8]) {
+    #[cfg(feature = "rayon")]
+    hasher.update_rayon(buffer);
+    #[cfg(not(feature = "rayon"))]
+    hasher.update(buffer);
+}
+
+pub(crate) fn update_file_direct(hasher: &mut crate::Hasher, path: &Path) -> io::Result<()> {
+    let is_einval = |e: &io::Error| e.raw_os_error() == Some(libc::EINVAL);
+    let file = match OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+    {
+        Ok(file) => file,
+        // The filesystem doesn't support O_DIRECT, for example tmpfs before Linux 6.6.
+        Err(e) if is_einval(&e) => return update_fallback(hasher, path),
+        Err(e) => return Err(e),
+    };
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        // Not a real file, so the length isn't known up front.
+        return update_fallback(hasher, path);
+    }
+    let ring = match IoUring::new(QUEUE_DEPTH as u32) {
+        Ok(ring) => ring,
+        // io_uring isn't available, either because the kernel is too old, or because it's been
+        // disabled, for example by a container's seccomp policy.
+        Err(_) => return update_fallback(hasher, path),
+    };
+    let mut reader = Reader {
+        ring,
+        file,
+        slots: (0..QUEUE_DEPTH)
+            .map(|_| Slot {
+                buffer: AlignedBuffer::new(),
+                offset: 0,
+                expected: 0,
+                filled: 0,
+                done: true,
+            })
+            .collect(),
+        in_flight: 0,
+    };
+    let mut hashed = 0;
+    match reader.read_all(metadata.len(), &mut hashed, |buffer| {
+        update_buffer(hasher, buffer)
+    }) {
+        Ok(()) => Ok(()),
+        // Some filesystems accept O_DIRECT at open time but refuse the reads, and kernels before
+        // 5.6 don't support the read opcode. If nothing has been hashed yet, start over.
+        Err(e) if hashed == 0 && is_einval(&e) => {
+            drop(reader);
+            update_fallback(hasher, path)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,1121 @@
+//! Verified streaming, in the style of the [Bao](https://github.com/oconnor663/bao) encoding.
+//!
+//! A BLAKE3 hash commits to the whole input, so normally a download has to finish before it can
+//! be checked. An encoding stores the chaining values of the tree's parent nodes alongside the
+//! input, so that a [`Decoder`] can check every piece of it against the root [`struct@Hash`] as
+//! it arrives, and never returns a byte that hasn't been verified.
+//!
+//! There are two formats. The "combined" encoding from [`encode`] interleaves the tree with the
+//! input bytes, and it's all a decoder needs. The "outboard" encoding from [`outboard`] contains
+//! only the tree, and a decoder reads the input bytes from a separate source. An [`Encoder`]
+//! produces either one incrementally, for inputs that don't fit in memory. Both start with the
+//! input length, as an 8-byte little-endian integer, followed by the parent nodes in pre-order.
+//! Each parent node is the 64-byte concatenation of its children's chaining values. In the
+//! combined encoding, each leaf's input bytes follow the parent nodes that lead to it.
+//!
+//! The leaves of the encoded tree are [`ChunkGroup`]s, rather than individual 1 KiB chunks. The
+//! default group of 16 chunks keeps the outboard encoding under 1/256 of the input length, and
+//! larger groups shrink it further, at the cost of buffering a whole group in the decoder before
+//! any of it can be verified. The encoder and the decoder have to agree on the group size.
+//!
+//! A client that only needs part of the input can ask for a slice instead. [`extract_slice`] and
+//! [`extract_slice_outboard`] pull out just the parent nodes and groups covering a byte range,
+//! and a [`SliceDecoder`] verifies them and returns only the bytes in that range.
+//!
+//! This module only supports the regular [`hash`](crate::hash) mode. It's gated by the
+//! `encoding` Cargo feature, which is disabled by default.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> std::io::Result<()> {
+//! use blake3::encoding::{encode, ChunkGroup, Decoder};
+//! use std::io::prelude::*;
+//!
+//! let input = vec![0xab; 100_000];
+//! let (encoded, hash) = encode(&input, ChunkGroup::default());
+//! assert_eq!(hash, blake3::hash(&input));
+//!
+//! let mut decoder = Decoder::new(&encoded[..], &hash, ChunkGroup::default());
+//! let mut decoded = Vec::new();
+//! decoder.read_to_end(&mut decoded)?;
+//! assert_eq!(input, decoded);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::guts::{self, CHUNK_LEN};
+use crate::hazmat::{self, Mode, Subtree};
+use crate::{Hash, OUT_LEN};
+use arrayref::array_ref;
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+
+/// The length of the encoding header, which holds the input length.
+pub const HEADER_LEN: usize = 8;
+
+/// The length of an encoded parent node, two chaining values.
+pub const PARENT_LEN: usize = 2 * OUT_LEN;
+
+/// The largest supported [`ChunkGroup`] size, as a power of two. 2<sup>16</sup> chunks is
+/// 64 MiB.
+pub const MAX_CHUNK_GROUP_LOG: u8 = 16;
+
+/// The number of chunks in each leaf of an encoded tree.
+///
+/// The group size is always a power of two. The encoder and the decoder must use the same group
+/// size, or decoding will fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkGroup {
+    log2_chunks: u8,
+}
+
+impl ChunkGroup {
+    /// A group of 2<sup>`log2_chunks`</sup> chunks. `ChunkGroup::new(0)` makes every chunk its
+    /// own leaf, like the original Bao format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `log2_chunks` is greater than [`MAX_CHUNK_GROUP_LOG`].
+    pub const fn new(log2_chunks: u8) -> Self {
+        assert!(log2_chunks <= MAX_CHUNK_GROUP_LOG, "chunk group too large");
+        Self { log2_chunks }
+    }
+
+    /// The number of chunks in a group.
+    pub const fn chunks(&self) -> u64 {
+        1 << self.log2_chunks
+    }
+
+    /// The number of input bytes in a group.
+    pub const fn byte_len(&self) -> u64 {
+        (CHUNK_LEN as u64) << self.log2_chunks
+    }
+}
+
+impl Default for ChunkGroup {
+    /// 16 chunks, 16 KiB.
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+// The number of parent nodes in an encoded tree, one less than the number of groups.
+fn parent_nodes(content_len: u64, group: ChunkGroup) -> u64 {
+    // An empty input still has one (empty) group.
+    content_len.saturating_sub(1) / group.byte_len()
+}
+
+/// The length of the outboard encoding of an input of `content_len` bytes.
+pub fn outboard_len(content_len: u64, group: ChunkGroup) -> u128 {
+    HEADER_LEN as u128 + PARENT_LEN as u128 * parent_nodes(content_len, group) as u128
+}
+
+/// The length of the combined encoding of an input of `content_len` bytes.
+pub fn encoded_len(content_len: u64, group: ChunkGroup) -> u128 {
+    outboard_len(content_len, group) + content_len as u128
+}
+
+// Given a subtree larger than one group, the number of bytes in its left child. This is the same
+// split as for chunks, because the group size is a power of two.
+fn left_len(content_len: u64) -> u64 {
+    hazmat::left_subtree_len(content_len)
+}
+
+// The chaining value of a leaf group, or the root hash if the whole input is a single group.
+fn group_hash(group_bytes: &[u8], start: u64, is_root: bool) -> Hash {
+    if is_root {
+        debug_assert_eq!(start, 0);
+        crate::hash(group_bytes)
+    } else {
+        // Group boundaries are always valid subtree boundaries.
+        let subtree = Subtree::hash(Mode::Hash, start, group_bytes).expect("valid group");
+        Hash::from(*subtree.chaining_value())
+    }
+}
+
+// The size of the buffer for moving leaves around in Encoder::finalize.
+const FLIP_BUF_LEN: usize = 16 * 1024;
+
+/// An incremental encoder, which writes the combined or outboard encoding of everything written to
+/// it.
+///
+/// The encoder hashes its input one chunk at a time, keeping only a stack of chaining values, so
+/// its memory use doesn't depend on the input length. Parent nodes can't be written in pre-order
+/// until their children are done, so the encoder writes the tree in post-order as it goes, and
+/// [`finalize`](Encoder::finalize) rearranges it into pre-order in place. That's why the
+/// underlying writer has to support reading and seeking too, like a [`File`](std::fs::File) or an
+/// [`io::Cursor`]. The encoding starts at the writer's position when the first byte is written.
+///
+/// If writing fails, the encoder and whatever it wrote so far should be discarded.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use blake3::encoding::{encode, ChunkGroup, Encoder};
+/// use std::io::prelude::*;
+///
+/// let input = vec![0xab; 100_000];
+/// let mut encoder = Encoder::new(std::io::Cursor::new(Vec::new()), ChunkGroup::default());
+/// encoder.write_all(&input[..50_000])?;
+/// encoder.write_all(&input[50_000..])?;
+/// let (hash, encoded) = encoder.finalize()?;
+/// assert_eq!(hash, blake3::hash(&input));
+/// assert_eq!(encoded.into_inner(), encode(&input, ChunkGroup::default()).0);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Encoder<W: Read + Write + Seek> {
+    inner: W,
+    group: ChunkGroup,
+    combined: bool,
+    // Where the encoding starts in `inner`, once the header placeholder has been written.
+    start: Option<u64>,
+    chunk: guts::ChunkState,
+    chunk_counter: u64,
+    // The chaining values of complete subtrees to the left of the current chunk, with their
+    // lengths in chunks, largest first.
+    cv_stack: Vec<(Hash, u64)>,
+    // Parent nodes waiting to be written, in post-order.
+    parents: Vec<u8>,
+}
+
+impl<W: Read + Write + Seek> Encoder<W> {
+    /// Write the combined encoding to `inner`.
+    pub fn new(inner: W, group: ChunkGroup) -> Self {
+        Self::new_inner(inner, group, true)
+    }
+
+    /// Write the outboard encoding to `inner`, which contains only the header and the parent
+    /// nodes, without the input bytes.
+    pub fn new_outboard(inner: W, group: ChunkGroup) -> Self {
+        Self::new_inner(inner, group, false)
+    }
+
+    fn new_inner(inner: W, group: ChunkGroup, combined: bool) -> Self {
+        Self {
+            inner,
+            group,
+            combined,
+            start: None,
+            chunk: guts::ChunkState::new(0),
+            chunk_counter: 0,
+            cv_stack: Vec::new(),
+            parents: Vec::new(),
+        }
+    }
+
+    fn start(&mut self) -> io::Result<u64> {
+        if let Some(start) = self.start {
+            return Ok(start);
+        }
+        // The header is filled in by finalize(), once the length is known.
+        let start = self.inner.stream_position()?;
+        self.inner.write_all(&[0; HEADER_LEN])?;
+        self.start = Some(start);
+        Ok(start)
+    }
+
+    // Push the chaining value of a finished chunk, which isn't the root because more input
+    // follows it, and merge every subtree that's now complete. Parent nodes above the leaves go
+    // into self.parents.
+    fn push_chunk_cv(&mut self, cv: Hash) {
+        let mut new = (cv, 1);
+        while let Some(&(left_cv, left_chunks)) = self.cv_stack.last() {
+            if left_chunks != new.1 {
+                break;
+            }
+            self.cv_stack.pop();
+            if left_chunks >= self.group.chunks() {
+                self.parents.extend_from_slice(left_cv.as_bytes());
+                self.parents.extend_from_slice(new.0.as_bytes());
+            }
+            new = (guts::parent_cv(&left_cv, &new.0, false), 2 * left_chunks);
+        }
+        self.cv_stack.push(new);
+    }
+
+    /// Finish the encoding, and return its root hash along with the underlying writer, which is
+    /// left positioned at the end of the encoding.
+    pub fn finalize(mut self) -> io::Result<(Hash, W)> {
+        let start = self.start()?;
+        // Merge what's left on the stack, from the right edge of the tree up to the root.
+        let mut right = self.chunk.output();
+        while let Some((left_cv, left_chunks)) = self.cv_stack.pop() {
+            let right_cv = right.chaining_value();
+            if left_chunks >= self.group.chunks() {
+                self.parents.extend_from_slice(left_cv.as_bytes());
+                self.parents.extend_from_slice(right_cv.as_bytes());
+            }
+            right = guts::parent_output(&left_cv, &right_cv, &guts::IV_BYTES, 0);
+        }
+        let hash = right.root_hash();
+        self.inner.write_all(&self.parents)?;
+        self.parents.clear();
+
+        let content_len = self.chunk_counter * CHUNK_LEN as u64 + self.chunk.len() as u64;
+        let end = self.inner.stream_position()?;
+        let mut read_end = end;
+        let mut write_end = end;
+        let mut buf = vec![0; FLIP_BUF_LEN];
+        self.flip(content_len, &mut read_end, &mut write_end, &mut buf)?;
+        debug_assert_eq!(read_end, start + HEADER_LEN as u64);
+        debug_assert_eq!(write_end, start + HEADER_LEN as u64);
+
+        self.inner.seek(io::SeekFrom::Start(start))?;
+        self.inner.write_all(&content_len.to_le_bytes())?;
+        self.inner.seek(io::SeekFrom::Start(end))?;
+        self.inner.flush()?;
+        Ok((hash, self.inner))
+    }
+
+    // Rearrange the post-order encoding of a subtree of `len` input bytes, which ends at
+    // `read_end`, into its pre-order encoding ending at `write_end`, working backwards. The
+    // post-order of a subtree is its left child, its right child, and then its parent node, so
+    // reading backwards finds each parent before its children, and it's held on the stack until
+    // they've been written. That keeps `write_end` at or after `read_end`, so nothing is
+    // overwritten before it's been read.
+    fn flip(
+        &mut self,
+        len: u64,
+        read_end: &mut u64,
+        write_end: &mut u64,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        if len <= self.group.byte_len() {
+            if self.combined {
+                self.move_back(len, read_end, write_end, buf)?;
+            }
+            return Ok(());
+        }
+        let mut parent = [0; PARENT_LEN];
+        *read_end -= PARENT_LEN as u64;
+        self.inner.seek(io::SeekFrom::Start(*read_end))?;
+        self.inner.read_exact(&mut parent)?;
+        let left_len = left_len(len);
+        self.flip(len - left_len, read_end, write_end, buf)?;
+        self.flip(left_len, read_end, write_end, buf)?;
+        *write_end -= PARENT_LEN as u64;
+        self.inner.seek(io::SeekFrom::Start(*write_end))?;
+        self.inner.write_all(&parent)
+    }
+
+    // Move the `len` bytes before `read_end` to just before `write_end`, last piece first, since
+    // the ranges may overlap.
+    fn move_back(
+        &mut self,
+        mut len: u64,
+        read_end: &mut u64,
+        write_end: &mut u64,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        if *read_end == *write_end {
+            *read_end -= len;
+            *write_end -= len;
+            return Ok(());
+        }
+        while len > 0 {
+            let piece_len = cmp::min(len, buf.len() as u64) as usize;
+            let piece = &mut buf[..piece_len];
+            *read_end -= piece.len() as u64;
+            self.inner.seek(io::SeekFrom::Start(*read_end))?;
+            self.inner.read_exact(piece)?;
+            *write_end -= piece.len() as u64;
+            self.inner.seek(io::SeekFrom::Start(*write_end))?;
+            self.inner.write_all(piece)?;
+            len -= piece.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Read + Write + Seek> Write for Encoder<W> {
+    fn write(&mut self, input: &[u8]) -> io::Result<usize> {
+        self.start()?;
+        // Input bytes go straight through, except that parent nodes have to be written in
+        // between them whenever a subtree is completed.
+        let mut written = 0;
+        let mut pos = 0;
+        while pos < input.len() {
+            if self.chunk.len() == CHUNK_LEN {
+                // More input is coming, so this chunk isn't the root.
+                let cv = self.chunk.finalize(false);
+                self.push_chunk_cv(cv);
+                self.chunk_counter += 1;
+                self.chunk = guts::ChunkState::new(self.chunk_counter);
+                if !self.parents.is_empty() {
+                    if self.combined {
+                        self.inner.write_all(&input[written..pos])?;
+                    }
+                    written = pos;
+                    self.inner.write_all(&self.parents)?;
+                    self.parents.clear();
+                }
+            }
+            let take = cmp::min(CHUNK_LEN - self.chunk.len(), input.len() - pos);
+            self.chunk.update(&input[pos..][..take]);
+            pos += take;
+        }
+        if self.combined {
+            self.inner.write_all(&input[written..])?;
+        }
+        Ok(input.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn encode_inner(input: &[u8], group: ChunkGroup, combined: bool) -> (Vec<u8>, Hash) {
+    let content_len = input.len() as u64;
+    let total_len = if combined {
+        encoded_len(content_len, group)
+    } else {
+        outboard_len(content_len, group)
+    };
+    let out = io::Cursor::new(Vec::with_capacity(total_len as usize));
+    let mut encoder = Encoder::new_inner(out, group, combined);
+    // Writing to a Vec can't fail.
+    encoder.write_all(input).unwrap();
+    let (hash, out) = encoder.finalize().unwrap();
+    let out = out.into_inner();
+    debug_assert_eq!(total_len, out.len() as u128);
+    (out, hash)
+}
+
+/// Compute the combined encoding of `input`, and its root hash. See [`Encoder`] for encoding
+/// incrementally.
+pub fn encode(input: &[u8], group: ChunkGroup) -> (Vec<u8>, Hash) {
+    encode_inner(input, group, true)
+}
+
+/// Compute the outboard encoding of `input`, and its root hash. The outboard encoding contains
+/// only the header and the parent nodes, without the input bytes. See [`Encoder::new_outboard`]
+/// for encoding incrementally.
+pub fn outboard(input: &[u8], group: ChunkGroup) -> (Vec<u8>, Hash) {
+    encode_inner(input, group, false)
+}
+
+// A subtree that the decoder hasn't reached yet, along with the chaining value it has to match.
+#[derive(Clone, Debug)]
+struct PendingSubtree {
+    expected: Hash,
+    start: u64,
+    len: u64,
+    is_root: bool,
+}
+
+fn hash_mismatch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "hash mismatch")
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "encoding is truncated")
+}
+
+// The range of input bytes whose leaves and parent nodes go into a slice. This is the requested
+// range, except that it always includes at least one byte, so that even an empty slice or one
+// past the end of the input includes a leaf. Verifying that leaf is what authenticates the input
+// length in the header. For an empty input, it's the single empty leaf.
+fn slice_cover(slice_start: u64, slice_len: u64, content_len: u64) -> (u64, u64) {
+    let cover_start = cmp::min(slice_start, content_len.saturating_sub(1));
+    let cover_end = cmp::min(slice_start.saturating_add(slice_len), content_len);
+    (cover_start, cmp::max(cover_end, cover_start + 1))
+}
+
+// Whether a subtree has any bytes in the cover range. Only the root leaf of an empty input has a
+// length of zero, and it's treated as one byte long, to match slice_cover().
+fn intersects(start: u64, len: u64, cover: (u64, u64)) -> bool {
+    start < cover.1 && cover.0 < start + cmp::max(len, 1)
+}
+
+/// An incremental decoder, which verifies the combined or outboard encoding against a root hash
+/// and returns the original input bytes.
+///
+/// Input is verified one [`ChunkGroup`] at a time. A whole group is buffered and checked before
+/// any of its bytes are returned. If the encoding has been tampered with, or if the group size or
+/// the hash is wrong, reading returns an error of kind [`InvalidData`](io::ErrorKind::InvalidData).
+/// If the encoding is cut short, reading returns an error of kind
+/// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof). Bytes returned before an error have already
+/// been verified, and after an error, every further read fails with the same kind of error. Any
+/// bytes after the end of the encoding are ignored.
+#[derive(Debug)]
+pub struct Decoder<T: Read, O: Read> {
+    input: T,
+    outboard: Option<O>,
+    group: ChunkGroup,
+    root_hash: Hash,
+    // The requested range, which is everything for a regular decoder.
+    slice_start: u64,
+    slice_len: u64,
+    // These are None until the header has been read.
+    pending: Option<Vec<PendingSubtree>>,
+    cover: (u64, u64),
+    output_range: (u64, u64),
+    buf: Vec<u8>,
+    buf_pos: usize,
+    failed: Option<io::ErrorKind>,
+}
+
+impl<T: Read> Decoder<T, T> {
+    /// Decode a combined encoding, as produced by [`encode`].
+    pub fn new(encoded: T, hash: &Hash, group: ChunkGroup) -> Self {
+        Self::new_inner(encoded, None, hash, group, 0, u64::MAX)
+    }
+}
+
+impl<T: Read, O: Read> Decoder<T, O> {
+    fn new_inner(
+        input: T,
+        outboard: Option<O>,
+        hash: &Hash,
+        group: ChunkGroup,
+        slice_start: u64,
+        slice_len: u64,
+    ) -> Self {
+        Self {
+            input,
+            outboard,
+            group,
+            root_hash: *hash,
+            slice_start,
+            slice_len,
+            pending: None,
+            cover: (0, 0),
+            output_range: (0, 0),
+            buf: Vec::new(),
+            buf_pos: 0,
+            failed: None,
+        }
+    }
+
+    /// Decode an outboard encoding, as produced by [`outboard`], together with the original
+    /// input.
+    pub fn new_outboard(input: T, outboard: O, hash: &Hash, group: ChunkGroup) -> Self {
+        Self::new_inner(input, Some(outboard), hash, group, 0, u64::MAX)
+    }
+
+    fn read_tree_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        match &mut self.outboard {
+            Some(outboard) => outboard.read_exact(buf),
+            None => self.input.read_exact(buf),
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = [0; HEADER_LEN];
+        self.read_tree_bytes(&mut header)?;
+        let content_len = u64::from_le_bytes(header);
+        self.cover = slice_cover(self.slice_start, self.slice_len, content_len);
+        let output_start = cmp::min(self.slice_start, content_len);
+        let output_end = cmp::min(self.slice_start.saturating_add(self.slice_len), content_len);
+        self.output_range = (output_start, output_end);
+        self.pending = Some(vec![PendingSubtree {
+            expected: self.root_hash,
+            start: 0,
+            len: content_len,
+            is_root: true,
+        }]);
+        Ok(())
+    }
+
+    // Verify parent nodes until the next group in the cover range is reached, then read and
+    // verify that group into the buffer. Returns false at the end of the encoding.
+    fn fill_buf(&mut self) -> io::Result<bool> {
+        if self.pending.is_none() {
+            self.read_header()?;
+        }
+        loop {
+            let subtree = match self.pending.as_mut().unwrap().pop() {
+                Some(subtree) => subtree,
+                None => return Ok(false),
+            };
+            // Subtrees outside the cover range aren't part of a slice.
+            if !intersects(subtree.start, subtree.len, self.cover) {
+                continue;
+            }
+            if subtree.len > self.group.byte_len() {
+                let mut parent = [0; PARENT_LEN];
+                self.read_tree_bytes(&mut parent)?;
+                let left_cv = Hash::from(*array_ref!(parent, 0, OUT_LEN));
+                let right_cv = Hash::from(*array_ref!(parent, OUT_LEN, OUT_LEN));
+                if guts::parent_cv(&left_cv, &right_cv, subtree.is_root) != subtree.expected {
+                    return Err(hash_mismatch());
+                }
+                let left_len = left_len(subtree.len);
+                let pending = self.pending.as_mut().unwrap();
+                // Push the right child first, so that the left child is popped next.
+                pending.push(PendingSubtree {
+                    expected: right_cv,
+                    start: subtree.start + left_len,
+                    len: subtree.len - left_len,
+                    is_root: false,
+                });
+                pending.push(PendingSubtree {
+                    expected: left_cv,
+                    start: subtree.start,
+                    len: left_len,
+                    is_root: false,
+                });
+                continue;
+            }
+            self.buf.resize(subtree.len as usize, 0);
+            self.input.read_exact(&mut self.buf)?;
+            if group_hash(&self.buf, subtree.start, subtree.is_root) != subtree.expected {
+                return Err(hash_mismatch());
+            }
+            // Only return the part of the group that was asked for.
+            let subtree_end = subtree.start + subtree.len;
+            let output_start = self.output_range.0.clamp(subtree.start, subtree_end);
+            let output_end = self.output_range.1.clamp(output_start, subtree_end);
+            self.buf.truncate((output_end - subtree.start) as usize);
+            self.buf_pos = (output_start - subtree.start) as usize;
+            return Ok(true);
+        }
+    }
+}
+
+impl<T: Read, O: Read> Read for Decoder<T, O> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(kind) = self.failed {
+            return Err(io::Error::new(kind, "decoding previously failed"));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A group can contribute no bytes, for example the empty input, or a group that's only
+        // part of a slice to authenticate the length. Loop until there are bytes or EOF.
+        while self.buf_pos == self.buf.len() {
+            match self.fill_buf() {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                Err(e) => {
+                    // Don't leave unverified bytes in the buffer.
+                    self.buf.clear();
+                    self.buf_pos = 0;
+                    self.failed = Some(e.kind());
+                    return Err(e);
+                }
+            }
+        }
+        let take = cmp::min(buf.len(), self.buf.len() - self.buf_pos);
+        buf[..take].copy_from_slice(&self.buf[self.buf_pos..][..take]);
+        self.buf_pos += take;
+        Ok(take)
+    }
+}
+
+/// An incremental decoder for slices produced by [`extract_slice`] or
+/// [`extract_slice_outboard`], which verifies the slice against the root hash and returns only
+/// the requested bytes.
+///
+/// The `slice_start` and `slice_len` must be the same as the ones the slice was extracted with.
+/// If the requested range goes past the end of the input, the output is cut short. Errors are the
+/// same as for [`Decoder`]: [`InvalidData`](io::ErrorKind::InvalidData) if the slice has been
+/// tampered with or doesn't match the hash, and [`UnexpectedEof`](io::ErrorKind::UnexpectedEof)
+/// if it's truncated.
+#[derive(Debug)]
+pub struct SliceDecoder<T: Read>(Decoder<T, T>);
+
+impl<T: Read> SliceDecoder<T> {
+    /// Decode the slice of `slice_len` bytes starting at `slice_start`.
+    pub fn new(slice: T, hash: &Hash, slice_start: u64, slice_len: u64, group: ChunkGroup) -> Self {
+        Self(Decoder::new_inner(
+            slice,
+            None,
+            hash,
+            group,
+            slice_start,
+            slice_len,
+        ))
+    }
+}
+
+impl<T: Read> Read for SliceDecoder<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+fn overflow() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "encoding length overflows")
+}
+
+struct SliceExtractor<T, O> {
+    input: T,
+    outboard: Option<O>,
+    group: ChunkGroup,
+    cover: (u64, u64),
+}
+
+impl<T: Read + Seek, O: Read + Seek> SliceExtractor<T, O> {
+    // Seek to `position` in the source of the tree nodes (the outboard if there is one, otherwise
+    // the combined encoding) or the source of the input bytes.
+    fn seek_source(&mut self, tree: bool, position: u64) -> io::Result<&mut dyn Read> {
+        match &mut self.outboard {
+            Some(outboard) if tree => {
+                outboard.seek(io::SeekFrom::Start(position))?;
+                Ok(outboard)
+            }
+            _ => {
+                self.input.seek(io::SeekFrom::Start(position))?;
+                Ok(&mut self.input)
+            }
+        }
+    }
+
+    fn copy_exact(
+        &mut self,
+        tree: bool,
+        position: u64,
+        len: u64,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        let source = self.seek_source(tree, position)?;
+        let copied = io::copy(&mut source.take(len), output)?;
+        if copied < len {
+            return Err(truncated());
+        }
+        Ok(())
+    }
+
+    // The number of bytes that a subtree takes up in the tree source.
+    fn encoded_subtree_len(&self, len: u64) -> Option<u64> {
+        let parents_len = parent_nodes(len, self.group).checked_mul(PARENT_LEN as u64)?;
+        if self.outboard.is_some() {
+            Some(parents_len)
+        } else {
+            parents_len.checked_add(len)
+        }
+    }
+
+    // Write out the parent nodes and leaves of this subtree that fall in the cover range, in
+    // pre-order. `position` is where the subtree starts in the tree source.
+    fn extract_subtree(
+        &mut self,
+        start: u64,
+        len: u64,
+        position: u64,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        if !intersects(start, len, self.cover) {
+            return Ok(());
+        }
+        if len <= self.group.byte_len() {
+            // In the outboard case, the leaf comes from the original input.
+            let leaf_position = if self.outboard.is_some() {
+                start
+            } else {
+                position
+            };
+            return self.copy_exact(false, leaf_position, len, output);
+        }
+        self.copy_exact(true, position, PARENT_LEN as u64, output)?;
+        let left_len = left_len(len);
+        let left_position = position
+            .checked_add(PARENT_LEN as u64)
+            .ok_or_else(overflow)?;
+        let right_position = self
+            .encoded_subtree_len(left_len)
+            .and_then(|left_encoded_len| left_position.checked_add(left_encoded_len))
+            .ok_or_else(overflow)?;
+        self.extract_subtree(start, left_len, left_position, output)?;
+        self.extract_subtree(start + left_len, len - left_len, right_position, output)
+    }
+
+    fn extract(
+        &mut self,
+        slice_start: u64,
+        slice_len: u64,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut header = [0; HEADER_LEN];
+        self.seek_source(true, 0)?
+            .read_exact(&mut header)
+            .map_err(|_| truncated())?;
+        output.write_all(&header)?;
+        let content_len = u64::from_le_bytes(header);
+        self.cover = slice_cover(slice_start, slice_len, content_len);
+        self.extract_subtree(0, content_len, HEADER_LEN as u64, output)
+    }
+}
+
+/// Extract the slice of `slice_len` bytes starting at `slice_start` from a combined encoding, and
+/// write it to `output`.
+///
+/// The slice contains the header, and only the parent nodes and [`ChunkGroup`]s needed to verify
+/// the requested range, in the same order as the combined encoding. Decode it with
+/// [`SliceDecoder`], using the same range and group size. A slice always includes at least one
+/// group, even if `slice_len` is zero or the range starts past the end of the input, so that the
+/// decoder can verify the input length.
+///
+/// The encoding isn't verified during extraction. If it's truncated, this returns an error of
+/// kind [`UnexpectedEof`](io::ErrorKind::UnexpectedEof).
+pub fn extract_slice<T: Read + Seek>(
+    encoded: T,
+    slice_start: u64,
+    slice_len: u64,
+    group: ChunkGroup,
+    mut output: impl Write,
+) -> io::Result<()> {
+    SliceExtractor::<T, T> {
+        input: encoded,
+        outboard: None,
+        group,
+        cover: (0, 0),
+    }
+    .extract(slice_start, slice_len, &mut output)
+}
+
+/// As [`extract_slice`], but reading the tree from an outboard encoding and the input bytes from
+/// the original input. The resulting slice is identical.
+pub fn extract_slice_outboard<T: Read + Seek, O: Read + Seek>(
+    input: T,
+    outboard: O,
+    slice_start: u64,
+    slice_len: u64,
+    group: ChunkGroup,
+    mut output: impl Write,
+) -> io::Result<()> {
+    SliceExtractor {
+        input,
+        outboard: Some(outboard),
+        group,
+        cover: (0, 0),
+    }
+    .extract(slice_start, slice_len, &mut output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{paint_test_input, TEST_CASES};
+
+    const GROUPS: &[ChunkGroup] = &[ChunkGroup::new(0), ChunkGroup::new(1), ChunkGroup::new(4)];
+
+    fn decode(
+        encoded: &[u8],
+        outboard: Option<&[u8]>,
+        input: &[u8],
+        hash: &Hash,
+        group: ChunkGroup,
+    ) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        match outboard {
+            Some(outboard) => {
+                Decoder::new_outboard(input, outboard, hash, group).read_to_end(&mut output)?
+            }
+            None => Decoder::new(encoded, hash, group).read_to_end(&mut output)?,
+        };
+        Ok(output)
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        for &case in TEST_CASES {
+            let mut input = vec![0; case];
+            paint_test_input(&mut input);
+            for &group in GROUPS {
+                dbg!(case, group);
+                let expected_hash = crate::hash(&input);
+                let (encoded, hash) = encode(&input, group);
+                assert_eq!(expected_hash, hash);
+                assert_eq!(encoded_len(case as u64, group), encoded.len() as u128);
+                assert_eq!(&(case as u64).to_le_bytes(), &encoded[..HEADER_LEN]);
+                let (outboard, hash) = outboard(&input, group);
+                assert_eq!(expected_hash, hash);
+                assert_eq!(outboard_len(case as u64, group), outboard.len() as u128);
+
+                assert_eq!(input, decode(&encoded, None, &[], &hash, group).unwrap());
+                assert_eq!(
+                    input,
+                    decode(&[], Some(&outboard), &input, &hash, group).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_outboard_size() {
+        // With the default group size, the outboard encoding is a tiny fraction of the input.
+        let content_len = 1 << 30;
+        let len = outboard_len(content_len, ChunkGroup::default());
+        assert_eq!(
+            HEADER_LEN as u128 + PARENT_LEN as u128 * ((1 << 16) - 1),
+            len
+        );
+        assert!(len < content_len as u128 / 256);
+        assert_eq!(8, outboard_len(0, ChunkGroup::default()));
+        assert_eq!(
+            8 + 64,
+            outboard_len(CHUNK_LEN as u64 + 1, ChunkGroup::new(0))
+        );
+    }
+
+    // The straightforward recursive encoder, which writes parent nodes in pre-order directly.
+    fn reference_encode_recurse(
+        input: &[u8],
+        start: u64,
+        is_root: bool,
+        group: ChunkGroup,
+        combined: bool,
+        out: &mut Vec<u8>,
+    ) -> Hash {
+        if input.len() as u64 <= group.byte_len() {
+            if combined {
+                out.extend_from_slice(input);
+            }
+            return group_hash(input, start, is_root);
+        }
+        let parent_start = out.len();
+        out.extend_from_slice(&[0; PARENT_LEN]);
+        let (left, right) = input.split_at(left_len(input.len() as u64) as usize);
+        let left_cv = reference_encode_recurse(left, start, false, group, combined, out);
+        let right_start = start + left.len() as u64;
+        let right_cv = reference_encode_recurse(right, right_start, false, group, combined, out);
+        out[parent_start..][..OUT_LEN].copy_from_slice(left_cv.as_bytes());
+        out[parent_start + OUT_LEN..][..OUT_LEN].copy_from_slice(right_cv.as_bytes());
+        guts::parent_cv(&left_cv, &right_cv, is_root)
+    }
+
+    #[test]
+    fn test_encode_matches_reference() {
+        for &case in TEST_CASES {
+            let mut input = vec![0; case];
+            paint_test_input(&mut input);
+            for &group in GROUPS {
+                for combined in [true, false] {
+                    let mut expected = (case as u64).to_le_bytes().to_vec();
+                    let expected_hash =
+                        reference_encode_recurse(&input, 0, true, group, combined, &mut expected);
+                    let (encoded, hash) = encode_inner(&input, group, combined);
+                    assert_eq!(expected_hash, hash);
+                    assert_eq!(expected, encoded);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoder_incremental() {
+        const PREFIX: &[u8] = b"prefix";
+        for &case in TEST_CASES {
+            let mut input = vec![0; case];
+            paint_test_input(&mut input);
+            for &group in GROUPS {
+                dbg!(case, group);
+                for (combined, (expected, expected_hash)) in [
+                    (true, encode(&input, group)),
+                    (false, outboard(&input, group)),
+                ] {
+                    // The encoding starts wherever the writer is, and the pieces don't line up
+                    // with chunks.
+                    let mut cursor = io::Cursor::new(PREFIX.to_vec());
+                    cursor.seek(io::SeekFrom::End(0)).unwrap();
+                    let mut encoder = Encoder::new_inner(cursor, group, combined);
+                    let mut rest = &input[..];
+                    for piece_len in [1, 7, 1000, 4097].iter().cycle() {
+                        if rest.is_empty() {
+                            break;
+                        }
+                        let (piece, tail) = rest.split_at(cmp::min(*piece_len, rest.len()));
+                        encoder.write_all(piece).unwrap();
+                        rest = tail;
+                    }
+                    let (hash, cursor) = encoder.finalize().unwrap();
+                    assert_eq!(expected_hash, hash);
+                    assert_eq!(cursor.position(), cursor.get_ref().len() as u64);
+                    let output = cursor.into_inner();
+                    assert_eq!(PREFIX, &output[..PREFIX.len()]);
+                    assert_eq!(expected, &output[PREFIX.len()..]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrupted_encoding() {
+        let mut input = vec![0; 5 * CHUNK_LEN + 1];
+        paint_test_input(&mut input);
+        let group = ChunkGroup::new(1);
+        let (encoded, hash) = encode(&input, group);
+        // Flip one bit in every byte position after the header. Every one of them has to be
+        // caught before the corrupt group is returned.
+        for i in HEADER_LEN..encoded.len() {
+            let mut bad = encoded.clone();
+            bad[i] ^= 1;
+            let mut decoder = Decoder::new(&bad[..], &hash, group);
+            let mut output = Vec::new();
+            let err = decoder.read_to_end(&mut output).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            // Everything returned before the error is correct.
+            assert_eq!(&input[..output.len()], &output[..]);
+        }
+        // A wrong length in the header changes the shape of the tree.
+        let mut bad = encoded.clone();
+        bad[0] ^= 1;
+        let err = decode(&bad, None, &[], &hash, group).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        // So does the wrong group size.
+        let err = decode(&encoded, None, &[], &hash, ChunkGroup::new(0)).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        // And the wrong hash fails immediately.
+        let err = decode(&encoded, None, &[], &crate::hash(b"foo"), group).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // Corrupting the input of an outboard encoding is also caught.
+        let (outboard, hash) = outboard(&input, group);
+        let mut bad_input = input.clone();
+        *bad_input.last_mut().unwrap() ^= 1;
+        let err = decode(&[], Some(&outboard), &bad_input, &hash, group).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_truncated_encoding() {
+        let mut input = vec![0; 3 * CHUNK_LEN];
+        paint_test_input(&mut input);
+        let group = ChunkGroup::new(0);
+        let (encoded, hash) = encode(&input, group);
+        for len in 0..encoded.len() {
+            let err = decode(&encoded[..len], None, &[], &hash, group).unwrap_err();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        }
+        let (outboard, hash) = outboard(&input, group);
+        for len in 0..outboard.len() {
+            let err = decode(&[], Some(&outboard[..len]), &input, &hash, group).unwrap_err();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        }
+        let err = decode(
+            &[],
+            Some(&outboard),
+            &input[..input.len() - 1],
+            &hash,
+            group,
+        );
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.unwrap_err().kind());
+    }
+
+    fn slice(encoded: &[u8], start: u64, len: u64, group: ChunkGroup) -> Vec<u8> {
+        let mut slice = Vec::new();
+        extract_slice(io::Cursor::new(encoded), start, len, group, &mut slice).unwrap();
+        slice
+    }
+
+    fn decode_slice(
+        slice: &[u8],
+        hash: &Hash,
+        start: u64,
+        len: u64,
+        group: ChunkGroup,
+    ) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        SliceDecoder::new(slice, hash, start, len, group).read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_slices() {
+        for &case in TEST_CASES {
+            let mut input = vec![0; case];
+            paint_test_input(&mut input);
+            for &group in GROUPS {
+                let (encoded, hash) = encode(&input, group);
+                let (outboard, _) = outboard(&input, group);
+                let case = case as u64;
+                let ranges = [
+                    (0, 0),
+                    (0, case),
+                    (0, u64::MAX),
+                    (1, 1),
+                    (case / 2, CHUNK_LEN as u64 + 1),
+                    (case.saturating_sub(1), 1),
+                    (case, 0),
+                    (case + 1, 100),
+                    (u64::MAX, u64::MAX),
+                ];
+                for (start, len) in ranges {
+                    dbg!(case, group, start, len);
+                    let slice = slice(&encoded, start, len, group);
+                    let mut outboard_slice = Vec::new();
+                    extract_slice_outboard(
+                        io::Cursor::new(&input),
+                        io::Cursor::new(&outboard),
+                        start,
+                        len,
+                        group,
+                        &mut outboard_slice,
+                    )
+                    .unwrap();
+                    assert_eq!(slice, outboard_slice);
+                    assert!(slice.len() <= encoded.len());
+
+                    let output = decode_slice(&slice, &hash, start, len, group).unwrap();
+                    let expected_start = cmp::min(start, case) as usize;
+                    let expected_end = cmp::min(start.saturating_add(len), case) as usize;
+                    assert_eq!(&input[expected_start..expected_end], &output[..]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_slice_size() {
+        // A small slice from the middle of a large input only needs one group per level, plus
+        // one parent node per level.
+        let input = vec![0; 1 << 20];
+        let group = ChunkGroup::new(0);
+        let (encoded, hash) = encode(&input, group);
+        let slice = slice(&encoded, 500_000, 10, group);
+        assert_eq!(HEADER_LEN + 10 * PARENT_LEN + CHUNK_LEN, slice.len());
+        let output = decode_slice(&slice, &hash, 500_000, 10, group).unwrap();
+        assert_eq!(&input[500_000..][..10], &output[..]);
+    }
+
+    #[test]
+    fn test_corrupted_slice() {
+        let mut input = vec![0; 9 * CHUNK_LEN];
+        paint_test_input(&mut input);
+        let group = ChunkGroup::new(1);
+        let (encoded, hash) = encode(&input, group);
+        let (start, len) = (3 * CHUNK_LEN as u64, 2 * CHUNK_LEN as u64);
+        let slice = slice(&encoded, start, len, group);
+        for i in HEADER_LEN..slice.len() {
+            let mut bad = slice.clone();
+            bad[i] ^= 1;
+            let err = decode_slice(&bad, &hash, start, len, group).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+        for truncated_len in 0..slice.len() {
+            let err = decode_slice(&slice[..truncated_len], &hash, start, len, group).unwrap_err();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        }
+        // Extracting from a truncated encoding fails the same way.
+        let mut output = Vec::new();
+        let truncated = io::Cursor::new(&encoded[..encoded.len() - 1]);
+        let err = extract_slice(truncated, 8 * CHUNK_LEN as u64, 1, group, &mut output);
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_decoder_error_is_sticky() {
+        let input = vec![0; 4 * CHUNK_LEN];
+        let group = ChunkGroup::new(0);
+        let (mut encoded, hash) = encode(&input, group);
+        // Corrupt the second chunk.
+        let second_chunk = HEADER_LEN + 3 * PARENT_LEN + CHUNK_LEN;
+        encoded[second_chunk] ^= 1;
+        let mut decoder = Decoder::new(&encoded[..], &hash, group);
+        let mut buf = [0; CHUNK_LEN];
+        decoder.read_exact(&mut buf).unwrap();
+        let err = decoder.read(&mut buf).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        // Later reads don't skip ahead to the next chunk.
+        let err = decoder.read(&mut buf).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
@@ -1,68 +1,207 @@
-//! This undocumented and unstable module is for use cases like the `bao` crate,
-//! which need to traverse the BLAKE3 Merkle tree and work with chunk and parent
-//! chaining values directly. There might be breaking changes to this module
-//! between patch versions.
+//! Low-level access to the BLAKE3 tree, for use cases like the `bao` crate, which need to
+//! traverse the Merkle tree and work with chunk and parent chaining values directly.
 //!
-//! We could stabilize something like this module in the future. If you have a
-//! use case for it, please let us know by filing a GitHub issue.
+//! A [`ChunkState`] hashes one chunk of input, and [`parent_output`] combines the chaining values
+//! of two children. Both produce an [`Output`], which can be turned into a non-root chaining
+//! value, a root [`struct@Hash`], or a root [`OutputReader`]. Building a correct tree out of these
+//! pieces is up to the caller. Getting the shape wrong, or finalizing
+//! the wrong node as the root, produces output that doesn't match [`hash`](crate::hash) or any
+//! other BLAKE3 function. For hashing independent subtrees with those rules checked, see the
+//! [`hazmat`](crate::hazmat) module.
+//!
+//! All three BLAKE3 modes are supported. [`ChunkState::new_keyed`] and [`parent_output`] take the
+//! key and flags of the mode: flags of 0 and [`IV_BYTES`] for the regular hash function, the
+//! [`KEYED_HASH`] flag and the 32-byte key for the keyed hash, or the [`DERIVE_KEY_MATERIAL`]
+//! flag and the context key from
+//! [`hazmat::hash_derive_key_context`](crate::hazmat::hash_derive_key_context) for key
+//! derivation. [`ChunkState::new`] and [`parent_cv`] are shortcuts for the regular hash function.
+//!
+//! # Stability
+//!
+//! This module follows the same SemVer rules as the rest of the crate. The tree structure it
+//! exposes is fixed by the BLAKE3 specification, so existing items aren't expected to change. New
+//! items may be added in minor versions. The exact values of the flag constants are part of the
+//! specification too, but callers should use the constants rather than hardcoding them.
+
+use crate::platform::Platform;
+use crate::{Hash, OutputReader, KEY_LEN};
 
+/// The number of bytes in a block, 64.
 pub const BLOCK_LEN: usize = 64;
+/// The number of bytes in a chunk, 1024.
 pub const CHUNK_LEN: usize = 1024;
 
+/// The key of the regular hash mode, which is the BLAKE3 IV as little-endian bytes. Use it with
+/// flags of 0.
+pub const IV_BYTES: [u8; KEY_LEN] = iv_bytes();
+
+const fn iv_bytes() -> [u8; KEY_LEN] {
+    let mut bytes = [0; KEY_LEN];
+    let mut i = 0;
+    while i < KEY_LEN {
+        bytes[i] = (crate::IV[i / 4] >> (8 * (i % 4))) as u8;
+        i += 1;
+    }
+    bytes
+}
+
+/// The domain separation flag for the keyed hash mode. See [`keyed_hash`](crate::keyed_hash).
+pub const KEYED_HASH: u8 = crate::KEYED_HASH;
+/// The domain separation flag for the key material half of the key derivation mode. See
+/// [`derive_key`](crate::derive_key).
+pub const DERIVE_KEY_MATERIAL: u8 = crate::DERIVE_KEY_MATERIAL;
+
+/// An incremental hasher for a single chunk of input.
 #[derive(Clone, Debug)]
 pub struct ChunkState(crate::ChunkState);
 
 impl ChunkState {
-    // Currently this type only supports the regular hash mode. If an
-    // incremental user needs keyed_hash or derive_key, we can add that.
+    /// Construct a new `ChunkState` for the regular hash mode, at the given chunk index.
     pub fn new(chunk_counter: u64) -> Self {
         Self(crate::ChunkState::new(
             crate::IV,
             chunk_counter,
             0,
-            crate::platform::Platform::detect(),
+            Platform::detect(),
+        ))
+    }
+
+    /// Construct a new `ChunkState` for any of the three modes, at the given chunk index.
+    ///
+    /// `flags` must be 0, with `key` being [`IV_BYTES`], or [`KEYED_HASH`], with `key` being the
+    /// keyed hash key, or [`DERIVE_KEY_MATERIAL`], with `key` being the context key. Every chunk
+    /// and parent node in the same tree must use the same key and flags.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `flags` is anything else, or if `flags` is 0 and `key` isn't [`IV_BYTES`].
+    pub fn new_keyed(key: &[u8; KEY_LEN], chunk_counter: u64, flags: u8) -> Self {
+        assert_mode_flags(key, flags);
+        Self(crate::ChunkState::new(
+            &crate::platform::words_from_le_bytes_32(key),
+            chunk_counter,
+            flags,
+            Platform::detect(),
         ))
     }
 
+    /// The number of input bytes this chunk has absorbed so far, at most [`CHUNK_LEN`].
     #[inline]
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
+    /// Add input bytes to the chunk. The total must not exceed [`CHUNK_LEN`].
     #[inline]
     pub fn update(&mut self, input: &[u8]) -> &mut Self {
         self.0.update(input);
         self
     }
 
-    pub fn finalize(&self, is_root: bool) -> crate::Hash {
-        let output = self.0.output();
+    /// The [`Output`] of this chunk, which can be finalized as either a chaining value or the
+    /// root.
+    pub fn output(&self) -> Output {
+        Output(self.0.output())
+    }
+
+    /// Finalize the chunk, as either the root or a non-root chaining value. This is equivalent
+    /// to calling [`Output::root_hash`] or [`Output::chaining_value`] on
+    /// [`output`](ChunkState::output).
+    pub fn finalize(&self, is_root: bool) -> Hash {
+        let output = self.output();
         if is_root {
             output.root_hash()
         } else {
-            output.chaining_value().into()
+            output.chaining_value()
         }
     }
 }
 
-// As above, this currently assumes the regular hash mode. If an incremental
-// user needs keyed_hash or derive_key, we can add that.
-pub fn parent_cv(
-    left_child: &crate::Hash,
-    right_child: &crate::Hash,
-    is_root: bool,
-) -> crate::Hash {
-    let output = crate::parent_node_output(
+fn assert_mode_flags(key: &[u8; KEY_LEN], flags: u8) {
+    assert!(
+        flags == 0 || flags == KEYED_HASH || flags == DERIVE_KEY_MATERIAL,
+        "flags must be 0, KEYED_HASH, or DERIVE_KEY_MATERIAL",
+    );
+    // Any other key with flags of 0 would be a hash function that isn't BLAKE3.
+    assert!(flags != 0 || *key == IV_BYTES, "flags of 0 need IV_BYTES");
+}
+
+/// The state of a chunk or parent node just before finalization.
+///
+/// Any node can produce a 32-byte chaining value, for use in its parent. The root node can
+/// instead produce the root hash or any amount of extended output. Callers are responsible for
+/// finalizing only the actual root of the tree as the root.
+// Don't derive(Debug), because the state may be secret.
+#[derive(Clone)]
+pub struct Output(crate::Output);
+
+impl Output {
+    /// The non-root chaining value of this node.
+    pub fn chaining_value(&self) -> Hash {
+        self.0.chaining_value().into()
+    }
+
+    /// The root hash, assuming this node is the root of the tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is the output of a chunk other than chunk 0, since that can never be the
+    /// root.
+    pub fn root_hash(&self) -> Hash {
+        assert_eq!(self.0.counter, 0, "only chunk 0 can be the root");
+        self.0.root_hash()
+    }
+
+    /// An [`OutputReader`] for extended output, assuming this node is the root of the tree. The
+    /// first 32 bytes of output are the same as [`root_hash`](Output::root_hash).
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is the output of a chunk other than chunk 0, since that can never be the
+    /// root.
+    pub fn root_output_reader(&self) -> OutputReader {
+        assert_eq!(self.0.counter, 0, "only chunk 0 can be the root");
+        OutputReader::new(self.0.clone())
+    }
+}
+
+/// The [`Output`] of a parent node in any of the three modes, given the chaining values of its two
+/// children. The `key` and `flags` are the same as for [`ChunkState::new_keyed`].
+///
+/// # Panics
+///
+/// Panics if `flags` is anything other than 0, [`KEYED_HASH`], or [`DERIVE_KEY_MATERIAL`], or if
+/// `flags` is 0 and `key` isn't [`IV_BYTES`].
+pub fn parent_output(
+    left_child: &Hash,
+    right_child: &Hash,
+    key: &[u8; KEY_LEN],
+    flags: u8,
+) -> Output {
+    assert_mode_flags(key, flags);
+    Output(crate::parent_node_output(
+        left_child.as_bytes(),
+        right_child.as_bytes(),
+        &crate::platform::words_from_le_bytes_32(key),
+        flags,
+        Platform::detect(),
+    ))
+}
+
+/// Compute the chaining value of a parent node in the regular hash mode, or the root hash if
+/// `is_root` is true, given the chaining values of its two children.
+pub fn parent_cv(left_child: &Hash, right_child: &Hash, is_root: bool) -> Hash {
+    let output = Output(crate::parent_node_output(
         left_child.as_bytes(),
         right_child.as_bytes(),
         crate::IV,
         0,
-        crate::platform::Platform::detect(),
-    );
+        Platform::detect(),
+    ));
     if is_root {
         output.root_hash()
     } else {
-        output.chaining_value().into()
+        output.chaining_value()
     }
 }
 
@@ -98,4 +237,108 @@ mod test {
         let root = parent_cv(&parent, &chunk2_cv, true);
         assert_eq!(hasher.finalize(), root);
     }
+
+    #[test]
+    fn test_keyed_modes() {
+        const CONTEXT: &str = "BLAKE3 2019-12-27 16:13:59 guts test context";
+        let key = crate::test::TEST_KEY;
+        let context_key = crate::hazmat::hash_derive_key_context(CONTEXT);
+        let mut input = [0; 2 * CHUNK_LEN + 1];
+        crate::test::paint_test_input(&mut input);
+        let cases = [
+            (crate::Hasher::new_keyed(&key), key, KEYED_HASH),
+            (
+                crate::Hasher::new_derive_key(CONTEXT),
+                context_key,
+                DERIVE_KEY_MATERIAL,
+            ),
+        ];
+        for (mut hasher, key, flags) in cases {
+            hasher.update(&input);
+
+            // A single chunk as the root.
+            let chunk = ChunkState::new_keyed(&key, 0, flags)
+                .update(b"foo")
+                .output();
+            let mut single_chunk_hasher = hasher.clone();
+            single_chunk_hasher.reset().update(b"foo");
+            assert_eq!(single_chunk_hasher.finalize(), chunk.root_hash());
+
+            // Three chunks, with extended output from the root parent.
+            let chunk0_cv = ChunkState::new_keyed(&key, 0, flags)
+                .update(&input[..CHUNK_LEN])
+                .output()
+                .chaining_value();
+            let chunk1_cv = ChunkState::new_keyed(&key, 1, flags)
+                .update(&input[CHUNK_LEN..][..CHUNK_LEN])
+                .output()
+                .chaining_value();
+            let chunk2_cv = ChunkState::new_keyed(&key, 2, flags)
+                .update(&input[2 * CHUNK_LEN..])
+                .output()
+                .chaining_value();
+            let parent = parent_output(&chunk0_cv, &chunk1_cv, &key, flags).chaining_value();
+            let root = parent_output(&parent, &chunk2_cv, &key, flags);
+            assert_eq!(hasher.finalize(), root.root_hash());
+
+            let mut expected = [0; 100];
+            hasher.finalize_xof().fill(&mut expected);
+            let mut output = [0; 100];
+            root.root_output_reader().fill(&mut output);
+            assert_eq!(expected, output);
+        }
+    }
+
+    #[test]
+    fn test_regular_mode_outputs() {
+        let mut input = [0; 2 * CHUNK_LEN + 1];
+        crate::test::paint_test_input(&mut input);
+        assert_eq!(
+            ChunkState::new(0).update(b"foo").finalize(true),
+            ChunkState::new_keyed(&IV_BYTES, 0, 0)
+                .update(b"foo")
+                .output()
+                .root_hash(),
+        );
+
+        let chunk_cvs: [Hash; 3] = core::array::from_fn(|i| {
+            let chunk =
+                &input[i * CHUNK_LEN..][..core::cmp::min(CHUNK_LEN, input.len() - i * CHUNK_LEN)];
+            ChunkState::new_keyed(&IV_BYTES, i as u64, 0)
+                .update(chunk)
+                .output()
+                .chaining_value()
+        });
+        let parent = parent_output(&chunk_cvs[0], &chunk_cvs[1], &IV_BYTES, 0).chaining_value();
+        let root = parent_output(&parent, &chunk_cvs[2], &IV_BYTES, 0);
+        assert_eq!(crate::hash(&input), root.root_hash());
+        assert_eq!(parent_cv(&parent, &chunk_cvs[2], true), root.root_hash());
+
+        let mut expected = [0; 100];
+        crate::Hasher::new()
+            .update(&input)
+            .finalize_xof()
+            .fill(&mut expected);
+        let mut output = [0; 100];
+        root.root_output_reader().fill(&mut output);
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_regular_mode_other_key() {
+        parent_output(&[0; 32].into(), &[0; 32].into(), &[0; KEY_LEN], 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_new_keyed_bad_flags() {
+        ChunkState::new_keyed(&[0; KEY_LEN], 0, crate::ROOT);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_root_of_later_chunk() {
+        ChunkState::new(1).update(b"foo").output().root_hash();
+    }
 }
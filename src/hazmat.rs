@@ -0,0 +1,671 @@
+//! Low-level tree hashing, for splitting one input across multiple workers.
+//!
+//! BLAKE3 is a Merkle tree, and a large enough input can be divided into chunk-aligned subtrees
+//! that are hashed independently, for example on different machines, and then merged into the
+//! same root hash that [`hash`](crate::hash) or [`Hasher`](crate::Hasher) would have produced.
+//! [`Subtree::hash`] computes the non-root chaining value of one subtree,
+//! [`merge_subtrees_non_root`] combines two sibling subtrees into their parent, and
+//! [`merge_subtrees_root`] and [`merge_subtrees_root_xof`] combine an ordered list of subtrees
+//! covering the whole input into the final [`Hash`](struct@Hash) or [`OutputReader`].
+//!
+//! Not every split is valid. A subtree must start at a multiple of the 1024-byte chunk length, and
+//! it can't be any longer than [`max_subtree_len`] of its starting offset. Every subtree except
+//! the last one must also be a power-of-two number of chunks. Violations of these rules are
+//! reported as a [`SubtreeError`], rather than silently producing a wrong hash.
+//!
+//! **Warning:** These functions are "hazardous material". Chaining values aren't hashes, and
+//! mixing them up, or merging subtrees that came from different inputs or different modes, gives
+//! output that looks fine but doesn't match anything. Most callers should use
+//! [`Hasher::update_rayon`](crate::Hasher::update_rayon) for multithreading instead.
+//!
+//! # Example
+//!
+//! ```
+//! use blake3::hazmat::{merge_subtrees_root, Mode, Subtree};
+//! use blake3::guts::CHUNK_LEN;
+//!
+//! # fn main() -> Result<(), blake3::hazmat::SubtreeError> {
+//! let input = vec![0xab; 5 * CHUNK_LEN + 1];
+//! // Two workers hash the first four chunks and everything after them.
+//! let (left, right) = input.split_at(4 * CHUNK_LEN);
+//! let left_subtree = Subtree::hash(Mode::Hash, 0, left)?;
+//! let right_subtree = Subtree::hash(Mode::Hash, left.len() as u64, right)?;
+//! let root = merge_subtrees_root(&[left_subtree, right_subtree], Mode::Hash)?;
+//! assert_eq!(root, blake3::hash(&input));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::platform::{self, Platform};
+use crate::{join, CVWords, Hash, OutputReader, CHUNK_LEN, KEY_LEN, OUT_LEN};
+use core::fmt;
+
+/// The 32-byte chaining value of a non-root subtree.
+pub type ChainingValue = [u8; OUT_LEN];
+
+/// The key that the [`derive_key`](crate::derive_key) mode derives from its context string. See
+/// [`hash_derive_key_context`].
+pub type ContextKey = [u8; KEY_LEN];
+
+/// Hash a [`derive_key`](crate::derive_key) context string into the key used by
+/// [`Mode::DeriveKeyMaterial`].
+///
+/// Workers can compute this once and share it, rather than each rehashing the context string.
+pub fn hash_derive_key_context(context: &str) -> ContextKey {
+    crate::hash_all_at_once::<join::SerialJoin>(
+        context.as_bytes(),
+        crate::IV,
+        crate::DERIVE_KEY_CONTEXT,
+    )
+    .root_hash()
+    .0
+}
+
+/// Which of the three BLAKE3 modes a subtree belongs to.
+///
+/// Every subtree that gets merged together must use the same mode, with the same key or context
+/// key.
+#[derive(Clone, Copy)]
+pub enum Mode<'a> {
+    /// The regular hash function, as in [`hash`](crate::hash).
+    Hash,
+    /// The keyed hash function, as in [`keyed_hash`](crate::keyed_hash).
+    KeyedHash(&'a [u8; KEY_LEN]),
+    /// The key material half of [`derive_key`](crate::derive_key), using the key returned by
+    /// [`hash_derive_key_context`].
+    DeriveKeyMaterial(&'a ContextKey),
+}
+
+impl Mode<'_> {
+    pub(crate) fn key_words(&self) -> CVWords {
+        match self {
+            Mode::Hash => *crate::IV,
+            Mode::KeyedHash(key) => platform::words_from_le_bytes_32(key),
+            Mode::DeriveKeyMaterial(context_key) => platform::words_from_le_bytes_32(context_key),
+        }
+    }
+
+    pub(crate) fn flags_byte(&self) -> u8 {
+        match self {
+            Mode::Hash => 0,
+            Mode::KeyedHash(_) => crate::KEYED_HASH,
+            Mode::DeriveKeyMaterial(_) => crate::DERIVE_KEY_MATERIAL,
+        }
+    }
+}
+
+/// The longest subtree that can start at `input_offset`, or `None` if there's no limit.
+///
+/// A subtree starting at offset 0 can be any length. Otherwise the limit is the largest power of
+/// two that divides the offset, so for example a subtree starting at `4 * CHUNK_LEN` can be at
+/// most `4 * CHUNK_LEN` bytes long.
+///
+/// # Panics
+///
+/// Panics if `input_offset` isn't a multiple of the chunk length.
+pub fn max_subtree_len(input_offset: u64) -> Option<u64> {
+    assert_eq!(
+        input_offset % CHUNK_LEN as u64,
+        0,
+        "input offset must be chunk-aligned",
+    );
+    if input_offset == 0 {
+        return None;
+    }
+    Some(1 << input_offset.trailing_zeros())
+}
+
+/// The length of the left subtree, given the combined length of a parent node's input.
+///
+/// This is the largest power-of-two number of chunks that leaves at least one byte for the right
+/// subtree. Callers deciding how to divide up an input can use this to find valid split points.
+///
+/// # Panics
+///
+/// Panics if `input_len` is one chunk or less, because then there's no parent node.
+pub fn left_subtree_len(input_len: u64) -> u64 {
+    assert!(input_len > CHUNK_LEN as u64, "no parent node");
+    // Subtract 1 to reserve at least one byte for the right side.
+    let full_chunks = (input_len - 1) / CHUNK_LEN as u64;
+    (1 << (63 - full_chunks.leading_zeros())) * CHUNK_LEN as u64
+}
+
+// Whether `len` is a power-of-two number of whole chunks. CHUNK_LEN is itself a power of two.
+fn is_complete_subtree_len(len: u64) -> bool {
+    len >= CHUNK_LEN as u64 && len.is_power_of_two()
+}
+
+fn check_subtree_bounds(input_offset: u64, input_len: u64) -> Result<(), SubtreeError> {
+    if input_offset.trailing_zeros() < CHUNK_LEN.trailing_zeros() {
+        return Err(SubtreeError(SubtreeErrorInner::Misaligned));
+    }
+    if input_len == 0 {
+        return Err(SubtreeError(SubtreeErrorInner::Empty));
+    }
+    if input_offset.checked_add(input_len).is_none() {
+        return Err(SubtreeError(SubtreeErrorInner::TooLong));
+    }
+    if let Some(max_len) = max_subtree_len(input_offset) {
+        if input_len > max_len {
+            return Err(SubtreeError(SubtreeErrorInner::TooLong));
+        }
+    }
+    Ok(())
+}
+
+// The output of the subtree at `input_offset`, which has already been checked to be valid. For a
+// subtree at offset 0 this could still be the root.
+pub(crate) fn subtree_output<J: join::Join>(
+    key: &CVWords,
+    flags: u8,
+    input_offset: u64,
+    input: &[u8],
+) -> crate::Output {
+    let platform = Platform::detect();
+    let chunk_counter = input_offset / CHUNK_LEN as u64;
+    if input.len() <= CHUNK_LEN {
+        crate::ChunkState::new(key, chunk_counter, flags, platform)
+            .update(input)
+            .output()
+    } else {
+        crate::Output {
+            input_chaining_value: *key,
+            block: crate::compress_subtree_to_parent_node::<J>(
+                input,
+                key,
+                chunk_counter,
+                flags,
+                platform,
+            ),
+            block_len: crate::BLOCK_LEN as u8,
+            counter: 0,
+            flags: flags | crate::PARENT,
+            platform,
+        }
+    }
+}
+
+/// The non-root chaining value of a chunk-aligned range of the input.
+///
+/// Returned by [`Subtree::hash`] and [`merge_subtrees_non_root`], and consumed by
+/// [`merge_subtrees_root`]. A `Subtree` remembers where in the input it came from, so that the
+/// merge functions can check that it fits the shape of the BLAKE3 tree.
+#[derive(Clone, Copy, Debug)]
+pub struct Subtree {
+    input_offset: u64,
+    input_len: u64,
+    cv: ChainingValue,
+}
+
+impl Subtree {
+    fn hash_with_join<J: join::Join>(
+        mode: Mode,
+        input_offset: u64,
+        input: &[u8],
+    ) -> Result<Self, SubtreeError> {
+        check_subtree_bounds(input_offset, input.len() as u64)?;
+        let output = subtree_output::<J>(&mode.key_words(), mode.flags_byte(), input_offset, input);
+        Ok(Self {
+            input_offset,
+            input_len: input.len() as u64,
+            cv: output.chaining_value(),
+        })
+    }
+
+    /// Hash the subtree of the input that starts at `input_offset` and contains `input`.
+    ///
+    /// `input_offset` must be a multiple of the chunk length, `input` must not be empty, and its
+    /// length must not exceed [`max_subtree_len`] of the offset. Otherwise this returns an error.
+    ///
+    /// This function is always single-threaded. For multithreading support, see
+    /// [`Subtree::hash_rayon`].
+    pub fn hash(mode: Mode, input_offset: u64, input: &[u8]) -> Result<Self, SubtreeError> {
+        Self::hash_with_join::<join::SerialJoin>(mode, input_offset, input)
+    }
+
+    /// As [`Subtree::hash`], but using Rayon-based multithreading internally.
+    ///
+    /// This method is gated by the `rayon` Cargo feature, which is disabled by default but
+    /// enabled on [docs.rs](https://docs.rs).
+    #[cfg(feature = "rayon")]
+    pub fn hash_rayon(mode: Mode, input_offset: u64, input: &[u8]) -> Result<Self, SubtreeError> {
+        Self::hash_with_join::<join::RayonJoin>(mode, input_offset, input)
+    }
+
+    /// Reassemble a `Subtree` from its parts, for example after receiving them from another
+    /// machine. The bounds are checked the same way as in [`Subtree::hash`], but of course the
+    /// chaining value can't be.
+    pub fn from_parts(
+        input_offset: u64,
+        input_len: u64,
+        cv: ChainingValue,
+    ) -> Result<Self, SubtreeError> {
+        check_subtree_bounds(input_offset, input_len)?;
+        Ok(Self {
+            input_offset,
+            input_len,
+            cv,
+        })
+    }
+
+    /// The offset of the start of this subtree in the input.
+    pub fn input_offset(&self) -> u64 {
+        self.input_offset
+    }
+
+    /// The number of input bytes this subtree covers.
+    pub fn input_len(&self) -> u64 {
+        self.input_len
+    }
+
+    /// The non-root chaining value of this subtree.
+    pub fn chaining_value(&self) -> &ChainingValue {
+        &self.cv
+    }
+}
+
+/// Combine two sibling subtrees into the subtree of their parent node.
+///
+/// `right` must start where `left` ends, and `left` must be exactly the left half of their
+/// parent, as given by [`left_subtree_len`]. The parent itself must also be a valid subtree at
+/// `left`'s offset. The result is never a root; to finish the hash, see [`merge_subtrees_root`].
+pub fn merge_subtrees_non_root(
+    left: &Subtree,
+    right: &Subtree,
+    mode: Mode,
+) -> Result<Subtree, SubtreeError> {
+    if left.input_offset + left.input_len != right.input_offset {
+        return Err(SubtreeError(SubtreeErrorInner::NotContiguous));
+    }
+    let input_len = left.input_len + right.input_len;
+    check_subtree_bounds(left.input_offset, input_len)?;
+    if left.input_len != left_subtree_len(input_len) {
+        return Err(SubtreeError(SubtreeErrorInner::WrongSplit));
+    }
+    let output = crate::parent_node_output(
+        &left.cv,
+        &right.cv,
+        &mode.key_words(),
+        mode.flags_byte(),
+        Platform::detect(),
+    );
+    Ok(Subtree {
+        input_offset: left.input_offset,
+        input_len,
+        cv: output.chaining_value(),
+    })
+}
+
+// Compute the chaining value of the tree node covering `input_len` bytes at `input_offset`, using
+// the subtrees that start at or after it. The subtrees have already been checked to be
+// contiguous, so any mismatch here means a subtree doesn't line up with the tree.
+fn node_cv(
+    subtrees: &[Subtree],
+    input_offset: u64,
+    input_len: u64,
+    key: &CVWords,
+    flags: u8,
+    platform: Platform,
+) -> Result<ChainingValue, SubtreeError> {
+    let index = subtrees
+        .binary_search_by_key(&input_offset, |s| s.input_offset)
+        .map_err(|_| SubtreeError(SubtreeErrorInner::WrongSplit))?;
+    let subtree = &subtrees[index];
+    if subtree.input_len == input_len {
+        return Ok(subtree.cv);
+    }
+    if subtree.input_len > input_len || input_len <= CHUNK_LEN as u64 {
+        return Err(SubtreeError(SubtreeErrorInner::WrongSplit));
+    }
+    let left_len = left_subtree_len(input_len);
+    let left_cv = node_cv(subtrees, input_offset, left_len, key, flags, platform)?;
+    let right_cv = node_cv(
+        subtrees,
+        input_offset + left_len,
+        input_len - left_len,
+        key,
+        flags,
+        platform,
+    )?;
+    Ok(crate::parent_node_output(&left_cv, &right_cv, key, flags, platform).chaining_value())
+}
+
+fn merge_subtrees_root_output(
+    subtrees: &[Subtree],
+    mode: Mode,
+) -> Result<crate::Output, SubtreeError> {
+    // A single subtree would be the whole input, and a root can't be computed from a chaining
+    // value. Hash the input directly in that case.
+    if subtrees.len() < 2 {
+        return Err(SubtreeError(SubtreeErrorInner::TooFewSubtrees));
+    }
+    if subtrees[0].input_offset != 0 {
+        return Err(SubtreeError(SubtreeErrorInner::NotAtStart));
+    }
+    for pair in subtrees.windows(2) {
+        if !is_complete_subtree_len(pair[0].input_len) {
+            return Err(SubtreeError(SubtreeErrorInner::Incomplete));
+        }
+        if pair[0].input_offset + pair[0].input_len != pair[1].input_offset {
+            return Err(SubtreeError(SubtreeErrorInner::NotContiguous));
+        }
+    }
+    let last = subtrees.last().unwrap();
+    let input_len = last.input_offset + last.input_len;
+    let key = mode.key_words();
+    let flags = mode.flags_byte();
+    let platform = Platform::detect();
+    // The first subtree is a complete chunk and there's at least one more, so there's a root
+    // parent node.
+    let left_len = left_subtree_len(input_len);
+    let left_cv = node_cv(subtrees, 0, left_len, &key, flags, platform)?;
+    let right_cv = node_cv(
+        subtrees,
+        left_len,
+        input_len - left_len,
+        &key,
+        flags,
+        platform,
+    )?;
+    Ok(crate::parent_node_output(
+        &left_cv, &right_cv, &key, flags, platform,
+    ))
+}
+
+/// Combine an ordered list of subtrees, covering the whole input, into the root hash.
+///
+/// The subtrees must be contiguous, starting at offset 0, and every subtree except the last must
+/// be a power-of-two number of chunks. There must be at least two of them; an input that fits in
+/// a single subtree should be hashed directly. The result is the same as hashing the whole input
+/// with [`hash`](crate::hash), [`keyed_hash`](crate::keyed_hash), or
+/// [`derive_key`](crate::derive_key), depending on the mode.
+pub fn merge_subtrees_root(subtrees: &[Subtree], mode: Mode) -> Result<Hash, SubtreeError> {
+    Ok(merge_subtrees_root_output(subtrees, mode)?.root_hash())
+}
+
+/// As [`merge_subtrees_root`], but returning an [`OutputReader`] for extended output.
+pub fn merge_subtrees_root_xof(
+    subtrees: &[Subtree],
+    mode: Mode,
+) -> Result<OutputReader, SubtreeError> {
+    Ok(OutputReader::new(merge_subtrees_root_output(
+        subtrees, mode,
+    )?))
+}
+
+/// The error type for [`Subtree::hash`] and the subtree merge functions.
+///
+/// The `.to_string()` representation of this error currently describes which rule was violated.
+/// This is to help with logging and debugging, but it isn't a stable API detail, and it may change
+/// at any time.
+#[derive(Clone, Debug)]
+pub struct SubtreeError(SubtreeErrorInner);
+
+#[derive(Clone, Debug)]
+enum SubtreeErrorInner {
+    Misaligned,
+    Empty,
+    TooLong,
+    NotContiguous,
+    WrongSplit,
+    Incomplete,
+    NotAtStart,
+    TooFewSubtrees,
+}
+
+impl fmt::Display for SubtreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            SubtreeErrorInner::Misaligned => {
+                write!(f, "subtree offset is not a multiple of the chunk length")
+            }
+            SubtreeErrorInner::Empty => write!(f, "subtree is empty"),
+            SubtreeErrorInner::TooLong => write!(f, "subtree is too long for its offset"),
+            SubtreeErrorInner::NotContiguous => write!(f, "subtrees are not contiguous"),
+            SubtreeErrorInner::WrongSplit => {
+                write!(f, "subtrees don't match the shape of the tree")
+            }
+            SubtreeErrorInner::Incomplete => write!(
+                f,
+                "only the last subtree can be a partial or non-power-of-two number of chunks"
+            ),
+            SubtreeErrorInner::NotAtStart => write!(f, "first subtree doesn't start at offset 0"),
+            SubtreeErrorInner::TooFewSubtrees => {
+                write!(f, "merging a root requires at least two subtrees")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SubtreeError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{paint_test_input, TEST_KEY};
+    use arrayvec::ArrayVec;
+    use core::cmp;
+
+    #[test]
+    fn test_max_subtree_len() {
+        assert_eq!(max_subtree_len(0), None);
+        assert_eq!(max_subtree_len(CHUNK_LEN as u64), Some(CHUNK_LEN as u64));
+        assert_eq!(
+            max_subtree_len(6 * CHUNK_LEN as u64),
+            Some(2 * CHUNK_LEN as u64)
+        );
+        assert_eq!(
+            max_subtree_len(8 * CHUNK_LEN as u64),
+            Some(8 * CHUNK_LEN as u64)
+        );
+        assert_eq!(max_subtree_len(1 << 63), Some(1 << 63));
+    }
+
+    #[test]
+    fn test_left_subtree_len() {
+        for len in CHUNK_LEN + 1..=20 * CHUNK_LEN {
+            assert_eq!(crate::left_len(len) as u64, left_subtree_len(len as u64));
+        }
+        assert_eq!(1 << 62, left_subtree_len(1 << 63));
+        assert_eq!(1 << 63, left_subtree_len((1 << 63) + 1));
+        assert_eq!(1 << 63, left_subtree_len(u64::MAX));
+    }
+
+    // Split the input into the largest valid subtrees, greedily from left to right, and check that
+    // merging them gives the same result as hashing the whole input with `hasher`. Then merge the
+    // first two subtrees with merge_subtrees_non_root(), and check the result again.
+    fn check_greedy_split(mode: Mode, input: &[u8], mut hasher: crate::Hasher) {
+        hasher.update(input);
+        let mut subtrees = ArrayVec::<Subtree, 64>::new();
+        let mut offset = 0;
+        while offset < input.len() {
+            let len = match max_subtree_len(offset as u64) {
+                // Start with a one-chunk subtree, so that there's usually more than one.
+                None => CHUNK_LEN,
+                Some(max) => max as usize,
+            };
+            let len = cmp::min(len, input.len() - offset);
+            subtrees.push(Subtree::hash(mode, offset as u64, &input[offset..][..len]).unwrap());
+            offset += len;
+        }
+        if subtrees.len() < 2 {
+            assert!(matches!(
+                merge_subtrees_root(&subtrees, mode).unwrap_err().0,
+                SubtreeErrorInner::TooFewSubtrees
+            ));
+            return;
+        }
+        assert_eq!(
+            hasher.finalize(),
+            merge_subtrees_root(&subtrees, mode).unwrap()
+        );
+        let mut expected_xof = [0; 200];
+        let mut xof = [0; 200];
+        hasher.finalize_xof().fill(&mut expected_xof);
+        merge_subtrees_root_xof(&subtrees, mode)
+            .unwrap()
+            .fill(&mut xof);
+        assert_eq!(expected_xof, xof);
+
+        // The first two subtrees are always single chunks, so they're siblings. Merging them
+        // shouldn't change the root, as long as there's still more than one subtree left.
+        if subtrees.len() > 2 {
+            let parent = merge_subtrees_non_root(&subtrees[0], &subtrees[1], mode).unwrap();
+            let whole = Subtree::hash(mode, 0, &input[..2 * CHUNK_LEN]).unwrap();
+            assert_eq!(whole.chaining_value(), parent.chaining_value());
+            subtrees.remove(0);
+            subtrees[0] = parent;
+            assert_eq!(
+                hasher.finalize(),
+                merge_subtrees_root(&subtrees, mode).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_subtrees_matches_hash() {
+        const CONTEXT: &str = "BLAKE3 2019-12-27 16:13:59 example context";
+        let context_key = hash_derive_key_context(CONTEXT);
+        let mut input = [0; crate::test::TEST_CASES_MAX];
+        paint_test_input(&mut input);
+        for &case in crate::test::TEST_CASES {
+            #[cfg(feature = "std")]
+            dbg!(case);
+            let input = &input[..case];
+            check_greedy_split(Mode::Hash, input, crate::Hasher::new());
+            check_greedy_split(
+                Mode::KeyedHash(&TEST_KEY),
+                input,
+                crate::Hasher::new_keyed(&TEST_KEY),
+            );
+            check_greedy_split(
+                Mode::DeriveKeyMaterial(&context_key),
+                input,
+                crate::Hasher::new_derive_key(CONTEXT),
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_single_chunks() {
+        // Hashing every chunk separately means the merge has to rebuild every level of the tree.
+        let mut input = [0; crate::test::TEST_CASES_MAX];
+        paint_test_input(&mut input);
+        for &case in crate::test::TEST_CASES {
+            if case <= CHUNK_LEN {
+                continue;
+            }
+            #[cfg(feature = "std")]
+            dbg!(case);
+            let input = &input[..case];
+            let subtrees: ArrayVec<Subtree, 128> = input
+                .chunks(CHUNK_LEN)
+                .enumerate()
+                .map(|(i, chunk)| Subtree::hash(Mode::Hash, (i * CHUNK_LEN) as u64, chunk).unwrap())
+                .collect();
+            assert_eq!(
+                crate::hash(input),
+                merge_subtrees_root(&subtrees, Mode::Hash).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn test_hash_rayon() {
+        let mut input = [0; 16 * CHUNK_LEN];
+        paint_test_input(&mut input);
+        let offset = 16 * CHUNK_LEN as u64;
+        assert_eq!(
+            Subtree::hash(Mode::Hash, offset, &input)
+                .unwrap()
+                .chaining_value(),
+            Subtree::hash_rayon(Mode::Hash, offset, &input)
+                .unwrap()
+                .chaining_value(),
+        );
+    }
+
+    #[test]
+    fn test_subtree_errors() {
+        let input = [0; 4 * CHUNK_LEN];
+        let chunk = CHUNK_LEN as u64;
+        let err = |result: Result<Subtree, SubtreeError>| result.unwrap_err().0;
+        assert!(matches!(
+            err(Subtree::hash(Mode::Hash, 1, &input)),
+            SubtreeErrorInner::Misaligned
+        ));
+        assert!(matches!(
+            err(Subtree::hash(Mode::Hash, chunk, &[])),
+            SubtreeErrorInner::Empty
+        ));
+        assert!(matches!(
+            err(Subtree::hash(
+                Mode::Hash,
+                2 * chunk,
+                &input[..3 * CHUNK_LEN]
+            )),
+            SubtreeErrorInner::TooLong
+        ));
+        assert!(matches!(
+            err(Subtree::from_parts(u64::MAX - chunk + 1, chunk, [0; 32])),
+            SubtreeErrorInner::TooLong
+        ));
+
+        let a = Subtree::hash(Mode::Hash, 0, &input[..CHUNK_LEN]).unwrap();
+        let b = Subtree::hash(Mode::Hash, chunk, &input[..CHUNK_LEN]).unwrap();
+        let c = Subtree::hash(Mode::Hash, 2 * chunk, &input[..2 * CHUNK_LEN]).unwrap();
+        let short = Subtree::hash(Mode::Hash, chunk, &input[..100]).unwrap();
+        assert!(matches!(
+            err(merge_subtrees_non_root(&a, &c, Mode::Hash)),
+            SubtreeErrorInner::NotContiguous
+        ));
+        // b and c are contiguous, but their parent would start at an odd chunk.
+        assert!(matches!(
+            err(merge_subtrees_non_root(&b, &c, Mode::Hash)),
+            SubtreeErrorInner::TooLong
+        ));
+        // a and the merged a+b aren't siblings, because the right side would be bigger.
+        let ab = merge_subtrees_non_root(&a, &b, Mode::Hash).unwrap();
+        let ab_c = merge_subtrees_non_root(&ab, &c, Mode::Hash).unwrap();
+        assert_eq!(4 * chunk, ab_c.input_len());
+        assert!(matches!(
+            err(merge_subtrees_non_root(&a, &ab, Mode::Hash)),
+            SubtreeErrorInner::NotContiguous
+        ));
+        let d = Subtree::hash(Mode::Hash, 0, &input[..2 * CHUNK_LEN]).unwrap();
+        assert!(matches!(
+            err(merge_subtrees_non_root(
+                &a,
+                &Subtree {
+                    input_offset: chunk,
+                    ..d
+                },
+                Mode::Hash
+            )),
+            SubtreeErrorInner::WrongSplit
+        ));
+
+        let root_err =
+            |subtrees: &[Subtree]| merge_subtrees_root(subtrees, Mode::Hash).unwrap_err().0;
+        assert!(matches!(root_err(&[]), SubtreeErrorInner::TooFewSubtrees));
+        assert!(matches!(root_err(&[a]), SubtreeErrorInner::TooFewSubtrees));
+        assert!(matches!(root_err(&[b, c]), SubtreeErrorInner::NotAtStart));
+        assert!(matches!(
+            root_err(&[a, c]),
+            SubtreeErrorInner::NotContiguous
+        ));
+        assert!(matches!(
+            root_err(&[a, short, c]),
+            SubtreeErrorInner::Incomplete
+        ));
+        // Three chunks followed by more input isn't a node in the tree.
+        let abc = Subtree::hash(Mode::Hash, 0, &input[..3 * CHUNK_LEN]).unwrap();
+        let e = Subtree::hash(Mode::Hash, 3 * chunk, &input[..CHUNK_LEN]).unwrap();
+        assert!(matches!(root_err(&[abc, e]), SubtreeErrorInner::Incomplete));
+        // a, b, and c are fine, and so is a partial final chunk.
+        merge_subtrees_root(&[a, b, c], Mode::Hash).unwrap();
+        merge_subtrees_root(&[a, short], Mode::Hash).unwrap();
+    }
+}
@@ -21,9 +21,237 @@ pub(crate) fn copy_wide(
     }
 }
 
-// Mmap a file, if it looks like a good idea. Return None in cases where we know mmap will fail, or
-// if the file is short enough that mmapping isn't worth it. However, if we do try to mmap and it
-// fails, return the error.
+// Fill the buffer from the reader, stopping early only at EOF.
+#[cfg(feature = "rayon")]
+fn read_full(mut reader: impl std::io::Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            // see test_update_reader_interrupted
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// A multithreaded copy_wide, with two buffers of `buffer_len` bytes. One buffer is hashed with
+// update_rayon while the other is read into. Every buffer but the last is completely full, and
+// buffer_len is a power of two, so after the first one the hasher stays aligned to whole subtrees
+// of that size, and update_rayon can hash each buffer as wide as possible.
+#[cfg(feature = "rayon")]
+pub(crate) fn copy_wide_rayon(
+    mut reader: impl std::io::Read + Send,
+    hasher: &mut crate::Hasher,
+    buffer_len: usize,
+) -> std::io::Result<u64> {
+    assert!(
+        buffer_len >= crate::CHUNK_LEN && buffer_len.is_power_of_two(),
+        "buffer length must be a power of two and at least one chunk",
+    );
+    let mut current = vec![0; buffer_len];
+    let mut next = vec![0; buffer_len];
+    let mut current_len = read_full(&mut reader, &mut current)?;
+    let mut total = current_len as u64;
+    while current_len == buffer_len {
+        let (_, next_len) = rayon::join(
+            || hasher.update_rayon(&current),
+            || read_full(&mut reader, &mut next),
+        );
+        current_len = next_len?;
+        total += current_len as u64;
+        core::mem::swap(&mut current, &mut next);
+    }
+    hasher.update_rayon(&current[..current_len]);
+    Ok(total)
+}
+
+// The async equivalents of copy_wide. The buffer lives on the heap here, so that it doesn't make
+// the caller's future 64 KiB larger.
+#[cfg(feature = "tokio")]
+pub(crate) async fn copy_wide_tokio(
+    mut reader: impl tokio::io::AsyncRead + Unpin,
+    hasher: &mut crate::Hasher,
+) -> std::io::Result<u64> {
+    let mut buffer = vec![0; 65536];
+    let mut total = 0;
+    loop {
+        let mut read_buf = tokio::io::ReadBuf::new(&mut buffer);
+        let result = std::future::poll_fn(|cx| {
+            tokio::io::AsyncRead::poll_read(std::pin::Pin::new(&mut reader), cx, &mut read_buf)
+        })
+        .await;
+        match result {
+            Ok(()) if read_buf.filled().is_empty() => return Ok(total),
+            Ok(()) => {
+                hasher.update(read_buf.filled());
+                total += read_buf.filled().len() as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "futures-io")]
+pub(crate) async fn copy_wide_futures(
+    mut reader: impl futures_io::AsyncRead + Unpin,
+    hasher: &mut crate::Hasher,
+) -> std::io::Result<u64> {
+    let mut buffer = vec![0; 65536];
+    let mut total = 0;
+    loop {
+        let result = std::future::poll_fn(|cx| {
+            futures_io::AsyncRead::poll_read(std::pin::Pin::new(&mut reader), cx, &mut buffer)
+        })
+        .await;
+        match result {
+            Ok(0) => return Ok(total),
+            Ok(n) => {
+                hasher.update(&buffer[..n]);
+                total += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Options for the memory mapping behavior of
+/// [`update_mmap_with_options`](crate::Hasher::update_mmap_with_options) and
+/// [`update_mmap_rayon_with_options`](crate::Hasher::update_mmap_rayon_with_options).
+///
+/// The defaults are the same as [`update_mmap`](crate::Hasher::update_mmap): files shorter than
+/// 16 KiB are read instead of mapped, the whole file is mapped at once, and no `madvise` hints are
+/// given. Files too long for the address space are read instead of mapped, unless a window length
+/// is set.
+///
+/// This type requires the `mmap` Cargo feature, which is disabled by default but enabled on
+/// [docs.rs](https://docs.rs).
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// // Map a huge disk image 1 GiB at a time, and tell the kernel we'll read it in order.
+/// let options = blake3::MmapOptions::new()
+///     .window_len(1 << 30)
+///     .sequential(true);
+/// let mut hasher = blake3::Hasher::new();
+/// hasher.update_mmap_with_options("disk.img", &options)?;
+/// println!("{}", hasher.finalize());
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "mmap")]
+#[derive(Clone, Debug)]
+pub struct MmapOptions {
+    min_len: u64,
+    window_len: Option<u64>,
+    sequential: bool,
+    will_need: bool,
+    huge_pages: bool,
+}
+
+#[cfg(feature = "mmap")]
+impl MmapOptions {
+    /// The default options.
+    pub fn new() -> Self {
+        Self {
+            min_len: 16 * 1024,
+            window_len: None,
+            sequential: false,
+            will_need: false,
+            huge_pages: false,
+        }
+    }
+
+    /// Files shorter than this are read instead of mapped. The default is 16 KiB.
+    pub fn min_len(mut self, min_len: u64) -> Self {
+        self.min_len = min_len;
+        self
+    }
+
+    /// Map the file in windows of this many bytes, one after another, rather than all at once.
+    /// This keeps the mapping small, and it allows files larger than the address space, for
+    /// example on 32-bit targets.
+    ///
+    /// The window length must be a nonzero multiple of the 1024-byte chunk length, and a power of
+    /// two is best for multithreading. Otherwise the hashing methods return an
+    /// [`InvalidInput`](std::io::ErrorKind::InvalidInput) error.
+    pub fn window_len(mut self, window_len: u64) -> Self {
+        self.window_len = Some(window_len);
+        self
+    }
+
+    /// Advise the kernel that the mapping will be read sequentially (`MADV_SEQUENTIAL`). This is
+    /// ignored on non-Unix platforms.
+    pub fn sequential(mut self, sequential: bool) -> Self {
+        self.sequential = sequential;
+        self
+    }
+
+    /// Advise the kernel that the whole mapping will be needed soon (`MADV_WILLNEED`). This is
+    /// ignored on non-Unix platforms.
+    pub fn will_need(mut self, will_need: bool) -> Self {
+        self.will_need = will_need;
+        self
+    }
+
+    /// Advise the kernel to back the mapping with transparent huge pages (`MADV_HUGEPAGE`). This
+    /// is ignored on platforms other than Linux.
+    pub fn huge_pages(mut self, huge_pages: bool) -> Self {
+        self.huge_pages = huge_pages;
+        self
+    }
+
+    pub(crate) fn check(&self) -> std::io::Result<()> {
+        if let Some(window_len) = self.window_len {
+            if window_len == 0
+                || window_len.trailing_zeros() < crate::CHUNK_LEN.trailing_zeros()
+                || window_len > isize::MAX as u64
+            {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "mmap window length must be a nonzero multiple of the chunk length",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // These are only hints, so errors are ignored.
+    #[allow(unused_variables)]
+    fn advise(&self, map: &memmap2::Mmap) {
+        #[cfg(unix)]
+        {
+            if self.sequential {
+                let _ = map.advise(memmap2::Advice::Sequential);
+            }
+            if self.will_need {
+                let _ = map.advise(memmap2::Advice::WillNeed);
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if self.huge_pages {
+            let _ = map.advise(memmap2::Advice::HugePage);
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Default for MmapOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Mmap a file, if it looks like a good idea, and pass each mapped window to `update`. Return false
+// without calling `update` in cases where we know mmap will fail, or if the file is short enough
+// that mmapping isn't worth it, and the caller falls back to reading the file. However, if we do
+// try to mmap and it fails, return the error.
 //
 // SAFETY: Mmaps are fundamentally unsafe, because you can call invariant-checking functions like
 // str::from_utf8 on them and then have them change out from under you. Letting a safe caller get
@@ -47,33 +275,46 @@ pub(crate) fn copy_wide(
 // But if you "know what you're doing," I don't think *const i32 and &i32 are fundamentally
 // different here. Feedback needed.
 #[cfg(feature = "mmap")]
-pub(crate) fn maybe_mmap_file(file: &std::fs::File) -> std::io::Result<Option<memmap2::Mmap>> {
+pub(crate) fn mmap_file(
+    file: &std::fs::File,
+    options: &MmapOptions,
+    mut update: impl FnMut(&[u8]),
+) -> std::io::Result<bool> {
+    options.check()?;
     let metadata = file.metadata()?;
     let file_size = metadata.len();
     #[allow(clippy::if_same_then_else)]
     if !metadata.is_file() {
         // Not a real file.
-        Ok(None)
-    } else if file_size > isize::max_value() as u64 {
-        // Too long to safely map.
+        return Ok(false);
+    } else if file_size > isize::max_value() as u64 && options.window_len.is_none() {
+        // Too long to safely map all at once.
         // https://github.com/danburkert/memmap-rs/issues/69
-        Ok(None)
+        return Ok(false);
     } else if file_size == 0 {
         // Mapping an empty file currently fails.
         // https://github.com/danburkert/memmap-rs/issues/72
         // See test_mmap_virtual_file.
-        Ok(None)
-    } else if file_size < 16 * 1024 {
+        return Ok(false);
+    } else if file_size < options.min_len {
         // Mapping small files is not worth it.
-        Ok(None)
-    } else {
-        // Explicitly set the length of the memory map, so that filesystem
+        return Ok(false);
+    }
+    let window_len = options.window_len.unwrap_or(file_size);
+    let mut offset = 0;
+    while offset < file_size {
+        // Explicitly set the length of each memory map, so that filesystem
         // changes can't race to violate the invariants we just checked.
+        let len = std::cmp::min(window_len, file_size - offset);
         let map = unsafe {
             memmap2::MmapOptions::new()
-                .len(file_size as usize)
+                .offset(offset)
+                .len(len as usize)
                 .map(file)?
         };
-        Ok(Some(map))
+        options.advise(&map);
+        update(&map);
+        offset += len;
     }
+    Ok(true)
 }
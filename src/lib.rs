@@ -49,7 +49,8 @@
 //! The `mmap` feature (disabled by default, but enabled for [docs.rs]) adds the
 //! [`update_mmap`](Hasher::update_mmap) and (in combination with `rayon` above)
 //! [`update_mmap_rayon`](Hasher::update_mmap_rayon) helper methods for
-//! memory-mapped IO.
+//! memory-mapped IO. The `_with_options` variants of those methods take an
+//! [`MmapOptions`] for tuning the mapping strategy.
 //!
 //! The `zeroize` feature (disabled by default, but enabled for [docs.rs])
 //! implements
@@ -59,7 +60,25 @@
 //! The `serde` feature (disabled by default, but enabled for [docs.rs]) implements
 //! [`serde::Serialize`](https://docs.rs/serde/latest/serde/trait.Serialize.html) and
 //! [`serde::Deserialize`](https://docs.rs/serde/latest/serde/trait.Deserialize.html)
-//! for [`Hash`](struct@Hash).
+//! for [`Hash`](struct@Hash) and [`Hasher`]. The latter uses the resumable state
+//! encoding from [`Hasher::export_state`].
+//!
+//! The `encoding` feature (disabled by default, but enabled for [docs.rs])
+//! adds the [`encoding`] module, for verified streaming in the style of the
+//! Bao encoding.
+//!
+//! The [`proof`] module, which requires the `std` feature, makes compact
+//! Merkle inclusion proofs for chunks and byte ranges.
+//!
+//! The `tokio` and `futures-io` features (disabled by default, but enabled for
+//! [docs.rs]) add the [`update_async_reader`](Hasher::update_async_reader) and
+//! [`update_futures_reader`](Hasher::update_futures_reader) methods, and
+//! implement the corresponding async IO traits for [`Hasher`] and
+//! [`OutputReader`].
+//!
+//! The `io-uring` feature (disabled by default) adds the Linux-only
+//! `update_file_direct` method, which reads files with `O_DIRECT` through
+//! io_uring, bypassing the page cache.
 //!
 //! The NEON implementation is enabled by default for AArch64 but requires the
 //! `neon` feature for other ARM targets. Not all ARMv7 CPUs support NEON, and
@@ -89,12 +108,18 @@
 mod test;
 
 // The guts module is for incremental use cases like the `bao` crate that need
-// to explicitly compute chunk and parent chaining values. It is semi-stable
-// and likely to keep working, but largely undocumented and not intended for
-// widespread use.
-#[doc(hidden)]
+// to explicitly compute chunk and parent chaining values. See its module docs
+// for the stability policy.
 pub mod guts;
 
+pub mod hazmat;
+
+#[cfg(feature = "encoding")]
+pub mod encoding;
+
+#[cfg(feature = "std")]
+pub mod proof;
+
 /// Undocumented and unstable, for benchmarks only.
 #[doc(hidden)]
 pub mod platform;
@@ -114,6 +139,8 @@ mod avx512;
 #[path = "ffi_neon.rs"]
 mod neon;
 mod portable;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod simd_xof;
 #[cfg(blake3_sse2_rust)]
 #[path = "rust_sse2.rs"]
 mod sse2;
@@ -133,6 +160,17 @@ pub mod traits;
 mod io;
 mod join;
 
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod direct_io;
+
+#[cfg(feature = "mmap")]
+pub use io::MmapOptions;
+
+#[cfg(feature = "std")]
+mod unordered;
+#[cfg(feature = "std")]
+pub use unordered::{UnorderedError, UnorderedHasher};
+
 use arrayref::{array_mut_ref, array_ref};
 use arrayvec::{ArrayString, ArrayVec};
 use core::cmp;
@@ -148,6 +186,13 @@ pub const KEY_LEN: usize = 32;
 const MAX_DEPTH: usize = 54; // 2^54 * CHUNK_LEN = 2^64
 use guts::{BLOCK_LEN, CHUNK_LEN};
 
+/// The maximum number of bytes in an exported [`Hasher`] state, 1901. See
+/// [`Hasher::export_state`].
+pub const MAX_STATE_LEN: usize = 77 + BLOCK_LEN + (MAX_DEPTH + 1) * OUT_LEN;
+
+// The version byte at the front of an exported Hasher state.
+const STATE_VERSION: u8 = 1;
+
 // While iterating the compression function within a chunk, the CV is
 // represented as words, to avoid doing two extra endianness conversions for
 // each compression in the portable implementation. But the hash_many interface
@@ -434,6 +479,39 @@ impl Output {
             self.flags | ROOT,
         )
     }
+
+    // Fill `out` with consecutive root output blocks, starting at the current counter. The
+    // length of `out` must be a multiple of BLOCK_LEN.
+    fn root_output_blocks(&self, out: &mut [u8]) {
+        self.platform.xof_many(
+            &self.input_chaining_value,
+            &self.block,
+            self.block_len,
+            self.counter,
+            self.flags | ROOT,
+            out,
+        )
+    }
+}
+
+// Fill `out` with consecutive root output blocks, starting at `output.counter`. Every output block
+// depends only on its counter, so large outputs get split in half recursively, the same way
+// compress_subtree_wide() splits its input. For fill_rayon(), this is where RayonJoin fills the
+// pieces on multiple threads. The length of `out` must be a multiple of BLOCK_LEN.
+fn root_output_blocks_wide<J: join::Join>(output: &Output, out: &mut [u8]) {
+    debug_assert_eq!(0, out.len() % BLOCK_LEN, "whole blocks only");
+    if out.len() <= output.platform.simd_degree() * CHUNK_LEN {
+        output.root_output_blocks(out);
+        return;
+    }
+    let left_blocks = out.len() / BLOCK_LEN / 2;
+    let (left_out, right_out) = out.split_at_mut(left_blocks * BLOCK_LEN);
+    let mut right_output = output.clone();
+    right_output.counter += left_blocks as u64;
+    J::join(
+        || root_output_blocks_wide::<J>(output, left_out),
+        || root_output_blocks_wide::<J>(&right_output, right_out),
+    );
 }
 
 #[derive(Clone)]
@@ -1299,6 +1377,165 @@ impl Hasher {
         self.chunk_state.chunk_counter * CHUNK_LEN as u64 + self.chunk_state.len() as u64
     }
 
+    /// Encode the complete hash state as bytes, so that hashing can be resumed later with
+    /// [`import_state`](Hasher::import_state), possibly in another process or on another machine.
+    ///
+    /// The returned [`ArrayVec`] is at most [`MAX_STATE_LEN`] bytes long and doesn't allocate
+    /// memory on the heap. The encoding is versioned, and it's a stable format. Version 1, the
+    /// current version, is laid out like this, with all integers little-endian:
+    ///
+    /// | offset | size       | field                                                       |
+    /// |--------|------------|-------------------------------------------------------------|
+    /// | 0      | 1          | version, currently 1                                        |
+    /// | 1      | 1          | mode flags: 0 (hash), 16 (keyed hash), or 64 (derive key)   |
+    /// | 2      | 32         | key words (the IV, the key, or the context key)             |
+    /// | 34     | 8          | chunk counter                                               |
+    /// | 42     | 32         | chaining value of the current chunk                         |
+    /// | 74     | 1          | number of blocks compressed in the current chunk, 0-15      |
+    /// | 75     | 1          | number of buffered block bytes `b`, 0-64                    |
+    /// | 76     | `b`        | buffered block bytes                                        |
+    /// | 76+`b` | 1          | number of chaining values on the stack `d`                  |
+    /// | 77+`b` | 32×`d`     | chaining values on the stack, from bottom to top            |
+    ///
+    /// The exported state contains the key (if any) and enough information to reconstruct any
+    /// unfinished input, so it should be protected like the key and the input themselves. The
+    /// current CPU feature detection isn't part of the state.
+    ///
+    /// When the `serde` Cargo feature is enabled, `Hasher` also implements
+    /// [`serde::Serialize`](https://docs.rs/serde/latest/serde/trait.Serialize.html) and
+    /// [`serde::Deserialize`](https://docs.rs/serde/latest/serde/trait.Deserialize.html) using
+    /// this encoding, serialized as bytes.
+    ///
+    /// [`ArrayVec`]: https://docs.rs/arrayvec/latest/arrayvec/struct.ArrayVec.html
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> Result<(), blake3::StateError> {
+    /// let mut hasher = blake3::Hasher::new();
+    /// hasher.update(b"foo");
+    /// let state = hasher.export_state();
+    ///
+    /// // Later, maybe somewhere else...
+    /// let mut resumed = blake3::Hasher::import_state(&state)?;
+    /// resumed.update(b"bar");
+    /// assert_eq!(resumed.finalize(), blake3::hash(b"foobar"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn export_state(&self) -> ArrayVec<u8, MAX_STATE_LEN> {
+        let chunk_state = &self.chunk_state;
+        let mut state = ArrayVec::new();
+        state.push(STATE_VERSION);
+        state.push(chunk_state.flags);
+        state.extend(platform::le_bytes_from_words_32(&self.key));
+        state.extend(chunk_state.chunk_counter.to_le_bytes());
+        state.extend(platform::le_bytes_from_words_32(&chunk_state.cv));
+        state.push(chunk_state.blocks_compressed);
+        state.push(chunk_state.buf_len);
+        state.extend(
+            chunk_state.buf[..chunk_state.buf_len as usize]
+                .iter()
+                .copied(),
+        );
+        state.push(self.cv_stack.len() as u8);
+        for cv in &self.cv_stack {
+            state.extend(cv.iter().copied());
+        }
+        state
+    }
+
+    /// Reconstruct a `Hasher` from the bytes returned by [`export_state`](Hasher::export_state).
+    ///
+    /// The encoding is fully validated. An unknown version, unknown mode flags, a chunk that's
+    /// longer than 1024 bytes, a chaining value stack whose depth is inconsistent with the
+    /// [`count`](Hasher::count) it implies, or a length that doesn't match the fields, all result
+    /// in an error. Note that chaining values and key words can't be
+    /// validated; an exported state that's been tampered with in those fields will import
+    /// successfully and produce the wrong hash.
+    pub fn import_state(state: &[u8]) -> Result<Self, StateError> {
+        fn take<'a>(state: &mut &'a [u8], n: usize) -> Result<&'a [u8], StateError> {
+            if state.len() < n {
+                return Err(StateError(StateErrorInner::Truncated));
+            }
+            let (taken, rest) = state.split_at(n);
+            *state = rest;
+            Ok(taken)
+        }
+
+        let mut state = state;
+        let version = take(&mut state, 1)?[0];
+        if version != STATE_VERSION {
+            return Err(StateError(StateErrorInner::UnknownVersion(version)));
+        }
+        let flags = take(&mut state, 1)?[0];
+        if flags != 0 && flags != KEYED_HASH && flags != DERIVE_KEY_MATERIAL {
+            return Err(StateError(StateErrorInner::UnknownFlags(flags)));
+        }
+        let key = platform::words_from_le_bytes_32(array_ref!(take(&mut state, 32)?, 0, 32));
+        // The regular hash mode always uses the IV as its key.
+        if flags == 0 && key != *IV {
+            return Err(StateError(StateErrorInner::WrongKey));
+        }
+        let chunk_counter = u64::from_le_bytes(*array_ref!(take(&mut state, 8)?, 0, 8));
+        // The maximum input length is 2^64-1 bytes, which is less than 2^54 chunks.
+        if chunk_counter >= 1 << MAX_DEPTH {
+            return Err(StateError(StateErrorInner::CounterTooLarge));
+        }
+        let chunk_cv = platform::words_from_le_bytes_32(array_ref!(take(&mut state, 32)?, 0, 32));
+        let blocks_compressed = take(&mut state, 1)?[0];
+        let buf_len = take(&mut state, 1)?[0];
+        // A chunk that has compressed any blocks always keeps its last block buffered, because it
+        // might need the CHUNK_END flag, so the largest possible chunk state is 15 compressed
+        // blocks plus one full buffer.
+        if blocks_compressed as usize >= CHUNK_LEN / BLOCK_LEN
+            || buf_len as usize > BLOCK_LEN
+            || (blocks_compressed > 0 && buf_len == 0)
+        {
+            return Err(StateError(StateErrorInner::InvalidChunkLen));
+        }
+        let mut buf = [0; BLOCK_LEN];
+        buf[..buf_len as usize].copy_from_slice(take(&mut state, buf_len as usize)?);
+        let stack_depth = take(&mut state, 1)?[0] as usize;
+        // See merge_cv_stack() and push_cv(). If the chunk state is non-empty, update() has
+        // already merged the stack down to one CV per 1-bit in the chunk counter. If it's empty,
+        // the last update() ended on a subtree boundary and left 1 or more extra CVs on top of
+        // the stack, unmerged. Any other depth is either impossible to reach or would make
+        // finalize() merge subtrees incorrectly.
+        let chunk_len = BLOCK_LEN * blocks_compressed as usize + buf_len as usize;
+        let ones = chunk_counter.count_ones() as usize;
+        let depth_ok = if chunk_len > 0 {
+            stack_depth == ones
+        } else if chunk_counter == 0 {
+            stack_depth == 0
+        } else {
+            stack_depth > ones && stack_depth <= ones + chunk_counter.trailing_zeros() as usize
+        };
+        if !depth_ok {
+            return Err(StateError(StateErrorInner::InvalidStackDepth(stack_depth)));
+        }
+        let mut cv_stack = ArrayVec::new();
+        for _ in 0..stack_depth {
+            cv_stack.push(*array_ref!(take(&mut state, OUT_LEN)?, 0, OUT_LEN));
+        }
+        if !state.is_empty() {
+            return Err(StateError(StateErrorInner::TrailingBytes));
+        }
+        Ok(Self {
+            key,
+            chunk_state: ChunkState {
+                cv: chunk_cv,
+                chunk_counter,
+                buf,
+                buf_len,
+                blocks_compressed,
+                flags,
+                platform: Platform::detect(),
+            },
+            cv_stack,
+        })
+    }
+
     /// As [`update`](Hasher::update), but reading from a
     /// [`std::io::Read`](https://doc.rust-lang.org/std/io/trait.Read.html) implementation.
     ///
@@ -1343,6 +1580,57 @@ impl Hasher {
         Ok(self)
     }
 
+    /// As [`update_reader`](Hasher::update_reader), but reading from a Tokio
+    /// [`AsyncRead`](https://docs.rs/tokio/latest/tokio/io/trait.AsyncRead.html)
+    /// source. This uses the same 64 KiB buffer, so that SIMD parallelism
+    /// still applies.
+    ///
+    /// This method is gated by the `tokio` Cargo feature, which is disabled
+    /// by default but enabled on [docs.rs](https://docs.rs). For the
+    /// `futures` crate's version of the trait, see
+    /// [`update_futures_reader`](Hasher::update_futures_reader).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> std::io::Result<()> {
+    /// # futures::executor::block_on(async {
+    /// let (mut writer, reader) = tokio::io::duplex(4096);
+    /// tokio::io::AsyncWriteExt::write_all(&mut writer, b"foo").await?;
+    /// drop(writer);
+    /// let mut hasher = blake3::Hasher::new();
+    /// hasher.update_async_reader(reader).await?;
+    /// assert_eq!(hasher.finalize(), blake3::hash(b"foo"));
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn update_async_reader(
+        &mut self,
+        reader: impl tokio::io::AsyncRead + Unpin,
+    ) -> std::io::Result<&mut Self> {
+        io::copy_wide_tokio(reader, self).await?;
+        Ok(self)
+    }
+
+    /// As [`update_async_reader`](Hasher::update_async_reader), but for the
+    /// [`futures`](https://docs.rs/futures) crate's
+    /// [`AsyncRead`](https://docs.rs/futures-io/latest/futures_io/trait.AsyncRead.html)
+    /// trait. The two traits are different, so the methods need different
+    /// names.
+    ///
+    /// This method is gated by the `futures-io` Cargo feature, which is
+    /// disabled by default but enabled on [docs.rs](https://docs.rs).
+    #[cfg(feature = "futures-io")]
+    pub async fn update_futures_reader(
+        &mut self,
+        reader: impl futures_io::AsyncRead + Unpin,
+    ) -> std::io::Result<&mut Self> {
+        io::copy_wide_futures(reader, self).await?;
+        Ok(self)
+    }
+
     /// As [`update`](Hasher::update), but using Rayon-based multithreading
     /// internally.
     ///
@@ -1368,6 +1656,58 @@ impl Hasher {
         self.update_with_join::<join::RayonJoin>(input)
     }
 
+    /// As [`update_reader`](Hasher::update_reader), but using Rayon-based
+    /// multithreading internally, for sources that can't be memory mapped,
+    /// like pipes, sockets, and standard input.
+    ///
+    /// This method reads into two buffers of 1 MiB each. While one is being
+    /// hashed with [`update_rayon`](Hasher::update_rayon), the next one is
+    /// being filled. To choose a different buffer size, see
+    /// [`update_reader_rayon_with_buffer_len`](Hasher::update_reader_rayon_with_buffer_len).
+    /// Like [`update_rayon`](Hasher::update_rayon), this is only worth it for
+    /// long inputs, and the performance warning on
+    /// [`update_mmap_rayon`](Hasher::update_mmap_rayon) applies here too.
+    ///
+    /// This method is gated by the `rayon` Cargo feature, which is disabled by
+    /// default but enabled on [docs.rs](https://docs.rs).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// // Hash standard input with multiple threads.
+    /// let mut hasher = blake3::Hasher::new();
+    /// hasher.update_reader_rayon(std::io::stdin())?;
+    /// println!("{}", hasher.finalize());
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn update_reader_rayon(
+        &mut self,
+        reader: impl std::io::Read + Send,
+    ) -> std::io::Result<&mut Self> {
+        self.update_reader_rayon_with_buffer_len(reader, 1 << 20)
+    }
+
+    /// As [`update_reader_rayon`](Hasher::update_reader_rayon), but with
+    /// two buffers of `buffer_len` bytes each, so that memory use is
+    /// `2 * buffer_len`. Larger buffers give more room for parallelism.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer_len` isn't a power of two, or if it's shorter than
+    /// the 1024-byte chunk length.
+    #[cfg(feature = "rayon")]
+    pub fn update_reader_rayon_with_buffer_len(
+        &mut self,
+        reader: impl std::io::Read + Send,
+        buffer_len: usize,
+    ) -> std::io::Result<&mut Self> {
+        io::copy_wide_rayon(reader, self, buffer_len)?;
+        Ok(self)
+    }
+
     /// As [`update`](Hasher::update), but reading the contents of a file using memory mapping.
     ///
     /// Not all files can be memory mapped, and memory mapping small files can be slower than
@@ -1411,10 +1751,24 @@ impl Hasher {
     /// ```
     #[cfg(feature = "mmap")]
     pub fn update_mmap(&mut self, path: impl AsRef<std::path::Path>) -> std::io::Result<&mut Self> {
+        self.update_mmap_with_options(path, &MmapOptions::new())
+    }
+
+    /// As [`update_mmap`](Hasher::update_mmap), but with control over when and how the file is
+    /// memory mapped. See [`MmapOptions`].
+    ///
+    /// This method requires the `mmap` Cargo feature, which is disabled by default but enabled on
+    /// [docs.rs](https://docs.rs).
+    #[cfg(feature = "mmap")]
+    pub fn update_mmap_with_options(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        options: &MmapOptions,
+    ) -> std::io::Result<&mut Self> {
         let file = std::fs::File::open(path.as_ref())?;
-        if let Some(mmap) = io::maybe_mmap_file(&file)? {
-            self.update(&mmap);
-        } else {
+        if !io::mmap_file(&file, options, |map| {
+            self.update(map);
+        })? {
             io::copy_wide(&file, self)?;
         }
         Ok(self)
@@ -1465,15 +1819,67 @@ impl Hasher {
     pub fn update_mmap_rayon(
         &mut self,
         path: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<&mut Self> {
+        self.update_mmap_rayon_with_options(path, &MmapOptions::new())
+    }
+
+    /// As [`update_mmap_rayon`](Hasher::update_mmap_rayon), but with control over when and how
+    /// the file is memory mapped. See [`MmapOptions`].
+    ///
+    /// This method requires both the `mmap` and `rayon` Cargo features, which are disabled by
+    /// default but enabled on [docs.rs](https://docs.rs).
+    #[cfg(feature = "mmap")]
+    #[cfg(feature = "rayon")]
+    pub fn update_mmap_rayon_with_options(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        options: &MmapOptions,
     ) -> std::io::Result<&mut Self> {
         let file = std::fs::File::open(path.as_ref())?;
-        if let Some(mmap) = io::maybe_mmap_file(&file)? {
-            self.update_rayon(&mmap);
-        } else {
+        if !io::mmap_file(&file, options, |map| {
+            self.update_rayon(map);
+        })? {
             io::copy_wide(&file, self)?;
         }
         Ok(self)
     }
+
+    /// As [`update_reader`](Hasher::update_reader), but reading the file at `path` with
+    /// `O_DIRECT` and [io_uring](https://en.wikipedia.org/wiki/Io_uring), with several reads in
+    /// flight at once.
+    ///
+    /// `O_DIRECT` bypasses the page cache, so hashing a very large file this way doesn't evict
+    /// everything else from memory, and reading it doesn't cost an extra copy. Reads go into
+    /// aligned 1 MiB buffers, and each filled buffer is hashed while the device fills the next
+    /// ones. If the `rayon` feature is also enabled, each buffer is hashed with
+    /// [`update_rayon`](Hasher::update_rayon).
+    ///
+    /// If the filesystem refuses `O_DIRECT`, if `path` isn't a regular file, or if io_uring isn't
+    /// available, this falls back to ordinary buffered reads. Whether this is faster than
+    /// [`update_mmap`](Hasher::update_mmap) depends heavily on the storage device, so benchmark
+    /// before switching.
+    ///
+    /// This method requires the `io-uring` Cargo feature, which is disabled by default, and it
+    /// only exists on Linux.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut hasher = blake3::Hasher::new();
+    /// hasher.update_file_direct("big_file.dat")?;
+    /// println!("{}", hasher.finalize());
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn update_file_direct(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<&mut Self> {
+        direct_io::update_file_direct(self, path.as_ref())?;
+        Ok(self)
+    }
 }
 
 // Don't derive(Debug), because the state may be secret.
@@ -1508,6 +1914,154 @@ impl std::io::Write for Hasher {
     }
 }
 
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for Hasher {
+    /// This is equivalent to [`update`](#method.update), and it never blocks.
+    #[inline]
+    fn poll_write(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+        input: &[u8],
+    ) -> core::task::Poll<std::io::Result<usize>> {
+        self.get_mut().update(input);
+        core::task::Poll::Ready(Ok(input.len()))
+    }
+
+    #[inline]
+    fn poll_flush(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<std::io::Result<()>> {
+        core::task::Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_shutdown(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<std::io::Result<()>> {
+        core::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncWrite for Hasher {
+    /// This is equivalent to [`update`](#method.update), and it never blocks.
+    #[inline]
+    fn poll_write(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+        input: &[u8],
+    ) -> core::task::Poll<std::io::Result<usize>> {
+        self.get_mut().update(input);
+        core::task::Poll::Ready(Ok(input.len()))
+    }
+
+    #[inline]
+    fn poll_flush(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<std::io::Result<()>> {
+        core::task::Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_close(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<std::io::Result<()>> {
+        core::task::Poll::Ready(Ok(()))
+    }
+}
+
+/// This uses the encoding from [`Hasher::export_state`], serialized as bytes.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Hasher {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.export_state())
+    }
+}
+
+/// This validates the encoding with [`Hasher::import_state`]. Both byte strings and sequences of
+/// bytes are accepted, for compatibility with formats that don't have a native bytes type.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Hasher {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StateVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for StateVisitor {
+            type Value = Hasher;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an exported blake3::Hasher state")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Hasher, E> {
+                Hasher::import_state(v).map_err(E::custom)
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Hasher, A::Error> {
+                let mut state = ArrayVec::<u8, MAX_STATE_LEN>::new();
+                while let Some(byte) = seq.next_element()? {
+                    if state.try_push(byte).is_err() {
+                        return Err(serde::de::Error::invalid_length(MAX_STATE_LEN + 1, &self));
+                    }
+                }
+                Hasher::import_state(&state).map_err(serde::de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_bytes(StateVisitor)
+    }
+}
+
+/// The error type for [`Hasher::import_state`].
+///
+/// The `.to_string()` representation of this error currently describes which part of the state
+/// was invalid. This is to help with logging and debugging, but it isn't a stable API detail, and
+/// it may change at any time.
+#[derive(Clone, Debug)]
+pub struct StateError(StateErrorInner);
+
+#[derive(Clone, Debug)]
+enum StateErrorInner {
+    Truncated,
+    TrailingBytes,
+    UnknownVersion(u8),
+    UnknownFlags(u8),
+    WrongKey,
+    CounterTooLarge,
+    InvalidChunkLen,
+    InvalidStackDepth(usize),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            StateErrorInner::Truncated => write!(f, "hasher state is truncated"),
+            StateErrorInner::TrailingBytes => write!(f, "trailing bytes after hasher state"),
+            StateErrorInner::UnknownVersion(version) => {
+                write!(f, "unknown hasher state version: {}", version)
+            }
+            StateErrorInner::UnknownFlags(flags) => {
+                write!(f, "unknown hasher state flags: 0x{:x}", flags)
+            }
+            StateErrorInner::WrongKey => write!(f, "regular hash state with a non-default key"),
+            StateErrorInner::CounterTooLarge => write!(f, "chunk counter too large"),
+            StateErrorInner::InvalidChunkLen => write!(f, "invalid chunk length"),
+            StateErrorInner::InvalidStackDepth(depth) => {
+                write!(f, "invalid chaining value stack depth: {}", depth)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for StateError {}
+
 /// An incremental reader for extended output, returned by
 /// [`Hasher::finalize_xof`](struct.Hasher.html#method.finalize_xof).
 ///
@@ -1546,13 +2100,32 @@ impl OutputReader {
         }
     }
 
+    // This helper function handles both the case where the output buffer is
+    // shorter than one block, and the case where our position_within_block is
+    // non-zero.
+    fn fill_one_block(&mut self, buf: &mut &mut [u8]) {
+        let output_block: [u8; BLOCK_LEN] = self.inner.root_output_block();
+        let output_bytes = &output_block[self.position_within_block as usize..];
+        let take = cmp::min(buf.len(), output_bytes.len());
+        buf[..take].copy_from_slice(&output_bytes[..take]);
+        self.position_within_block += take as u8;
+        if self.position_within_block == BLOCK_LEN as u8 {
+            self.inner.counter += 1;
+            self.position_within_block = 0;
+        }
+        // Advance the dest buffer. mem::take() is a borrowck workaround.
+        *buf = &mut core::mem::take(buf)[take..];
+    }
+
     /// Fill a buffer with output bytes and advance the position of the
     /// `OutputReader`. This is equivalent to [`Read::read`], except that it
     /// doesn't return a `Result`. Both methods always fill the entire buffer.
     ///
-    /// Note that `OutputReader` doesn't buffer output bytes internally, so
-    /// calling `fill` repeatedly with a short-length or odd-length slice will
-    /// end up performing the same compression multiple times. If you're
+    /// Whole output blocks are computed several at a time, using SIMD where
+    /// the platform supports it, and each block is computed at most once per
+    /// call. Note that `OutputReader` doesn't buffer output bytes internally,
+    /// so calling `fill` repeatedly with a short-length or odd-length slice
+    /// will end up performing the same compression multiple times. If you're
     /// reading output in a loop, prefer a slice length that's a multiple of
     /// 64.
     ///
@@ -1561,21 +2134,54 @@ impl OutputReader {
     /// reading further, the behavior is unspecified.
     ///
     /// [`Read::read`]: #method.read
-    pub fn fill(&mut self, mut buf: &mut [u8]) {
-        while !buf.is_empty() {
-            let block: [u8; BLOCK_LEN] = self.inner.root_output_block();
-            let output_bytes = &block[self.position_within_block as usize..];
-            let take = cmp::min(buf.len(), output_bytes.len());
-            buf[..take].copy_from_slice(&output_bytes[..take]);
-            buf = &mut buf[take..];
-            self.position_within_block += take as u8;
-            if self.position_within_block == BLOCK_LEN as u8 {
-                self.inner.counter += 1;
-                self.position_within_block = 0;
-            }
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        self.fill_with_join::<join::SerialJoin>(buf);
+    }
+
+    fn fill_with_join<J: join::Join>(&mut self, mut buf: &mut [u8]) {
+        if buf.is_empty() {
+            return;
+        }
+
+        // If we're partway through a block, try to get to a block boundary.
+        if self.position_within_block != 0 {
+            self.fill_one_block(&mut buf);
+        }
+
+        let full_blocks = buf.len() / BLOCK_LEN;
+        let full_blocks_len = full_blocks * BLOCK_LEN;
+        if full_blocks > 0 {
+            debug_assert_eq!(0, self.position_within_block);
+            root_output_blocks_wide::<J>(&self.inner, &mut buf[..full_blocks_len]);
+            self.inner.counter += full_blocks as u64;
+            buf = &mut buf[full_blocks_len..];
+        }
+
+        if !buf.is_empty() {
+            debug_assert!(buf.len() < BLOCK_LEN);
+            self.fill_one_block(&mut buf);
+            debug_assert!(buf.is_empty());
         }
     }
 
+    /// As [`fill`](#method.fill), but using Rayon-based multithreading
+    /// internally.
+    ///
+    /// This method is gated by the `rayon` Cargo feature, which is disabled by
+    /// default but enabled on [docs.rs](https://docs.rs).
+    ///
+    /// Each output block depends only on its position in the output stream,
+    /// so the buffer is split up by block index and the pieces are filled in
+    /// parallel. The result is always identical to calling `fill` from the
+    /// same [`position`](#method.position), and the position advances the
+    /// same way. As with [`update_rayon`](Hasher::update_rayon), the buffer
+    /// needs to be large (at least 128 KiB as a rule of thumb on x86_64) for
+    /// multithreading to be any faster than `fill`.
+    #[cfg(feature = "rayon")]
+    pub fn fill_rayon(&mut self, buf: &mut [u8]) {
+        self.fill_with_join::<join::RayonJoin>(buf);
+    }
+
     /// Return the current read position in the output stream. This is
     /// equivalent to [`Seek::stream_position`], except that it doesn't return
     /// a `Result`. The position of a new `OutputReader` starts at 0, and each
@@ -1643,3 +2249,63 @@ impl std::io::Seek for OutputReader {
         Ok(self.position())
     }
 }
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for OutputReader {
+    /// This is equivalent to [`fill`](#method.fill), and it never blocks.
+    #[inline]
+    fn poll_read(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> core::task::Poll<std::io::Result<()>> {
+        let len = buf.remaining();
+        self.get_mut().fill(buf.initialize_unfilled());
+        buf.advance(len);
+        core::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncSeek for OutputReader {
+    /// This is equivalent to [`Seek::seek`](std::io::Seek::seek), and it
+    /// completes immediately.
+    fn start_seek(self: core::pin::Pin<&mut Self>, pos: std::io::SeekFrom) -> std::io::Result<()> {
+        std::io::Seek::seek(self.get_mut(), pos)?;
+        Ok(())
+    }
+
+    fn poll_complete(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<std::io::Result<u64>> {
+        core::task::Poll::Ready(Ok(self.position()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for OutputReader {
+    /// This is equivalent to [`fill`](#method.fill), and it never blocks.
+    #[inline]
+    fn poll_read(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> core::task::Poll<std::io::Result<usize>> {
+        self.get_mut().fill(buf);
+        core::task::Poll::Ready(Ok(buf.len()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncSeek for OutputReader {
+    /// This is equivalent to [`Seek::seek`](std::io::Seek::seek), and it
+    /// completes immediately.
+    fn poll_seek(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+        pos: std::io::SeekFrom,
+    ) -> core::task::Poll<std::io::Result<u64>> {
+        core::task::Poll::Ready(std::io::Seek::seek(self.get_mut(), pos))
+    }
+}
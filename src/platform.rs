@@ -272,6 +272,55 @@ impl Platform {
         }
     }
 
+    // Write `out.len() / BLOCK_LEN` consecutive root output blocks, starting at `counter`. The
+    // length of `out` must be a multiple of BLOCK_LEN.
+    pub fn xof_many(
+        &self,
+        cv: &CVWords,
+        block: &[u8; BLOCK_LEN],
+        block_len: u8,
+        mut counter: u64,
+        flags: u8,
+        out: &mut [u8],
+    ) {
+        debug_assert_eq!(0, out.len() % BLOCK_LEN, "whole blocks only");
+        // The SIMD implementations only fill whole groups of blocks, and they return how many
+        // blocks they wrote. The leftovers are compressed one at a time below.
+        let simd_blocks = match self {
+            Platform::Portable => 0,
+            // Safe because detect() checked for platform support.
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Platform::SSE2 | Platform::SSE41 => unsafe {
+                crate::simd_xof::xof_many_sse2(cv, block, block_len, counter, flags, out)
+            },
+            // Safe because detect() checked for platform support.
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Platform::AVX2 => unsafe {
+                crate::simd_xof::xof_many_avx2(cv, block, block_len, counter, flags, out)
+            },
+            // There's no AVX-512 XOF yet, so use the widest one we have. Safe because detect()
+            // checked for platform support.
+            #[cfg(blake3_avx512_ffi)]
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Platform::AVX512 => unsafe {
+                if avx2_detected() {
+                    crate::simd_xof::xof_many_avx2(cv, block, block_len, counter, flags, out)
+                } else {
+                    crate::simd_xof::xof_many_sse2(cv, block, block_len, counter, flags, out)
+                }
+            },
+            // No NEON xof_many() implementation yet.
+            #[cfg(blake3_neon)]
+            Platform::NEON => 0,
+        };
+        counter += simd_blocks as u64;
+        for out_block in out[simd_blocks * BLOCK_LEN..].chunks_exact_mut(BLOCK_LEN) {
+            *array_mut_ref!(out_block, 0, BLOCK_LEN) =
+                self.compress_xof(cv, block, block_len, counter, flags);
+            counter += 1;
+        }
+    }
+
     // Explicit platform constructors, for benchmarks.
 
     pub fn portable() -> Self {
@@ -0,0 +1,517 @@
+//! Compact Merkle inclusion proofs for chunks and byte ranges.
+//!
+//! A [`Proof`] shows that a range of bytes belongs to an input with a given root
+//! [`struct@Hash`], without sending the rest of the input or a full
+//! [encoding](crate::encoding). It holds the input length, the position of the range, the bytes of
+//! any partial chunks at either end of the range, and the chaining values of the subtrees that
+//! don't overlap the range, in pre-order. Proving a single chunk of a large input takes one
+//! chaining value per level of the tree, so a proof for a 1 GiB input is well under 1 KiB.
+//!
+//! The verifier works out the shape of the tree from the claimed input length, the same way the
+//! hasher does. A proof that claims a length that changes the shape of the tree along the path to
+//! the range either has the wrong number of chaining values, which [`Proof::from_bytes`] rejects,
+//! or hashes to a different root, which [`verify`] rejects. Lengths that only differ inside a
+//! subtree the proof doesn't open can't be told apart, so only a proof that includes the last
+//! chunk, for example a proof of the empty range at the end of the input, authenticates the exact
+//! length.
+//!
+//! All three modes are supported, using [`hazmat::Mode`]. In the
+//! [`derive_key`](crate::derive_key) mode, the root hash is the first 32 bytes of the derived key.
+//!
+//! # Example
+//!
+//! ```
+//! use blake3::guts::CHUNK_LEN;
+//! use blake3::hazmat::Mode;
+//! use blake3::proof::{prove, verify, Proof};
+//!
+//! # fn main() -> Result<(), blake3::proof::ProofError> {
+//! let input = vec![0xab; 1_000_000];
+//! let hash = blake3::hash(&input);
+//!
+//! // Prove that chunk 42 is part of the input.
+//! let range = 42 * CHUNK_LEN..43 * CHUNK_LEN;
+//! let proof_bytes = prove(Mode::Hash, &input, range.clone()).to_bytes();
+//!
+//! // The verifier only needs the hash, the chunk, and the proof.
+//! let proof = Proof::from_bytes(&proof_bytes)?;
+//! verify(Mode::Hash, &hash, &input[range], &proof)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::guts::CHUNK_LEN;
+use crate::hazmat::{self, ChainingValue, Mode, Subtree};
+use crate::platform::Platform;
+use crate::{Hash, OUT_LEN};
+use arrayref::array_ref;
+use core::cmp;
+use core::fmt;
+use core::ops::Range;
+
+/// The version byte at the start of [`Proof::to_bytes`].
+pub const PROOF_VERSION: u8 = 1;
+
+// The version byte, the input length, the range start, the range length, and the number of
+// chaining values.
+const PROOF_HEADER_LEN: usize = 1 + 8 + 8 + 8 + 1;
+
+// The chunk-aligned part of the input that a proof carries bytes for. Like a slice in the encoding
+// module, it always includes at least one byte of a non-empty input, so that a proof of an empty
+// range still authenticates the input length.
+fn chunk_cover(range_start: u64, range_end: u64, input_len: u64) -> (u64, u64) {
+    let first = cmp::min(range_start, input_len.saturating_sub(1));
+    let last = cmp::max(cmp::min(range_end, input_len), first + 1);
+    let chunk_len = CHUNK_LEN as u64;
+    let cover_start = first / chunk_len * chunk_len;
+    let cover_end = cmp::min(
+        ((last - 1) / chunk_len + 1).saturating_mul(chunk_len),
+        input_len,
+    );
+    (cover_start, cover_end)
+}
+
+// Call `f` with the offset and length of every non-root subtree that doesn't overlap the cover,
+// in pre-order. Those are the subtrees whose chaining values go in a proof. Subtrees that partly
+// overlap the cover are always bigger than one chunk, because the cover is chunk-aligned.
+fn for_each_sibling(input_len: u64, cover: (u64, u64), f: &mut impl FnMut(u64, u64)) {
+    fn recurse(start: u64, len: u64, cover: (u64, u64), f: &mut impl FnMut(u64, u64)) {
+        if start >= cover.1 || start + len <= cover.0 {
+            f(start, len);
+        } else if start < cover.0 || start + len > cover.1 {
+            let left_len = hazmat::left_subtree_len(len);
+            recurse(start, left_len, cover, f);
+            recurse(start + left_len, len - left_len, cover, f);
+        }
+    }
+    // The root covers the whole input, so it always overlaps the cover.
+    if input_len > CHUNK_LEN as u64 {
+        let left_len = hazmat::left_subtree_len(input_len);
+        recurse(0, left_len, cover, f);
+        recurse(left_len, input_len - left_len, cover, f);
+    }
+}
+
+/// An inclusion proof for a range of bytes. See the [module docs](self).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proof {
+    input_len: u64,
+    range_start: u64,
+    range_len: u64,
+    // The bytes between the start of the first covered chunk and the start of the range, and
+    // between the end of the range and the end of the last covered chunk.
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    siblings: Vec<ChainingValue>,
+}
+
+impl Proof {
+    /// The total length of the input, as claimed by the proof.
+    pub fn input_len(&self) -> u64 {
+        self.input_len
+    }
+
+    /// The range of input bytes that the proof is for.
+    pub fn range(&self) -> Range<u64> {
+        self.range_start..self.range_start + self.range_len
+    }
+
+    /// The index of the first chunk that the range overlaps. For a range within a single chunk,
+    /// this is that chunk's index.
+    pub fn chunk_index(&self) -> u64 {
+        self.cover().0 / CHUNK_LEN as u64
+    }
+
+    /// The chaining values of the subtrees outside the range, in pre-order.
+    pub fn siblings(&self) -> &[ChainingValue] {
+        &self.siblings
+    }
+
+    fn cover(&self) -> (u64, u64) {
+        chunk_cover(
+            self.range_start,
+            self.range_start + self.range_len,
+            self.input_len,
+        )
+    }
+
+    /// Serialize the proof in its compact format.
+    ///
+    /// This format is stable. Version 1 is the version byte [`PROOF_VERSION`], then the input
+    /// length, the range start, and the range length as 8-byte little-endian integers, then the
+    /// number of chaining values as a single byte, then the bytes of the partial chunks before
+    /// and after the range, and finally the chaining values. The lengths of the partial chunks
+    /// aren't stored, because they follow from the range and the input length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            PROOF_HEADER_LEN + self.head.len() + self.tail.len() + OUT_LEN * self.siblings.len(),
+        );
+        bytes.push(PROOF_VERSION);
+        bytes.extend_from_slice(&self.input_len.to_le_bytes());
+        bytes.extend_from_slice(&self.range_start.to_le_bytes());
+        bytes.extend_from_slice(&self.range_len.to_le_bytes());
+        bytes.push(self.siblings.len() as u8);
+        bytes.extend_from_slice(&self.head);
+        bytes.extend_from_slice(&self.tail);
+        for sibling in &self.siblings {
+            bytes.extend_from_slice(sibling);
+        }
+        bytes
+    }
+
+    /// Parse a proof serialized with [`Proof::to_bytes`].
+    ///
+    /// The structure is fully validated. An unknown version, a range that extends past the
+    /// claimed input length, the wrong number of chaining values for the shape of the tree, or
+    /// trailing bytes are all errors. Whether the proof matches a root hash is only checked by
+    /// [`verify`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProofError> {
+        if bytes.len() < PROOF_HEADER_LEN {
+            return Err(ProofError(ProofErrorInner::WrongLength));
+        }
+        if bytes[0] != PROOF_VERSION {
+            return Err(ProofError(ProofErrorInner::UnknownVersion(bytes[0])));
+        }
+        let input_len = u64::from_le_bytes(*array_ref!(bytes, 1, 8));
+        let range_start = u64::from_le_bytes(*array_ref!(bytes, 9, 8));
+        let range_len = u64::from_le_bytes(*array_ref!(bytes, 17, 8));
+        let sibling_count = bytes[25] as usize;
+        let range_end = match range_start.checked_add(range_len) {
+            Some(end) if end <= input_len => end,
+            _ => return Err(ProofError(ProofErrorInner::InvalidRange)),
+        };
+        let cover = chunk_cover(range_start, range_end, input_len);
+        let mut expected_count = 0;
+        for_each_sibling(input_len, cover, &mut |_, _| expected_count += 1);
+        if sibling_count != expected_count {
+            return Err(ProofError(ProofErrorInner::WrongShape));
+        }
+        // Both of these are less than two chunks, so the casts can't truncate.
+        let head_len = (range_start.saturating_sub(cover.0)) as usize;
+        let tail_len = (cover.1.saturating_sub(range_end)) as usize;
+        let rest = &bytes[PROOF_HEADER_LEN..];
+        if rest.len() != head_len + tail_len + OUT_LEN * sibling_count {
+            return Err(ProofError(ProofErrorInner::WrongLength));
+        }
+        let (head, rest) = rest.split_at(head_len);
+        let (tail, rest) = rest.split_at(tail_len);
+        Ok(Self {
+            input_len,
+            range_start,
+            range_len,
+            head: head.to_vec(),
+            tail: tail.to_vec(),
+            siblings: rest
+                .chunks_exact(OUT_LEN)
+                .map(|cv| *array_ref!(cv, 0, OUT_LEN))
+                .collect(),
+        })
+    }
+}
+
+/// Make a proof that `input[range]` belongs to `input`, hashed in the given mode.
+///
+/// The range doesn't need to be chunk-aligned, and it can be empty. A proof of an empty range
+/// still shows that the input has the length the proof claims.
+///
+/// # Panics
+///
+/// Panics if the range is out of bounds, as slicing `input` with it would.
+pub fn prove(mode: Mode, input: &[u8], range: Range<usize>) -> Proof {
+    let range_len = input[range.clone()].len();
+    let cover = chunk_cover(range.start as u64, range.end as u64, input.len() as u64);
+    let mut siblings = Vec::new();
+    for_each_sibling(input.len() as u64, cover, &mut |start, len| {
+        let bytes = &input[start as usize..][..len as usize];
+        let subtree = Subtree::hash(mode, start, bytes).expect("tree nodes are valid subtrees");
+        siblings.push(*subtree.chaining_value());
+    });
+    Proof {
+        input_len: input.len() as u64,
+        range_start: range.start as u64,
+        range_len: range_len as u64,
+        head: input[cover.0 as usize..range.start].to_vec(),
+        tail: input[range.end..cover.1 as usize].to_vec(),
+        siblings,
+    }
+}
+
+// The verifier's view of the tree. `covered` holds the input bytes of the chunks in `cover`.
+struct Verifier<'a, I> {
+    mode: Mode<'a>,
+    platform: Platform,
+    cover: (u64, u64),
+    covered: &'a [u8],
+    siblings: I,
+}
+
+impl<'a, I: Iterator<Item = &'a ChainingValue>> Verifier<'a, I> {
+    fn parent_output(&self, left: &ChainingValue, right: &ChainingValue) -> crate::Output {
+        crate::parent_node_output(
+            left,
+            right,
+            &self.mode.key_words(),
+            self.mode.flags_byte(),
+            self.platform,
+        )
+    }
+
+    fn node_cv(&mut self, start: u64, len: u64) -> Result<ChainingValue, ProofError> {
+        let (cover_start, cover_end) = self.cover;
+        if start >= cover_end || start + len <= cover_start {
+            return match self.siblings.next() {
+                Some(cv) => Ok(*cv),
+                None => Err(ProofError(ProofErrorInner::WrongShape)),
+            };
+        }
+        if cover_start <= start && start + len <= cover_end {
+            let bytes = &self.covered[(start - cover_start) as usize..][..len as usize];
+            let subtree = Subtree::hash(self.mode, start, bytes).expect("tree nodes are valid");
+            return Ok(*subtree.chaining_value());
+        }
+        let left_len = hazmat::left_subtree_len(len);
+        let left_cv = self.node_cv(start, left_len)?;
+        let right_cv = self.node_cv(start + left_len, len - left_len)?;
+        Ok(self.parent_output(&left_cv, &right_cv).chaining_value())
+    }
+
+    fn root_hash(&mut self, input_len: u64) -> Result<Hash, ProofError> {
+        if input_len <= CHUNK_LEN as u64 {
+            // The whole input is one chunk, and the cover is all of it.
+            let mut chunk = crate::ChunkState::new(
+                &self.mode.key_words(),
+                0,
+                self.mode.flags_byte(),
+                self.platform,
+            );
+            chunk.update(self.covered);
+            return Ok(chunk.output().root_hash());
+        }
+        let left_len = hazmat::left_subtree_len(input_len);
+        let left_cv = self.node_cv(0, left_len)?;
+        let right_cv = self.node_cv(left_len, input_len - left_len)?;
+        Ok(self.parent_output(&left_cv, &right_cv).root_hash())
+    }
+}
+
+/// Check that `range_bytes` are the bytes in `proof.range()` of an input with the hash `root`,
+/// in the given mode.
+///
+/// This returns an error if `range_bytes` isn't the length of the range, or if the proof doesn't
+/// match `root`. The comparison with `root` is constant-time.
+pub fn verify(
+    mode: Mode,
+    root: &Hash,
+    range_bytes: &[u8],
+    proof: &Proof,
+) -> Result<(), ProofError> {
+    if range_bytes.len() as u64 != proof.range_len {
+        return Err(ProofError(ProofErrorInner::WrongRangeLen));
+    }
+    let mut covered = Vec::with_capacity(proof.head.len() + range_bytes.len() + proof.tail.len());
+    covered.extend_from_slice(&proof.head);
+    covered.extend_from_slice(range_bytes);
+    covered.extend_from_slice(&proof.tail);
+    let mut verifier = Verifier {
+        mode,
+        platform: Platform::detect(),
+        cover: proof.cover(),
+        covered: &covered,
+        siblings: proof.siblings.iter(),
+    };
+    let computed = verifier.root_hash(proof.input_len)?;
+    if verifier.siblings.next().is_some() {
+        return Err(ProofError(ProofErrorInner::WrongShape));
+    }
+    if computed != *root {
+        return Err(ProofError(ProofErrorInner::HashMismatch));
+    }
+    Ok(())
+}
+
+/// The error type for [`Proof::from_bytes`] and [`verify`].
+///
+/// The `.to_string()` representation of this error currently describes what went wrong. This is
+/// to help with logging and debugging, but it isn't a stable API detail, and it may change at any
+/// time.
+#[derive(Clone, Debug)]
+pub struct ProofError(ProofErrorInner);
+
+#[derive(Clone, Debug)]
+enum ProofErrorInner {
+    UnknownVersion(u8),
+    WrongLength,
+    InvalidRange,
+    WrongShape,
+    WrongRangeLen,
+    HashMismatch,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            ProofErrorInner::UnknownVersion(version) => {
+                write!(f, "unknown proof version {}", version)
+            }
+            ProofErrorInner::WrongLength => write!(f, "proof has the wrong length"),
+            ProofErrorInner::InvalidRange => {
+                write!(f, "range extends past the end of the input")
+            }
+            ProofErrorInner::WrongShape => write!(
+                f,
+                "number of chaining values doesn't match the shape of the tree"
+            ),
+            ProofErrorInner::WrongRangeLen => {
+                write!(f, "range bytes don't match the length of the range")
+            }
+            ProofErrorInner::HashMismatch => write!(f, "proof doesn't match the root hash"),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{paint_test_input, TEST_CASES, TEST_CASES_MAX, TEST_KEY};
+
+    const CONTEXT: &str = "BLAKE3 2019-12-27 16:29:52 test vectors context";
+
+    // Ranges that start and end inside, at the edges of, and across chunks.
+    fn test_ranges(input_len: usize) -> Vec<Range<usize>> {
+        let mut ranges = vec![0..0, 0..input_len, input_len..input_len];
+        for &start in &[1, CHUNK_LEN - 1, CHUNK_LEN, 3 * CHUNK_LEN + 7] {
+            for &len in &[0, 1, CHUNK_LEN, 2 * CHUNK_LEN + 1] {
+                if start + len <= input_len {
+                    ranges.push(start..start + len);
+                }
+            }
+        }
+        ranges
+    }
+
+    #[test]
+    fn test_prove_verify() {
+        let mut input = vec![0; TEST_CASES_MAX];
+        paint_test_input(&mut input);
+        let context_key = hazmat::hash_derive_key_context(CONTEXT);
+        for &case in TEST_CASES {
+            let input = &input[..case];
+            let modes = [
+                (Mode::Hash, crate::hash(input)),
+                (
+                    Mode::KeyedHash(&TEST_KEY),
+                    crate::keyed_hash(&TEST_KEY, input),
+                ),
+                (
+                    Mode::DeriveKeyMaterial(&context_key),
+                    Hash::from(crate::derive_key(CONTEXT, input)),
+                ),
+            ];
+            for (mode, hash) in modes {
+                for range in test_ranges(case) {
+                    let proof = prove(mode, input, range.clone());
+                    assert_eq!(proof.input_len(), case as u64);
+                    assert_eq!(proof.range(), range.start as u64..range.end as u64);
+                    let parsed = Proof::from_bytes(&proof.to_bytes()).unwrap();
+                    assert_eq!(proof, parsed);
+                    verify(mode, &hash, &input[range.clone()], &parsed).unwrap();
+                    // The same proof doesn't verify in a different mode.
+                    verify(
+                        Mode::Hash,
+                        &crate::keyed_hash(&TEST_KEY, input),
+                        &input[range],
+                        &proof,
+                    )
+                    .unwrap_err();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_proof_size() {
+        let input = vec![0; 1 << 20];
+        let proof = prove(Mode::Hash, &input, 42 * CHUNK_LEN..43 * CHUNK_LEN);
+        assert_eq!(proof.chunk_index(), 42);
+        // One sibling per level of a 1024-chunk tree.
+        assert_eq!(proof.siblings().len(), 10);
+        assert_eq!(proof.to_bytes().len(), PROOF_HEADER_LEN + 10 * OUT_LEN);
+        // An unaligned range carries the rest of its chunks.
+        let proof = prove(Mode::Hash, &input, 42 * CHUNK_LEN + 1..43 * CHUNK_LEN + 1);
+        // It covers chunks 42 and 43, which form a single subtree one level up.
+        assert_eq!(proof.chunk_index(), 42);
+        assert_eq!(proof.siblings().len(), 9);
+        assert_eq!(
+            proof.to_bytes().len(),
+            PROOF_HEADER_LEN + CHUNK_LEN + 9 * OUT_LEN,
+        );
+    }
+
+    #[test]
+    fn test_corrupted_proof() {
+        let mut input = vec![0; 10 * CHUNK_LEN + 1];
+        paint_test_input(&mut input);
+        let hash = crate::hash(&input);
+        let range = 3 * CHUNK_LEN + 1..5 * CHUNK_LEN - 1;
+        let bytes = prove(Mode::Hash, &input, range.clone()).to_bytes();
+        for i in PROOF_HEADER_LEN..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 1;
+            let proof = Proof::from_bytes(&corrupted).unwrap();
+            verify(Mode::Hash, &hash, &input[range.clone()], &proof).unwrap_err();
+        }
+        let proof = Proof::from_bytes(&bytes).unwrap();
+        let mut range_bytes = input[range].to_vec();
+        range_bytes[0] ^= 1;
+        verify(Mode::Hash, &hash, &range_bytes, &proof).unwrap_err();
+        verify(Mode::Hash, &hash, &range_bytes[1..], &proof).unwrap_err();
+    }
+
+    #[test]
+    fn test_inconsistent_length() {
+        let mut input = vec![0; 10 * CHUNK_LEN + 1];
+        paint_test_input(&mut input);
+        let hash = crate::hash(&input);
+        let range = 2 * CHUNK_LEN..3 * CHUNK_LEN;
+        let bytes = prove(Mode::Hash, &input, range.clone()).to_bytes();
+        // Any length that changes the shape of the tree along the path to the range is rejected,
+        // either because the number of chaining values is wrong or because the root is.
+        for claimed_len in [
+            0,
+            3 * CHUNK_LEN,
+            4 * CHUNK_LEN,
+            8 * CHUNK_LEN,
+            16 * CHUNK_LEN + 1,
+        ] {
+            let mut changed = bytes.clone();
+            changed[1..9].copy_from_slice(&(claimed_len as u64).to_le_bytes());
+            if let Ok(proof) = Proof::from_bytes(&changed) {
+                verify(Mode::Hash, &hash, &input[range.clone()], &proof).unwrap_err();
+            }
+        }
+        // A proof that includes the last chunk pins down the exact length.
+        let end = input.len()..input.len();
+        let end_bytes = prove(Mode::Hash, &input, end.clone()).to_bytes();
+        for claimed_len in [input.len() - 1, input.len() + 1] {
+            let mut changed = end_bytes.clone();
+            changed[1..9].copy_from_slice(&(claimed_len as u64).to_le_bytes());
+            changed[9..17].copy_from_slice(&(claimed_len as u64).to_le_bytes());
+            if let Ok(proof) = Proof::from_bytes(&changed) {
+                verify(Mode::Hash, &hash, &[], &proof).unwrap_err();
+            }
+        }
+        // Truncated, extended, and unknown-version proofs are rejected outright.
+        Proof::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+        Proof::from_bytes(&[&bytes[..], &[0]].concat()).unwrap_err();
+        Proof::from_bytes(&bytes[..PROOF_HEADER_LEN - 1]).unwrap_err();
+        let mut changed = bytes.clone();
+        changed[0] = 2;
+        Proof::from_bytes(&changed).unwrap_err();
+        // A range past the end of the input.
+        let mut changed = bytes;
+        changed[9..17].copy_from_slice(&u64::MAX.to_le_bytes());
+        Proof::from_bytes(&changed).unwrap_err();
+    }
+}
@@ -0,0 +1,265 @@
+//! Multi-block XOF output for x86, computing several blocks at a time with SIMD. This is shared
+//! by the intrinsics and the assembly builds, since the assembly implementations don't have a
+//! multi-block XOF. Every output block compresses the same message with the same CV, so the
+//! message words are broadcast rather than transposed, and only the counter differs between
+//! lanes. That means one generic round function covers every vector width.
+
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+use crate::{counter_high, counter_low, CVWords, BLOCK_LEN, IV, MSG_SCHEDULE};
+
+const MAX_LANES: usize = 8;
+
+// A vector of 32-bit words, one word per output block.
+trait Vector: Copy {
+    const LANES: usize;
+    unsafe fn set1(x: u32) -> Self;
+    unsafe fn loadu(words: &[u32; MAX_LANES]) -> Self;
+    unsafe fn storeu(self, words: &mut [u32; MAX_LANES]);
+    unsafe fn add(self, other: Self) -> Self;
+    unsafe fn xor(self, other: Self) -> Self;
+    unsafe fn rot(self, bits: i32) -> Self;
+}
+
+// These rotations are the "simple/shifts version", like in rust_sse2.rs, which works with SSE2
+// alone.
+impl Vector for __m128i {
+    const LANES: usize = 4;
+
+    #[inline(always)]
+    unsafe fn set1(x: u32) -> Self {
+        _mm_set1_epi32(x as i32)
+    }
+
+    #[inline(always)]
+    unsafe fn loadu(words: &[u32; MAX_LANES]) -> Self {
+        // This is an unaligned load, so the pointer cast is allowed.
+        _mm_loadu_si128(words.as_ptr() as *const __m128i)
+    }
+
+    #[inline(always)]
+    unsafe fn storeu(self, words: &mut [u32; MAX_LANES]) {
+        // This is an unaligned store, so the pointer cast is allowed.
+        _mm_storeu_si128(words.as_mut_ptr() as *mut __m128i, self)
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        _mm_add_epi32(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        _mm_xor_si128(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn rot(self, bits: i32) -> Self {
+        _mm_or_si128(
+            _mm_srl_epi32(self, _mm_cvtsi32_si128(bits)),
+            _mm_sll_epi32(self, _mm_cvtsi32_si128(32 - bits)),
+        )
+    }
+}
+
+impl Vector for __m256i {
+    const LANES: usize = 8;
+
+    #[inline(always)]
+    unsafe fn set1(x: u32) -> Self {
+        _mm256_set1_epi32(x as i32)
+    }
+
+    #[inline(always)]
+    unsafe fn loadu(words: &[u32; MAX_LANES]) -> Self {
+        // This is an unaligned load, so the pointer cast is allowed.
+        _mm256_loadu_si256(words.as_ptr() as *const __m256i)
+    }
+
+    #[inline(always)]
+    unsafe fn storeu(self, words: &mut [u32; MAX_LANES]) {
+        // This is an unaligned store, so the pointer cast is allowed.
+        _mm256_storeu_si256(words.as_mut_ptr() as *mut __m256i, self)
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        _mm256_add_epi32(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        _mm256_xor_si256(self, other)
+    }
+
+    #[inline(always)]
+    unsafe fn rot(self, bits: i32) -> Self {
+        _mm256_or_si256(
+            _mm256_srl_epi32(self, _mm_cvtsi32_si128(bits)),
+            _mm256_sll_epi32(self, _mm_cvtsi32_si128(32 - bits)),
+        )
+    }
+}
+
+// The G function, as in portable.rs.
+#[inline(always)]
+unsafe fn g<V: Vector>(state: &mut [V; 16], a: usize, b: usize, c: usize, d: usize, x: V, y: V) {
+    state[a] = state[a].add(state[b]).add(x);
+    state[d] = state[d].xor(state[a]).rot(16);
+    state[c] = state[c].add(state[d]);
+    state[b] = state[b].xor(state[c]).rot(12);
+    state[a] = state[a].add(state[b]).add(y);
+    state[d] = state[d].xor(state[a]).rot(8);
+    state[c] = state[c].add(state[d]);
+    state[b] = state[b].xor(state[c]).rot(7);
+}
+
+#[inline(always)]
+unsafe fn round<V: Vector>(state: &mut [V; 16], msg: &[V; 16], round: usize) {
+    // Select the message schedule based on the round.
+    let schedule = MSG_SCHEDULE[round];
+
+    // Mix the columns.
+    g(state, 0, 4, 8, 12, msg[schedule[0]], msg[schedule[1]]);
+    g(state, 1, 5, 9, 13, msg[schedule[2]], msg[schedule[3]]);
+    g(state, 2, 6, 10, 14, msg[schedule[4]], msg[schedule[5]]);
+    g(state, 3, 7, 11, 15, msg[schedule[6]], msg[schedule[7]]);
+
+    // Mix the diagonals.
+    g(state, 0, 5, 10, 15, msg[schedule[8]], msg[schedule[9]]);
+    g(state, 1, 6, 11, 12, msg[schedule[10]], msg[schedule[11]]);
+    g(state, 2, 7, 8, 13, msg[schedule[12]], msg[schedule[13]]);
+    g(state, 3, 4, 9, 14, msg[schedule[14]], msg[schedule[15]]);
+}
+
+// Write V::LANES output blocks to the front of `out`, starting at `counter`.
+#[inline(always)]
+unsafe fn xof_lanes<V: Vector>(
+    cv: &CVWords,
+    msg: &[V; 16],
+    block_len: u8,
+    counter: u64,
+    flags: u8,
+    out: &mut [u8],
+) {
+    let mut counter_low_words = [0; MAX_LANES];
+    let mut counter_high_words = [0; MAX_LANES];
+    for lane in 0..V::LANES {
+        counter_low_words[lane] = counter_low(counter + lane as u64);
+        counter_high_words[lane] = counter_high(counter + lane as u64);
+    }
+    let mut state = [
+        V::set1(cv[0]),
+        V::set1(cv[1]),
+        V::set1(cv[2]),
+        V::set1(cv[3]),
+        V::set1(cv[4]),
+        V::set1(cv[5]),
+        V::set1(cv[6]),
+        V::set1(cv[7]),
+        V::set1(IV[0]),
+        V::set1(IV[1]),
+        V::set1(IV[2]),
+        V::set1(IV[3]),
+        V::loadu(&counter_low_words),
+        V::loadu(&counter_high_words),
+        V::set1(block_len as u32),
+        V::set1(flags as u32),
+    ];
+    round(&mut state, msg, 0);
+    round(&mut state, msg, 1);
+    round(&mut state, msg, 2);
+    round(&mut state, msg, 3);
+    round(&mut state, msg, 4);
+    round(&mut state, msg, 5);
+    round(&mut state, msg, 6);
+
+    // Word i of every output block is in state vector i, one block per lane. Transposing through
+    // memory is cheap next to the compression itself.
+    let mut words = [[0; MAX_LANES]; 16];
+    for i in 0..8 {
+        state[i].xor(state[i + 8]).storeu(&mut words[i]);
+        state[i + 8].xor(V::set1(cv[i])).storeu(&mut words[i + 8]);
+    }
+    for (lane, out_block) in out.chunks_exact_mut(BLOCK_LEN).take(V::LANES).enumerate() {
+        for (i, out_word) in out_block.chunks_exact_mut(4).enumerate() {
+            out_word.copy_from_slice(&words[i][lane].to_le_bytes());
+        }
+    }
+}
+
+// Fill as many whole groups of V::LANES blocks at the front of `out` as there are, and return the
+// number of blocks written. The caller computes the rest one at a time.
+#[inline(always)]
+unsafe fn xof_many<V: Vector>(
+    cv: &CVWords,
+    block: &[u8; BLOCK_LEN],
+    block_len: u8,
+    mut counter: u64,
+    flags: u8,
+    out: &mut [u8],
+) -> usize {
+    debug_assert_eq!(0, out.len() % BLOCK_LEN, "whole blocks only");
+    let block_words = crate::platform::words_from_le_bytes_64(block);
+    let mut msg = [V::set1(0); 16];
+    for i in 0..16 {
+        msg[i] = V::set1(block_words[i]);
+    }
+    let groups = out.len() / (V::LANES * BLOCK_LEN);
+    for out_group in out.chunks_exact_mut(V::LANES * BLOCK_LEN) {
+        xof_lanes(cv, &msg, block_len, counter, flags, out_group);
+        counter += V::LANES as u64;
+    }
+    groups * V::LANES
+}
+
+// Unsafe because this may only be called on platforms supporting SSE2. SSE4.1 adds nothing that
+// this needs.
+#[target_feature(enable = "sse2")]
+pub unsafe fn xof_many_sse2(
+    cv: &CVWords,
+    block: &[u8; BLOCK_LEN],
+    block_len: u8,
+    counter: u64,
+    flags: u8,
+    out: &mut [u8],
+) -> usize {
+    xof_many::<__m128i>(cv, block, block_len, counter, flags, out)
+}
+
+// Unsafe because this may only be called on platforms supporting AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn xof_many_avx2(
+    cv: &CVWords,
+    block: &[u8; BLOCK_LEN],
+    block_len: u8,
+    counter: u64,
+    flags: u8,
+    out: &mut [u8],
+) -> usize {
+    xof_many::<__m256i>(cv, block, block_len, counter, flags, out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_xof_many_sse2() {
+        if !crate::platform::sse2_detected() {
+            return;
+        }
+        crate::test::test_xof_many_fn(xof_many_sse2);
+    }
+
+    #[test]
+    fn test_xof_many_avx2() {
+        if !crate::platform::avx2_detected() {
+            return;
+        }
+        crate::test::test_xof_many_fn(xof_many_avx2);
+    }
+}
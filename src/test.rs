@@ -1,3 +1,4 @@
+use crate::platform::Platform;
 use crate::{CVBytes, CVWords, IncrementCounter, BLOCK_LEN, CHUNK_LEN, OUT_LEN};
 use arrayref::array_ref;
 use arrayvec::ArrayVec;
@@ -206,6 +207,103 @@ pub fn test_hash_many_fn(
     }
 }
 
+type XofManyFn = unsafe fn(
+    cv: &CVWords,
+    block: &[u8; BLOCK_LEN],
+    block_len: u8,
+    counter: u64,
+    flags: u8,
+    out: &mut [u8],
+) -> usize;
+
+// A shared helper function for platform-specific tests. The SIMD implementations only fill whole
+// groups of blocks, and they return the number of blocks they wrote.
+pub fn test_xof_many_fn(xof_many_fn: XofManyFn) {
+    check_xof_many(|cv, block, block_len, counter, flags, out| unsafe {
+        let written = xof_many_fn(cv, block, block_len, counter, flags, out);
+        // No SIMD group is wider than 8 blocks.
+        assert!(written * BLOCK_LEN <= out.len());
+        assert!((written + 8) * BLOCK_LEN > out.len());
+        written
+    });
+}
+
+fn check_xof_many(
+    mut xof_many_fn: impl FnMut(&CVWords, &[u8; BLOCK_LEN], u8, u64, u8, &mut [u8]) -> usize,
+) {
+    let block_len: u8 = 61;
+    let mut block = [0; BLOCK_LEN];
+    paint_test_input(&mut block[..block_len as usize]);
+    let flags = crate::CHUNK_END | crate::ROOT | crate::KEYED_HASH;
+    // Test a few different initial counter values, as in test_hash_many_fn(). The second one makes
+    // the low word of the counter overflow partway through a SIMD batch.
+    let initial_counters = [0, u32::MAX as u64 - 2, i32::MAX as u64];
+    for counter in initial_counters {
+        #[cfg(feature = "std")]
+        dbg!(counter);
+
+        // 31 (16 + 8 + 4 + 2 + 1) outputs
+        const NUM_OUTPUTS: usize = 31;
+        let mut portable_out = [0; NUM_OUTPUTS * BLOCK_LEN];
+        for (i, out_block) in portable_out.chunks_exact_mut(BLOCK_LEN).enumerate() {
+            out_block.copy_from_slice(&crate::portable::compress_xof(
+                &TEST_KEY_WORDS,
+                &block,
+                block_len,
+                counter + i as u64,
+                flags,
+            ));
+        }
+
+        // Test every output length, so that both full SIMD batches and leftover blocks get
+        // exercised.
+        for num_outputs in 0..=NUM_OUTPUTS {
+            #[cfg(feature = "std")]
+            dbg!(num_outputs);
+            let mut test_out = [0; NUM_OUTPUTS * BLOCK_LEN];
+            let written = xof_many_fn(
+                &TEST_KEY_WORDS,
+                &block,
+                block_len,
+                counter,
+                flags,
+                &mut test_out[..num_outputs * BLOCK_LEN],
+            );
+            assert_eq!(
+                &portable_out[..written * BLOCK_LEN],
+                &test_out[..written * BLOCK_LEN],
+            );
+            // Nothing past what was written should be touched.
+            assert!(test_out[written * BLOCK_LEN..].iter().all(|&b| b == 0));
+        }
+    }
+}
+
+#[test]
+fn test_xof_many_platforms() {
+    let mut platforms = ArrayVec::<Platform, 6>::new();
+    platforms.push(Platform::portable());
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        platforms.extend(Platform::sse2());
+        platforms.extend(Platform::sse41());
+        platforms.extend(Platform::avx2());
+        #[cfg(blake3_avx512_ffi)]
+        platforms.extend(Platform::avx512());
+    }
+    #[cfg(blake3_neon)]
+    platforms.extend(Platform::neon());
+    for platform in platforms {
+        #[cfg(feature = "std")]
+        dbg!(platform);
+        // Platform::xof_many() always writes every block, including the leftovers.
+        check_xof_many(|cv, block, block_len, counter, flags, out| {
+            platform.xof_many(cv, block, block_len, counter, flags, out);
+            out.len() / BLOCK_LEN
+        });
+    }
+}
+
 #[test]
 fn test_key_bytes_equal_key_words() {
     assert_eq!(
@@ -493,6 +591,72 @@ fn test_xof_seek() {
     }
 }
 
+#[test]
+fn test_xof_fill_unaligned() {
+    // Fill the expected output one byte at a time, which only ever uses single-block compression.
+    // Then check that multi-block fills agree with it, for a variety of unaligned starting
+    // positions and lengths.
+    const OUT_LEN_TOTAL: usize = 20 * BLOCK_LEN;
+    let mut hasher = crate::Hasher::new_keyed(&TEST_KEY);
+    hasher.update(b"foo");
+    let mut expected = [0; OUT_LEN_TOTAL];
+    let mut reader = hasher.finalize_xof();
+    for byte in expected.iter_mut() {
+        reader.fill(core::slice::from_mut(byte));
+    }
+
+    for &start in &[0, 1, 63, 64, 65, 130] {
+        for &len in &[
+            0,
+            1,
+            63,
+            64,
+            65,
+            127,
+            128,
+            129,
+            8 * BLOCK_LEN + 3,
+            16 * BLOCK_LEN,
+        ] {
+            let mut reader = hasher.finalize_xof();
+            reader.set_position(start as u64);
+            let mut out = [0; OUT_LEN_TOTAL];
+            reader.fill(&mut out[..len]);
+            assert_eq!(&expected[start..][..len], &out[..len]);
+            assert_eq!((start + len) as u64, reader.position());
+            // Reading on from there should also agree.
+            reader.fill(&mut out[..BLOCK_LEN + 1]);
+            assert_eq!(
+                &expected[start + len..][..BLOCK_LEN + 1],
+                &out[..BLOCK_LEN + 1]
+            );
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "rayon")]
+fn test_xof_fill_rayon() {
+    // Long enough to get split across several threads.
+    const OUT_LEN_TOTAL: usize = 1 << 20;
+    let mut hasher = crate::Hasher::new();
+    hasher.update(b"foo");
+    let mut expected = vec![0; OUT_LEN_TOTAL];
+    hasher.finalize_xof().fill(&mut expected);
+
+    let mut out = vec![0; OUT_LEN_TOTAL];
+    for &start in &[0, 1, 64, 1000] {
+        for &len in &[0, 1, 63, 64, 65, 100_000, OUT_LEN_TOTAL - 1000] {
+            let mut reader = hasher.finalize_xof();
+            reader.set_position(start as u64);
+            out.iter_mut().for_each(|b| *b = 0);
+            reader.fill_rayon(&mut out[..len]);
+            assert_eq!(&expected[start..][..len], &out[..len]);
+            assert_eq!((start + len) as u64, reader.position());
+        }
+    }
+}
+
 #[test]
 fn test_msg_schedule_permutation() {
     let permutation = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
@@ -747,6 +911,124 @@ fn test_update_reader_interrupted() -> std::io::Result<()> {
     Ok(())
 }
 
+#[test]
+#[cfg(feature = "rayon")]
+fn test_update_reader_rayon() -> std::io::Result<()> {
+    // Return short reads, so that the buffers have to be filled in several steps.
+    struct ShortReader<'a>(&'a [u8]);
+    impl std::io::Read for ShortReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let take = std::cmp::min(std::cmp::min(self.0.len(), buf.len()), 1000);
+            buf[..take].copy_from_slice(&self.0[..take]);
+            self.0 = &self.0[take..];
+            Ok(take)
+        }
+    }
+
+    let mut input = vec![0; 3 << 20];
+    paint_test_input(&mut input);
+    for &len in &[
+        0,
+        1,
+        CHUNK_LEN,
+        4 * CHUNK_LEN,
+        13 * CHUNK_LEN + 5,
+        input.len(),
+    ] {
+        let input = &input[..len];
+        for &buffer_len in &[CHUNK_LEN, 4 * CHUNK_LEN, 1 << 20] {
+            let mut hasher = crate::Hasher::new();
+            hasher.update_reader_rayon_with_buffer_len(ShortReader(input), buffer_len)?;
+            assert_eq!(hasher.finalize(), crate::hash(input));
+
+            // Start from a hasher that isn't chunk-aligned.
+            let mut hasher = crate::Hasher::new();
+            hasher.update(b"x");
+            hasher.update_reader_rayon_with_buffer_len(ShortReader(input), buffer_len)?;
+            let mut expected = crate::Hasher::new();
+            expected.update(b"x").update(input);
+            assert_eq!(hasher.finalize(), expected.finalize());
+        }
+        let mut hasher = crate::Hasher::new();
+        hasher.update_reader_rayon(ShortReader(input))?;
+        assert_eq!(hasher.finalize(), crate::hash(input));
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "rayon")]
+#[should_panic]
+fn test_update_reader_rayon_bad_buffer_len() {
+    let _ = crate::Hasher::new().update_reader_rayon_with_buffer_len(&b"foo"[..], 3000);
+}
+
+#[test]
+#[cfg(feature = "tokio")]
+fn test_tokio() -> std::io::Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    let mut input = vec![0; 1_000_000];
+    paint_test_input(&mut input);
+    futures::executor::block_on(async {
+        // A small duplex buffer makes the reader see lots of short reads.
+        let (mut writer, reader) = tokio::io::duplex(1000);
+        let write_all = async {
+            writer.write_all(&input).await?;
+            writer.shutdown().await
+        };
+        let mut hasher = crate::Hasher::new();
+        let (written, updated) = futures::join!(write_all, hasher.update_async_reader(reader));
+        written?;
+        assert_eq!(updated?.finalize(), crate::hash(&input));
+
+        let mut hasher = crate::Hasher::new();
+        hasher.write_all(&input).await?;
+        assert_eq!(hasher.finalize(), crate::hash(&input));
+
+        let mut expected = [0; 1000];
+        hasher.finalize_xof().fill(&mut expected);
+        let mut reader = hasher.finalize_xof();
+        let mut output = [0; 1000];
+        reader.read_exact(&mut output).await?;
+        assert_eq!(output, expected);
+        assert_eq!(reader.seek(std::io::SeekFrom::Start(100)).await?, 100);
+        reader.read_exact(&mut output[..100]).await?;
+        assert_eq!(output[..100], expected[100..200]);
+        Ok(())
+    })
+}
+
+#[test]
+#[cfg(feature = "futures-io")]
+fn test_futures_io() -> std::io::Result<()> {
+    use futures::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    let mut input = vec![0; 1_000_000];
+    paint_test_input(&mut input);
+    futures::executor::block_on(async {
+        let mut hasher = crate::Hasher::new();
+        hasher
+            .update_futures_reader(futures::io::Cursor::new(&input))
+            .await?;
+        assert_eq!(hasher.finalize(), crate::hash(&input));
+
+        let mut hasher = crate::Hasher::new();
+        hasher.write_all(&input).await?;
+        hasher.close().await?;
+        assert_eq!(hasher.finalize(), crate::hash(&input));
+
+        let mut expected = [0; 1000];
+        hasher.finalize_xof().fill(&mut expected);
+        let mut reader = hasher.finalize_xof();
+        let mut output = [0; 1000];
+        reader.read_exact(&mut output).await?;
+        assert_eq!(output, expected);
+        assert_eq!(reader.seek(std::io::SeekFrom::Current(-900)).await?, 100);
+        reader.read_exact(&mut output[..100]).await?;
+        assert_eq!(output[..100], expected[100..200]);
+        Ok(())
+    })
+}
+
 #[test]
 #[cfg(feature = "mmap")]
 fn test_mmap() -> Result<(), std::io::Error> {
@@ -807,6 +1089,97 @@ fn test_mmap_rayon() -> Result<(), std::io::Error> {
     Ok(())
 }
 
+#[test]
+#[cfg(feature = "mmap")]
+fn test_mmap_options() -> Result<(), std::io::Error> {
+    use crate::MmapOptions;
+    use std::io::prelude::*;
+    let mut input = vec![0; 1_000_000];
+    paint_test_input(&mut input);
+    let mut tempfile = tempfile::NamedTempFile::new()?;
+    tempfile.write_all(&input)?;
+    tempfile.flush()?;
+    let expected = crate::hash(&input);
+    let all_options = [
+        MmapOptions::new(),
+        // Too short to map, so it's read instead.
+        MmapOptions::new().min_len(2_000_000),
+        // Windows that don't divide the file evenly.
+        MmapOptions::new().window_len(64 * 1024),
+        MmapOptions::new().window_len(3 * CHUNK_LEN as u64),
+        MmapOptions::new()
+            .window_len(1 << 20)
+            .sequential(true)
+            .will_need(true)
+            .huge_pages(true),
+    ];
+    for options in &all_options {
+        let mut hasher = crate::Hasher::new();
+        hasher.update_mmap_with_options(tempfile.path(), options)?;
+        assert_eq!(hasher.finalize(), expected);
+        #[cfg(feature = "rayon")]
+        {
+            let mut hasher = crate::Hasher::new();
+            hasher.update_mmap_rayon_with_options(tempfile.path(), options)?;
+            assert_eq!(hasher.finalize(), expected);
+        }
+    }
+
+    // Windows that would break chunk alignment are rejected, even for short files.
+    for &window_len in &[0, 1, CHUNK_LEN as u64 + 1, 1000] {
+        let options = MmapOptions::new().window_len(window_len);
+        let err = crate::Hasher::new()
+            .update_mmap_with_options(tempfile.path(), &options)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "io-uring")]
+#[cfg(target_os = "linux")]
+fn test_update_file_direct() -> Result<(), std::io::Error> {
+    use std::io::prelude::*;
+    let mut input = vec![0; 5 * (1 << 20) + 12345];
+    paint_test_input(&mut input);
+    // Sizes around the 4096-byte alignment, the 1 MiB buffer length, and the four buffers in
+    // flight.
+    let lengths = [
+        0,
+        1,
+        4096,
+        4097,
+        1 << 20,
+        (1 << 20) + 1,
+        4 << 20,
+        input.len(),
+    ];
+    for &len in &lengths {
+        let mut tempfile = tempfile::NamedTempFile::new()?;
+        tempfile.write_all(&input[..len])?;
+        tempfile.flush()?;
+        let mut hasher = crate::Hasher::new();
+        hasher.update_file_direct(tempfile.path())?;
+        assert_eq!(hasher.finalize(), crate::hash(&input[..len]), "len {}", len);
+    }
+
+    // tmpfs refuses O_DIRECT before Linux 6.6, which exercises the fallback there.
+    if let Ok(mut tempfile) = tempfile::NamedTempFile::new_in("/dev/shm") {
+        tempfile.write_all(&input)?;
+        tempfile.flush()?;
+        let mut hasher = crate::Hasher::new();
+        hasher.update_file_direct(tempfile.path())?;
+        assert_eq!(hasher.finalize(), crate::hash(&input));
+    }
+
+    let err = crate::Hasher::new()
+        .update_file_direct("/this/path/does/not/exist")
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    Ok(())
+}
+
 #[test]
 #[cfg(feature = "std")]
 #[cfg(feature = "serde")]
@@ -820,3 +1193,286 @@ fn test_serde() {
     let hash2: crate::Hash = serde_json::from_str(&json).unwrap();
     assert_eq!(hash, hash2);
 }
+
+#[test]
+fn test_export_import_state() {
+    // Split the input at a variety of points, so that the exported state covers empty chunks, full
+    // chunks, partial blocks, and lazily merged CV stacks.
+    let mut input_buf = [0; 2 * TEST_CASES_MAX];
+    paint_test_input(&mut input_buf);
+    let context = "BLAKE3 2023-10-02 12:34:56 export state test";
+    for &first_update in TEST_CASES {
+        #[cfg(feature = "std")]
+        dbg!(first_update);
+        let total_input = &input_buf[..first_update + 1000];
+        let hashers = [
+            crate::Hasher::new(),
+            crate::Hasher::new_keyed(&TEST_KEY),
+            crate::Hasher::new_derive_key(context),
+        ];
+        for mut hasher in hashers {
+            let mut expected_hasher = hasher.clone();
+            expected_hasher.update(total_input);
+
+            hasher.update(&total_input[..first_update]);
+            let state = hasher.export_state();
+            assert!(state.len() <= crate::MAX_STATE_LEN);
+            let mut resumed = crate::Hasher::import_state(&state).unwrap();
+            assert_eq!(resumed.count(), first_update as u64);
+            assert_eq!(resumed.finalize(), hasher.finalize());
+            assert_eq!(&resumed.export_state()[..], &state[..]);
+            resumed.update(&total_input[first_update..]);
+            assert_eq!(resumed.finalize(), expected_hasher.finalize());
+        }
+    }
+
+    // The largest possible state round trips.
+    let mut hasher = crate::Hasher {
+        key: *crate::IV,
+        chunk_state: crate::ChunkState::new(
+            crate::IV,
+            (1 << crate::MAX_DEPTH) - 1,
+            0,
+            crate::Platform::detect(),
+        ),
+        cv_stack: [[42; 32]; crate::MAX_DEPTH].into_iter().collect(),
+    };
+    hasher.update(&[0; BLOCK_LEN]);
+    let state = hasher.export_state();
+    assert_eq!(state.len(), 77 + BLOCK_LEN + crate::MAX_DEPTH * OUT_LEN);
+    let resumed = crate::Hasher::import_state(&state).unwrap();
+    assert_eq!(resumed.finalize(), hasher.finalize());
+}
+
+#[test]
+fn test_import_state_errors() {
+    let mut hasher = crate::Hasher::new_keyed(&TEST_KEY);
+    hasher.update(&[42; 2 * CHUNK_LEN + 10]);
+    let state = hasher.export_state();
+    assert_eq!(state.len(), 77 + 10 + OUT_LEN);
+    crate::Hasher::import_state(&state).unwrap();
+
+    // Every truncation is an error.
+    for len in 0..state.len() {
+        crate::Hasher::import_state(&state[..len]).unwrap_err();
+    }
+
+    // Trailing bytes.
+    let mut long = state.clone();
+    long.push(0);
+    crate::Hasher::import_state(&long).unwrap_err();
+
+    // Unknown version.
+    let mut bad = state.clone();
+    bad[0] = 2;
+    crate::Hasher::import_state(&bad).unwrap_err();
+
+    // Unknown flags, including the internal ones.
+    for flags in [
+        crate::ROOT,
+        crate::PARENT,
+        crate::KEYED_HASH | crate::CHUNK_START,
+    ] {
+        let mut bad = state.clone();
+        bad[1] = flags;
+        crate::Hasher::import_state(&bad).unwrap_err();
+    }
+
+    // The regular hash mode with a key other than the IV.
+    let mut bad = state.clone();
+    bad[1] = 0;
+    crate::Hasher::import_state(&bad).unwrap_err();
+
+    // Too many blocks in the chunk.
+    let mut bad = state.clone();
+    bad[74] = 16;
+    crate::Hasher::import_state(&bad).unwrap_err();
+
+    // Compressed blocks with an empty buffer.
+    let mut empty_buf = crate::Hasher::new();
+    empty_buf.update(&[42; 2 * CHUNK_LEN]);
+    let mut bad = empty_buf.export_state();
+    assert_eq!(bad[74], 0);
+    assert_eq!(bad[75], 0);
+    crate::Hasher::import_state(&bad).unwrap();
+    bad[74] = 1;
+    crate::Hasher::import_state(&bad).unwrap_err();
+
+    // A buffer longer than a block.
+    let mut bad = state.clone();
+    bad[75] = BLOCK_LEN as u8 + 1;
+    crate::Hasher::import_state(&bad).unwrap_err();
+
+    // A chunk counter of 3 needs exactly 2 CVs in the stack when the chunk isn't empty.
+    let mut bad = state.clone();
+    bad[34] = 3;
+    crate::Hasher::import_state(&bad).unwrap_err();
+
+    // A chunk counter that's too large.
+    let mut bad = state.clone();
+    bad[34..42].copy_from_slice(&(1u64 << crate::MAX_DEPTH).to_le_bytes());
+    crate::Hasher::import_state(&bad).unwrap_err();
+
+    // An empty chunk state with lazily merged CVs. 4 chunks were added all at once, so the stack
+    // holds 2 CVs. Adding them 2 at a time would've left 3 CVs. Any other depth is impossible.
+    let mut lazy = crate::Hasher::new();
+    lazy.update(&[42; 4 * CHUNK_LEN]);
+    let lazy_state = lazy.export_state();
+    assert_eq!(lazy_state[76], 2);
+    let mut lazier = crate::Hasher::new();
+    lazier.update(&[42; 2 * CHUNK_LEN]);
+    lazier.update(&[42; 2 * CHUNK_LEN]);
+    let lazier_state = lazier.export_state();
+    assert_eq!(lazier_state[76], 3);
+    let resumed = crate::Hasher::import_state(&lazier_state).unwrap();
+    assert_eq!(resumed.finalize(), lazy.finalize());
+    let mut bad = lazier_state.clone();
+    bad[76] = 4;
+    bad.extend([0; OUT_LEN]);
+    crate::Hasher::import_state(&bad).unwrap_err();
+    let mut bad = lazy_state.clone();
+    bad[76] = 1;
+    bad.truncate(bad.len() - OUT_LEN);
+    crate::Hasher::import_state(&bad).unwrap_err();
+
+    // Flipping any single bit never panics, and any state that imports successfully still
+    // finalizes without panicking.
+    for i in 0..state.len() {
+        for bit in 0..8 {
+            let mut mutated = state.clone();
+            mutated[i] ^= 1 << bit;
+            if let Ok(mut hasher) = crate::Hasher::import_state(&mutated) {
+                hasher.finalize();
+                hasher.update(&[0; 3 * CHUNK_LEN]);
+                hasher.finalize();
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    {
+        let err = crate::Hasher::import_state(&state[..10]).unwrap_err();
+        assert_eq!(err.to_string(), "hasher state is truncated");
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+#[cfg(feature = "serde")]
+fn test_serde_hasher() {
+    let mut hasher = crate::Hasher::new();
+    hasher.update(&[42; 3 * CHUNK_LEN + 7]);
+    let json = serde_json::to_string(&hasher).unwrap();
+    let mut resumed: crate::Hasher = serde_json::from_str(&json).unwrap();
+    assert_eq!(resumed.finalize(), hasher.finalize());
+    resumed.update(b"more");
+    hasher.update(b"more");
+    assert_eq!(resumed.finalize(), hasher.finalize());
+
+    // Invalid states are deserialization errors.
+    let mut state = hasher.export_state();
+    state[0] = 99;
+    let bad_json = serde_json::to_string(&state[..]).unwrap();
+    let err = serde_json::from_str::<crate::Hasher>(&bad_json).unwrap_err();
+    assert!(err.to_string().contains("unknown hasher state version"));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_unordered_hasher() {
+    const CONTEXT: &str = "BLAKE3 2019-12-27 16:29:52 test vectors context";
+    // Use a fixed RNG seed for reproducibility.
+    let mut rng = rand_chacha::ChaCha8Rng::from_seed([2; 32]);
+    let mut input_buf = [0; TEST_CASES_MAX];
+    paint_test_input(&mut input_buf);
+    for &case in TEST_CASES {
+        let input = &input_buf[..case];
+        // Cut the input into pieces of one to four chunks, and shuffle them.
+        let mut pieces = Vec::new();
+        let mut offset = 0;
+        while offset < case {
+            let len = std::cmp::min(rng.gen_range(1..5) * CHUNK_LEN, case - offset);
+            pieces.push(offset..offset + len);
+            offset += len;
+        }
+        pieces.shuffle(&mut rng);
+
+        let mut hashers = [
+            (crate::UnorderedHasher::new(), crate::hash(input)),
+            (
+                crate::UnorderedHasher::new_keyed(&TEST_KEY),
+                crate::keyed_hash(&TEST_KEY, input),
+            ),
+            (
+                crate::UnorderedHasher::new_derive_key(CONTEXT),
+                crate::derive_key(CONTEXT, input).into(),
+            ),
+        ];
+        for (hasher, expected) in &mut hashers {
+            for piece in &pieces {
+                assert!(!hasher.is_complete());
+                hasher
+                    .update_at(piece.start as u64, &input[piece.clone()])
+                    .unwrap();
+            }
+            // Only a partial chunk at the end sets the length.
+            if hasher.total_len().is_none() {
+                hasher.set_total_len(case as u64).unwrap();
+            }
+            assert_eq!(hasher.total_len(), Some(case as u64));
+            assert_eq!(hasher.finalize().unwrap(), *expected);
+            let mut xof = [0; 300];
+            hasher.finalize_xof().unwrap().fill(&mut xof);
+            assert_eq!(&xof[..OUT_LEN], expected.as_bytes());
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_unordered_hasher_errors() {
+    let input = [0xab; 4 * CHUNK_LEN + 1];
+    let mut hasher = crate::UnorderedHasher::new();
+    // Nothing has arrived yet, so the length is unknown.
+    hasher.finalize().unwrap_err();
+    hasher
+        .update_at(2 * CHUNK_LEN as u64, &input[..CHUNK_LEN])
+        .unwrap();
+    // Misaligned offset.
+    hasher.update_at(1, &input[..CHUNK_LEN]).unwrap_err();
+    // Overlapping pieces, from the left, the right, and around.
+    hasher
+        .update_at(CHUNK_LEN as u64, &input[..2 * CHUNK_LEN])
+        .unwrap_err();
+    hasher
+        .update_at(2 * CHUNK_LEN as u64, &input[..1])
+        .unwrap_err();
+    hasher.update_at(0, &input[..4 * CHUNK_LEN]).unwrap_err();
+    // A partial piece before input that's already arrived.
+    hasher.update_at(0, &input[..CHUNK_LEN + 1]).unwrap_err();
+    // The last piece sets the length, and nothing can go past it.
+    hasher.update_at(4 * CHUNK_LEN as u64, &input[..1]).unwrap();
+    assert_eq!(hasher.total_len(), Some(4 * CHUNK_LEN as u64 + 1));
+    hasher.set_total_len(5 * CHUNK_LEN as u64).unwrap_err();
+    hasher
+        .update_at(5 * CHUNK_LEN as u64, &input[..1])
+        .unwrap_err();
+    hasher.update_at(0, &input[..CHUNK_LEN + 1]).unwrap_err();
+    // Chunks 0, 1, and 3 are missing.
+    hasher.finalize().unwrap_err();
+    hasher.update_at(0, &input[..2 * CHUNK_LEN]).unwrap();
+    hasher.finalize_xof().unwrap_err();
+    hasher
+        .update_at(3 * CHUNK_LEN as u64, &input[..CHUNK_LEN])
+        .unwrap();
+    assert_eq!(hasher.finalize().unwrap(), crate::hash(&input));
+
+    // A length that's shorter than the input received.
+    let mut hasher = crate::UnorderedHasher::new();
+    hasher
+        .update_at(CHUNK_LEN as u64, &input[..CHUNK_LEN])
+        .unwrap();
+    hasher.set_total_len(CHUNK_LEN as u64).unwrap_err();
+    hasher.set_total_len(2 * CHUNK_LEN as u64).unwrap();
+    hasher.set_total_len(3 * CHUNK_LEN as u64).unwrap_err();
+}
@@ -0,0 +1,412 @@
+// The UnorderedHasher, for input that arrives in pieces and out of order. This lives in its own
+// file to keep lib.rs manageable, but the types are re-exported at the crate root.
+
+use crate::{hazmat, join, platform, CVBytes, CVWords, Hash, OutputReader, CHUNK_LEN, KEY_LEN};
+use crate::{DERIVE_KEY_CONTEXT, DERIVE_KEY_MATERIAL, IV, KEYED_HASH};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// A subtree that's been hashed. The subtree at offset 0 keeps its whole output, because it's the
+// root if it turns out to be the whole input. Every other subtree only needs its chaining value.
+#[derive(Clone)]
+enum NodeValue {
+    Output(crate::Output),
+    ChainingValue(CVBytes),
+}
+
+#[derive(Clone)]
+struct Node {
+    len: u64,
+    value: NodeValue,
+}
+
+impl Node {
+    fn new(input_offset: u64, len: u64, output: crate::Output) -> Self {
+        let value = if input_offset == 0 {
+            NodeValue::Output(output)
+        } else {
+            NodeValue::ChainingValue(output.chaining_value())
+        };
+        Self { len, value }
+    }
+
+    // Only called when the node has a sibling, so it can't be the root.
+    fn chaining_value(&self) -> CVBytes {
+        match &self.value {
+            NodeValue::Output(output) => output.chaining_value(),
+            NodeValue::ChainingValue(cv) => *cv,
+        }
+    }
+}
+
+/// A hasher for input that arrives in chunk-aligned pieces, in any order.
+///
+/// Each piece passed to [`update_at`](UnorderedHasher::update_at) is hashed right away, and
+/// neighboring subtrees are merged into their parent as soon as both are present. Memory use is
+/// proportional to the number of gaps in the input received so far, not to the amount of input
+/// buffered. Once the whole input has arrived and its length is known,
+/// [`finalize`](UnorderedHasher::finalize) returns the same [`struct@Hash`] that [`Hasher`]
+/// would have.
+///
+/// Every piece must start at a multiple of the 1024-byte chunk length, and only the piece at the
+/// end of the input can have a length that isn't a multiple of the chunk length. The total length
+/// is known once that piece arrives. If the input is a whole number of chunks, call
+/// [`set_total_len`](UnorderedHasher::set_total_len) instead.
+///
+/// This type requires the `std` Cargo feature, which is enabled by default.
+///
+/// [`Hasher`]: crate::Hasher
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), blake3::UnorderedError> {
+/// let input = vec![0xab; 5000];
+/// let mut hasher = blake3::UnorderedHasher::new();
+/// hasher.update_at(4096, &input[4096..])?;
+/// hasher.update_at(0, &input[..2048])?;
+/// hasher.update_at(2048, &input[2048..4096])?;
+/// assert_eq!(hasher.finalize()?, blake3::hash(&input));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct UnorderedHasher {
+    key: CVWords,
+    flags: u8,
+    // Hashed subtrees, keyed by their input offset. They never overlap.
+    nodes: BTreeMap<u64, Node>,
+    total_len: Option<u64>,
+    // The end of the furthest piece received so far.
+    received_end: u64,
+}
+
+impl UnorderedHasher {
+    fn new_internal(key: &CVWords, flags: u8) -> Self {
+        Self {
+            key: *key,
+            flags,
+            nodes: BTreeMap::new(),
+            total_len: None,
+            received_end: 0,
+        }
+    }
+
+    /// Construct a new `UnorderedHasher` for the regular hash function.
+    pub fn new() -> Self {
+        Self::new_internal(IV, 0)
+    }
+
+    /// Construct a new `UnorderedHasher` for the keyed hash function. See
+    /// [`keyed_hash`](crate::keyed_hash).
+    pub fn new_keyed(key: &[u8; KEY_LEN]) -> Self {
+        let key_words = platform::words_from_le_bytes_32(key);
+        Self::new_internal(&key_words, KEYED_HASH)
+    }
+
+    /// Construct a new `UnorderedHasher` for the key derivation function. See
+    /// [`derive_key`](crate::derive_key). The context string should be hardcoded, globally
+    /// unique, and application-specific.
+    pub fn new_derive_key(context: &str) -> Self {
+        let context_key =
+            crate::hash_all_at_once::<join::SerialJoin>(context.as_bytes(), IV, DERIVE_KEY_CONTEXT)
+                .root_hash();
+        let context_key_words = platform::words_from_le_bytes_32(context_key.as_bytes());
+        Self::new_internal(&context_key_words, DERIVE_KEY_MATERIAL)
+    }
+
+    /// The total length of the input, if it's known yet.
+    pub fn total_len(&self) -> Option<u64> {
+        self.total_len
+    }
+
+    /// Set the total length of the input.
+    ///
+    /// This is only needed when the input is a whole number of chunks, because otherwise the last
+    /// piece sets it. It's an error if the length conflicts with a length that's already known,
+    /// or with a piece that's already been received.
+    pub fn set_total_len(&mut self, total_len: u64) -> Result<&mut Self, UnorderedError> {
+        if let Some(known_len) = self.total_len {
+            if known_len != total_len {
+                return Err(UnorderedError(UnorderedErrorInner::LengthConflict));
+            }
+        }
+        if total_len < self.received_end {
+            return Err(UnorderedError(UnorderedErrorInner::PastEnd));
+        }
+        self.total_len = Some(total_len);
+        Ok(self)
+    }
+
+    /// Add a piece of the input, starting at `input_offset`.
+    ///
+    /// The offset must be a multiple of the chunk length, and the piece must not overlap any
+    /// piece that's already been received. A piece whose length isn't a multiple of the chunk
+    /// length must be the last one, and it sets the total length. A piece that breaks any of
+    /// these rules is rejected, and the hasher is left unchanged.
+    pub fn update_at(
+        &mut self,
+        input_offset: u64,
+        input: &[u8],
+    ) -> Result<&mut Self, UnorderedError> {
+        if input_offset.trailing_zeros() < CHUNK_LEN.trailing_zeros() {
+            return Err(UnorderedError(UnorderedErrorInner::Misaligned));
+        }
+        let end = input_offset
+            .checked_add(input.len() as u64)
+            .ok_or(UnorderedError(UnorderedErrorInner::PastEnd))?;
+        if input.is_empty() {
+            return Ok(self);
+        }
+        let partial = input.len().trailing_zeros() < CHUNK_LEN.trailing_zeros();
+        match self.total_len {
+            Some(total_len) if end > total_len => {
+                return Err(UnorderedError(UnorderedErrorInner::PastEnd));
+            }
+            Some(total_len) if partial && end != total_len => {
+                return Err(UnorderedError(UnorderedErrorInner::PartialChunk));
+            }
+            None if partial && end < self.received_end => {
+                return Err(UnorderedError(UnorderedErrorInner::PartialChunk));
+            }
+            _ => {}
+        }
+        // The nodes don't overlap each other, so only the last one starting before the end of
+        // this piece could overlap it.
+        if let Some((&start, node)) = self.nodes.range(..end).next_back() {
+            if start + node.len > input_offset {
+                return Err(UnorderedError(UnorderedErrorInner::Overlap));
+            }
+        }
+        if partial {
+            self.total_len = Some(end);
+        }
+        self.received_end = self.received_end.max(end);
+
+        // Split the piece into the largest subtrees that fit, and then the partial chunk at the
+        // end, if any.
+        let mut offset = input_offset;
+        let mut input = input;
+        while !input.is_empty() {
+            let whole_chunks_len = (input.len() / CHUNK_LEN * CHUNK_LEN) as u64;
+            let len = if whole_chunks_len == 0 {
+                input.len() as u64
+            } else {
+                let max_len = hazmat::max_subtree_len(offset).unwrap_or(u64::MAX);
+                let len = whole_chunks_len.min(max_len);
+                1 << (63 - len.leading_zeros())
+            };
+            let (subtree, rest) = input.split_at(len as usize);
+            let output =
+                hazmat::subtree_output::<join::SerialJoin>(&self.key, self.flags, offset, subtree);
+            self.insert(offset, Node::new(offset, len, output));
+            offset += len;
+            input = rest;
+        }
+        Ok(self)
+    }
+
+    // Insert a node, merging it with its sibling as many times as possible. Only complete
+    // subtrees get merged, so the partial chunk at the end stays put until finalization.
+    fn insert(&mut self, mut input_offset: u64, mut node: Node) {
+        while node.len >= CHUNK_LEN as u64 && node.len.is_power_of_two() {
+            let is_left = input_offset & node.len == 0;
+            let sibling_offset = if is_left {
+                input_offset + node.len
+            } else {
+                input_offset - node.len
+            };
+            match self.nodes.get(&sibling_offset) {
+                Some(sibling) if sibling.len == node.len => {}
+                _ => break,
+            }
+            let sibling = self.nodes.remove(&sibling_offset).unwrap();
+            let (left, right) = if is_left {
+                (&node, &sibling)
+            } else {
+                (&sibling, &node)
+            };
+            let output = crate::parent_node_output(
+                &left.chaining_value(),
+                &right.chaining_value(),
+                &self.key,
+                self.flags,
+                platform::Platform::detect(),
+            );
+            input_offset = input_offset.min(sibling_offset);
+            node = Node::new(input_offset, 2 * node.len, output);
+        }
+        self.nodes.insert(input_offset, node);
+    }
+
+    /// Whether the whole input has been received, so that [`finalize`](Self::finalize) will
+    /// succeed.
+    pub fn is_complete(&self) -> bool {
+        self.root_output().is_ok()
+    }
+
+    fn node_cv(&self, input_offset: u64, len: u64) -> Result<CVBytes, UnorderedError> {
+        match self.nodes.get(&input_offset) {
+            Some(node) if node.len == len => return Ok(node.chaining_value()),
+            _ if len <= CHUNK_LEN as u64 => {
+                return Err(UnorderedError(UnorderedErrorInner::Incomplete));
+            }
+            _ => {}
+        }
+        let left_len = hazmat::left_subtree_len(len);
+        let left_cv = self.node_cv(input_offset, left_len)?;
+        let right_cv = self.node_cv(input_offset + left_len, len - left_len)?;
+        Ok(crate::parent_node_output(
+            &left_cv,
+            &right_cv,
+            &self.key,
+            self.flags,
+            platform::Platform::detect(),
+        )
+        .chaining_value())
+    }
+
+    fn root_output(&self) -> Result<crate::Output, UnorderedError> {
+        let total_len = self
+            .total_len
+            .ok_or(UnorderedError(UnorderedErrorInner::Incomplete))?;
+        if total_len == 0 {
+            return Ok(hazmat::subtree_output::<join::SerialJoin>(
+                &self.key,
+                self.flags,
+                0,
+                &[],
+            ));
+        }
+        match self.nodes.get(&0) {
+            Some(Node {
+                len,
+                value: NodeValue::Output(output),
+            }) if *len == total_len => return Ok(output.clone()),
+            _ if total_len <= CHUNK_LEN as u64 => {
+                return Err(UnorderedError(UnorderedErrorInner::Incomplete));
+            }
+            _ => {}
+        }
+        let left_len = hazmat::left_subtree_len(total_len);
+        let left_cv = self.node_cv(0, left_len)?;
+        let right_cv = self.node_cv(left_len, total_len - left_len)?;
+        Ok(crate::parent_node_output(
+            &left_cv,
+            &right_cv,
+            &self.key,
+            self.flags,
+            platform::Platform::detect(),
+        ))
+    }
+
+    /// Finalize the hash state and return the [`struct@Hash`] of the input.
+    ///
+    /// This returns an error if the total length isn't known yet, or if any of the input is
+    /// missing. Like [`Hasher::finalize`](crate::Hasher::finalize), this doesn't modify the
+    /// hasher.
+    pub fn finalize(&self) -> Result<Hash, UnorderedError> {
+        Ok(self.root_output()?.root_hash())
+    }
+
+    /// Finalize the hash state and return an [`OutputReader`], which can supply any number of
+    /// output bytes. The same errors apply as in [`finalize`](Self::finalize).
+    pub fn finalize_xof(&self) -> Result<OutputReader, UnorderedError> {
+        Ok(OutputReader::new(self.root_output()?))
+    }
+}
+
+impl Default for UnorderedHasher {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Don't derive(Debug), because the state may be secret.
+impl fmt::Debug for UnorderedHasher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UnorderedHasher")
+            .field("flags", &self.flags)
+            .field("subtrees", &self.nodes.len())
+            .field("total_len", &self.total_len)
+            .finish()
+    }
+}
+
+/// The error type for [`UnorderedHasher`].
+///
+/// The `.to_string()` representation of this error currently describes what went wrong. This is
+/// to help with logging and debugging, but it isn't a stable API detail, and it may change at any
+/// time.
+#[derive(Clone, Debug)]
+pub struct UnorderedError(UnorderedErrorInner);
+
+#[derive(Clone, Debug)]
+enum UnorderedErrorInner {
+    Misaligned,
+    Overlap,
+    PastEnd,
+    PartialChunk,
+    LengthConflict,
+    Incomplete,
+}
+
+impl fmt::Display for UnorderedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            UnorderedErrorInner::Misaligned => {
+                write!(f, "piece offset is not a multiple of the chunk length")
+            }
+            UnorderedErrorInner::Overlap => write!(f, "piece overlaps input already received"),
+            UnorderedErrorInner::PastEnd => write!(f, "piece extends past the end of the input"),
+            UnorderedErrorInner::PartialChunk => {
+                write!(f, "only the last piece can end partway through a chunk")
+            }
+            UnorderedErrorInner::LengthConflict => {
+                write!(f, "total length conflicts with the known length")
+            }
+            UnorderedErrorInner::Incomplete => write!(f, "input is incomplete"),
+        }
+    }
+}
+
+impl std::error::Error for UnorderedError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_subtrees_merge() {
+        let input = [0xab; 31 * CHUNK_LEN];
+        let mut hasher = UnorderedHasher::new();
+        // Every other chunk leaves a gap after each piece, so nothing merges.
+        for i in (0..31).step_by(2) {
+            hasher
+                .update_at((i * CHUNK_LEN) as u64, &input[..CHUNK_LEN])
+                .unwrap();
+        }
+        assert_eq!(hasher.nodes.len(), 16);
+        // Filling the gaps in reverse order collapses everything into 16 + 8 + 4 + 2 + 1 chunks.
+        for i in (1..31).step_by(2).rev() {
+            hasher
+                .update_at((i * CHUNK_LEN) as u64, &input[..CHUNK_LEN])
+                .unwrap();
+        }
+        let lens: Vec<u64> = hasher.nodes.values().map(|node| node.len).collect();
+        let chunk_len = CHUNK_LEN as u64;
+        assert_eq!(
+            lens,
+            [
+                16 * chunk_len,
+                8 * chunk_len,
+                4 * chunk_len,
+                2 * chunk_len,
+                chunk_len
+            ],
+        );
+        hasher.set_total_len(input.len() as u64).unwrap();
+        assert_eq!(hasher.finalize().unwrap(), crate::hash(&input));
+    }
+}